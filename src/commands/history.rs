@@ -0,0 +1,145 @@
+use crate::commands::CommandError;
+use crate::io::history::{read_runs, JobRecord, RunRecord};
+use clap::Args;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Args)]
+pub struct History {
+    /// How many of the latest runs to show.
+    #[clap(short = 'n', long, default_value_t = 10)]
+    pub limit: usize,
+}
+
+pub fn command(current_directory: &str, args: &History) -> Result<(), CommandError> {
+    let runs = read_runs(Path::new(current_directory))?
+        .into_iter()
+        .take(args.limit)
+        .map(|(_, run)| run)
+        .collect::<Vec<_>>();
+
+    if runs.is_empty() {
+        println!("No runs yet");
+    } else {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        println!("{}", format_runs(&runs, now));
+    }
+
+    Ok(())
+}
+
+fn format_runs(runs: &[RunRecord], now: u64) -> String {
+    runs.iter()
+        .map(|run| {
+            let mut lines = vec![format!(
+                "Run {} ({}, {})",
+                run.id,
+                ago(now.saturating_sub(run.started)),
+                run.git_sha.chars().take(8).collect::<String>()
+            )];
+            let width = run.jobs.iter().map(|job| job.name.len()).max().unwrap_or(0);
+
+            lines.extend(
+                run.jobs
+                    .iter()
+                    .map(|job| format!("  {:width$}  {}", job.name, outcome(job))),
+            );
+
+            lines.join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn outcome(job: &JobRecord) -> String {
+    let status = match (job.succeeded, job.exit_code) {
+        (true, _) => "passed".to_string(),
+        (false, Some(code)) => format!("failed with exit code {}", code),
+        (false, None) => "failed".to_string(),
+    };
+    let artifacts = match job.artifacts.len() {
+        0 => String::new(),
+        1 => ", 1 artifact".to_string(),
+        count => format!(", {} artifacts", count),
+    };
+
+    format!("{} after {}s{}", status, job.duration, artifacts)
+}
+
+fn ago(seconds: u64) -> String {
+    let (count, unit) = match seconds {
+        0..=59 => return "just now".into(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    match count {
+        1 => format!("1 {} ago", unit),
+        count => format!("{} {}s ago", count, unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_jobs_of_runs_with_their_outcome() {
+        let runs = vec![
+            RunRecord {
+                id: "1700007200".into(),
+                started: 1700007200,
+                git_sha: "0123456789abcdef".into(),
+                jobs: vec![
+                    JobRecord {
+                        name: "build".into(),
+                        succeeded: true,
+                        exit_code: Some(0),
+                        duration: 12,
+                        artifacts: vec!["app".into(), "app.sha256".into()],
+                        ..Default::default()
+                    },
+                    JobRecord {
+                        name: "test".into(),
+                        exit_code: Some(1),
+                        duration: 3,
+                        ..Default::default()
+                    },
+                ],
+            },
+            RunRecord {
+                id: "1699920000".into(),
+                started: 1699920000,
+                git_sha: "fedcba".into(),
+                jobs: vec![JobRecord {
+                    name: "lint".into(),
+                    ..Default::default()
+                }],
+            },
+        ];
+
+        assert_eq!(
+            format_runs(&runs, 1700010000),
+            "\
+Run 1700007200 (46 minutes ago, 01234567)
+  build  passed after 12s, 2 artifacts
+  test   failed with exit code 1 after 3s
+
+Run 1699920000 (1 day ago, fedcba)
+  lint  failed after 0s"
+        );
+    }
+
+    #[test]
+    fn tells_how_long_ago_runs_started() {
+        assert_eq!(ago(5), "just now");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(7200), "2 hours ago");
+        assert_eq!(ago(3 * 86400), "3 days ago");
+    }
+}
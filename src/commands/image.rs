@@ -1,6 +1,6 @@
 use crate::commands::CommandError;
 use crate::io::processes::ProcessesToExecute;
-use crate::io::prompt::Prompts;
+use crate::io::prompt::{InfoLines, Prompts};
 use crate::Context;
 use clap::Args;
 
@@ -19,7 +19,7 @@ pub fn command<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
 ) -> Result<(), CommandError> {
     if args.force || processes.image_needs_to_be_built(&context.image_tag)? {
         prompt.info("Building Fake CI image");
-        processes.build_image(&context.image_tag)?;
+        processes.build_image(&context.image_tag, &mut InfoLines::new(prompt))?;
     } else {
         prompt.info("Image is up-to-date");
     }
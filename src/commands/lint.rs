@@ -0,0 +1,61 @@
+use crate::commands::CommandError;
+use crate::diagnostic::{Diagnostic, ErrorFormat};
+use crate::file::FileAccess;
+use crate::git::GitDetails;
+use crate::gitlab::lint::lint;
+use crate::gitlab::schema::validate;
+use crate::gitlab::{read_gitlab_configuration, read_unmerged_configuration, GitLabAccess};
+use clap::Args;
+
+#[derive(Args)]
+pub struct Lint {
+    /// Validate the merged configuration against GitLab's CI JSON schema instead.
+    #[clap(long)]
+    pub schema: bool,
+}
+
+// Whether the configuration is valid. Findings are reported here already, so they are no error
+// to report again.
+pub async fn command(
+    path_to_config_file: String,
+    file_access: &impl FileAccess,
+    git: &GitDetails,
+    gitlab: &GitLabAccess,
+    args: &Lint,
+    error_format: ErrorFormat,
+) -> Result<bool, CommandError> {
+    let problems = if args.schema {
+        let configuration =
+            read_gitlab_configuration(path_to_config_file, file_access, git, gitlab).await?;
+
+        validate(&configuration)?
+    } else {
+        let configuration =
+            read_unmerged_configuration(path_to_config_file, file_access, git, gitlab).await?;
+
+        lint(&configuration)
+    };
+
+    if problems.is_empty() {
+        // Nothing but findings ends up in machine readable output.
+        if error_format == ErrorFormat::Human {
+            println!("Configuration is valid");
+        }
+
+        return Ok(true);
+    }
+
+    let separator = match error_format {
+        ErrorFormat::Human => "\n\n",
+        ErrorFormat::Json => "\n",
+    };
+    let report = problems
+        .iter()
+        .map(|problem| Diagnostic::from(problem).format(error_format))
+        .collect::<Vec<_>>()
+        .join(separator);
+
+    println!("{}", report);
+
+    Ok(false)
+}
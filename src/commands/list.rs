@@ -0,0 +1,327 @@
+use crate::commands::CommandError;
+use crate::file::FileAccess;
+use crate::git::GitDetails;
+use crate::gitlab::configuration::{GitLabConfiguration, OneOrMoreNeeds};
+use crate::gitlab::error::GitLabError;
+use crate::gitlab::lint::{available_stages, DEFAULT_STAGE};
+use crate::gitlab::rules::is_included;
+use crate::gitlab::{read_gitlab_configuration, GitLabAccess};
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct List {
+    /// Render the `needs` graph instead of listing jobs by stage.
+    #[clap(long, value_enum)]
+    pub format: Option<ListFormat>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// Graphviz, e.g. `fake-ci list --format dot | dot -Tsvg > pipeline.svg`.
+    Dot,
+    /// Mermaid flowchart, e.g. to paste into Markdown.
+    Mermaid,
+    /// Stages and jobs with their `needs` as JSON.
+    Json,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Pipeline {
+    pub stages: Vec<String>,
+    pub jobs: Vec<JobSummary>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct JobSummary {
+    pub name: String,
+    pub stage: String,
+    pub image: Option<String>,
+    pub needs: Vec<String>,
+    // Whether the job's `rules` (or `only`/`except`) add it to a pipeline of the current branch.
+    pub included: bool,
+}
+
+pub async fn command(
+    path_to_config_file: String,
+    file_access: &impl FileAccess,
+    git: &GitDetails,
+    gitlab: &GitLabAccess,
+    args: &List,
+) -> Result<(), CommandError> {
+    let configuration =
+        read_gitlab_configuration(path_to_config_file, file_access, git, gitlab).await?;
+    let pipeline = summarize(&configuration)?;
+    let output = match args.format {
+        None => as_text(&pipeline),
+        Some(ListFormat::Dot) => as_dot(&pipeline),
+        Some(ListFormat::Mermaid) => as_mermaid(&pipeline),
+        Some(ListFormat::Json) => serde_json::to_string_pretty(&pipeline).unwrap(),
+    };
+
+    println!("{}", output);
+
+    Ok(())
+}
+
+// Expects the merged configuration, i.e. templates and global variables applied to jobs.
+pub fn summarize(configuration: &GitLabConfiguration) -> Result<Pipeline, GitLabError> {
+    let stages = available_stages(configuration);
+    let mut jobs = vec![];
+
+    for (name, job) in &configuration.jobs {
+        let included = is_included(job).map_err(|e| GitLabError::job(name, job, e))?;
+        let needs = match &job.needs {
+            Some(OneOrMoreNeeds(needs)) => needs.iter().map(|need| need.job.clone()).collect(),
+            None => vec![],
+        };
+
+        jobs.push(JobSummary {
+            name: name.clone(),
+            stage: job.stage.clone().unwrap_or_else(|| DEFAULT_STAGE.into()),
+            image: job.image.clone(),
+            needs,
+            included,
+        });
+    }
+
+    // Jobs of undefined stages are listed as well, after all others.
+    jobs.sort_by_key(|job| {
+        stages
+            .iter()
+            .position(|stage| *stage == job.stage)
+            .unwrap_or(stages.len())
+    });
+
+    let mut used_stages: Vec<String> = vec![];
+    for job in &jobs {
+        if !used_stages.contains(&job.stage) {
+            used_stages.push(job.stage.clone());
+        }
+    }
+
+    Ok(Pipeline {
+        stages: used_stages,
+        jobs,
+    })
+}
+
+fn jobs_of<'a>(pipeline: &'a Pipeline, stage: &'a str) -> impl Iterator<Item = &'a JobSummary> {
+    pipeline.jobs.iter().filter(move |job| job.stage == stage)
+}
+
+// build
+//   compile     rust:1.65
+// test
+//   unit-tests  rust:1.65  needs: compile
+//   release     alpine     (excluded by rules)
+fn as_text(pipeline: &Pipeline) -> String {
+    let name_width = pipeline
+        .jobs
+        .iter()
+        .map(|job| job.name.len())
+        .max()
+        .unwrap_or(0);
+    let image_width = pipeline
+        .jobs
+        .iter()
+        .map(|job| job.image.as_deref().unwrap_or("-").len())
+        .max()
+        .unwrap_or(0);
+    let mut lines = vec![];
+
+    for stage in &pipeline.stages {
+        lines.push(stage.clone());
+
+        for job in jobs_of(pipeline, stage) {
+            let mut line = format!(
+                "  {:name_width$}  {:image_width$}",
+                job.name,
+                job.image.as_deref().unwrap_or("-"),
+            );
+
+            if !job.needs.is_empty() {
+                line.push_str(&format!("  needs: {}", job.needs.join(", ")));
+            }
+            if !job.included {
+                line.push_str("  (excluded by rules)");
+            }
+
+            lines.push(line.trim_end().to_string());
+        }
+    }
+
+    lines.join("\n")
+}
+
+fn as_dot(pipeline: &Pipeline) -> String {
+    let mut lines = vec!["digraph pipeline {".to_string(), "  rankdir=LR;".into()];
+
+    for (index, stage) in pipeline.stages.iter().enumerate() {
+        lines.push(format!("  subgraph cluster_{} {{", index));
+        lines.push(format!("    label={:?};", stage));
+
+        for job in jobs_of(pipeline, stage) {
+            let style = if job.included { "" } else { " [style=dashed]" };
+            lines.push(format!("    {:?}{};", job.name, style));
+        }
+
+        lines.push("  }".into());
+    }
+
+    for job in &pipeline.jobs {
+        for need in &job.needs {
+            lines.push(format!("  {:?} -> {:?};", need, job.name));
+        }
+    }
+
+    lines.push("}".into());
+    lines.join("\n")
+}
+
+fn as_mermaid(pipeline: &Pipeline) -> String {
+    // Job names can contain characters Mermaid doesn't allow in IDs, so they are numbered.
+    let id = |name: &str| {
+        pipeline
+            .jobs
+            .iter()
+            .position(|job| job.name == name)
+            .map(|index| format!("job{}", index))
+            .unwrap_or_else(|| name.to_string())
+    };
+    let mut lines = vec!["flowchart LR".to_string()];
+
+    for (index, stage) in pipeline.stages.iter().enumerate() {
+        lines.push(format!("  subgraph stage{} [\"{}\"]", index, stage));
+
+        for job in jobs_of(pipeline, stage) {
+            lines.push(format!("    {}[\"{}\"]", id(&job.name), job.name));
+        }
+
+        lines.push("  end".into());
+    }
+
+    for job in &pipeline.jobs {
+        for need in &job.needs {
+            let arrow = if job.included { "-->" } else { "-.->" };
+            lines.push(format!("  {} {} {}", id(need), arrow, id(&job.name)));
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::merge_jobs;
+    use crate::gitlab::read_configuration;
+
+    fn pipeline(content: &str) -> Pipeline {
+        let git = GitDetails {
+            branch_name: "main".into(),
+            ..Default::default()
+        };
+        let mut configuration =
+            read_configuration(content.as_bytes(), ".gitlab-ci.yml", &git).unwrap();
+        merge_jobs(&mut configuration).unwrap();
+
+        summarize(&configuration).unwrap()
+    }
+
+    const CONFIGURATION: &str = "
+stages: [build, test, deploy]
+.rust:
+  image: rust
+build:
+  extends: .rust
+  stage: build
+  script: cargo build
+unit-tests:
+  extends: .rust
+  needs: [build]
+  script: cargo test
+release:
+  stage: deploy
+  image: alpine
+  needs: [build, unit-tests]
+  script: release
+  rules:
+    - if: $CI_COMMIT_TAG
+";
+
+    #[test]
+    fn summarizes_jobs_in_order_of_stages() {
+        let pipeline = pipeline(
+            "
+            stages: [build, test]
+            test:
+              script: echo
+            build:
+              stage: build
+              script: echo
+            ",
+        );
+
+        assert_eq!(pipeline.stages, vec!["build", "test"]);
+        assert_eq!(
+            pipeline
+                .jobs
+                .iter()
+                .map(|job| job.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["build", "test"]
+        );
+    }
+
+    #[test]
+    fn takes_stage_and_image_from_templates() {
+        let pipeline = pipeline(
+            "
+            .template:
+              stage: build
+              image: alpine
+            job:
+              extends: .template
+              script: echo
+            ",
+        );
+
+        assert_eq!(pipeline.jobs[0].stage, "build");
+        assert_eq!(pipeline.jobs[0].image, Some("alpine".into()));
+    }
+
+    #[test]
+    fn lists_jobs_by_stage() {
+        assert_eq!(
+            as_text(&pipeline(CONFIGURATION)),
+            "\
+build
+  build       rust
+test
+  unit-tests  rust    needs: build
+deploy
+  release     alpine  needs: build, unit-tests  (excluded by rules)"
+        );
+    }
+
+    #[test]
+    fn renders_needs_as_dot() {
+        let dot = as_dot(&pipeline(CONFIGURATION));
+
+        assert!(dot.starts_with("digraph pipeline {"));
+        assert!(dot.contains("    label=\"deploy\";\n    \"release\" [style=dashed];"));
+        assert!(dot.contains("  \"build\" -> \"unit-tests\";"));
+        assert!(dot.contains("  \"unit-tests\" -> \"release\";"));
+    }
+
+    #[test]
+    fn renders_needs_as_mermaid() {
+        let mermaid = as_mermaid(&pipeline(CONFIGURATION));
+
+        assert!(mermaid.starts_with("flowchart LR\n  subgraph stage0 [\"build\"]"));
+        assert!(mermaid.contains("    job1[\"unit-tests\"]"));
+        assert!(mermaid.contains("  job0 --> job1"));
+        assert!(mermaid.contains("  job1 -.-> job2"));
+    }
+}
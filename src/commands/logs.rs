@@ -0,0 +1,75 @@
+use crate::commands::CommandError;
+use crate::io::history::{log_file, read_runs};
+use clap::Args;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct Logs {
+    /// The job whose log to show.
+    pub job: String,
+    /// Show the log of this run instead of the latest one. See `fake-ci history` for all runs.
+    #[clap(long, value_name = "ID")]
+    pub run: Option<String>,
+}
+
+pub fn command(current_directory: &str, args: &Logs) -> Result<(), CommandError> {
+    let path = find_log(Path::new(current_directory), &args.job, args.run.as_deref())?;
+
+    print!("{}", std::fs::read_to_string(path)?);
+
+    Ok(())
+}
+
+// The log of the job in the given run, or in the latest run the job has been part of.
+fn find_log(
+    current_directory: &Path,
+    job_name: &str,
+    run_id: Option<&str>,
+) -> Result<PathBuf, CommandError> {
+    read_runs(current_directory)?
+        .into_iter()
+        .filter(|(_, run)| run_id.is_none_or(|id| run.id == id))
+        .filter(|(_, run)| run.jobs.iter().any(|job| job.name == job_name))
+        .map(|(directory, _)| log_file(&directory, job_name))
+        .find(|path| path.exists())
+        .ok_or_else(|| CommandError::NoLogs {
+            job: job_name.into(),
+            run: run_id.map(str::to_string),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::history::{record_job, JobRecord, RUNS_DIRECTORY};
+    use crate::test_support::TemporaryDirectory;
+
+    #[test]
+    fn finds_the_log_of_the_latest_run_of_a_job_or_of_a_given_run() {
+        let directory = TemporaryDirectory::new("logs");
+        let project = &directory.path;
+        for (run, job) in [("100", "build"), ("100", "test"), ("200", "build")] {
+            let directory = project.join(RUNS_DIRECTORY).join(run);
+            let record = JobRecord {
+                name: job.into(),
+                ..Default::default()
+            };
+
+            record_job(&directory, "sha", record).unwrap();
+            std::fs::write(log_file(&directory, job), "output").unwrap();
+        }
+
+        let latest_build = find_log(project, "build", None).unwrap();
+        let earlier_build = find_log(project, "build", Some("100")).unwrap();
+        let latest_test = find_log(project, "test", None).unwrap();
+        let missing = find_log(project, "test", Some("200")).unwrap_err();
+
+        assert!(latest_build.ends_with("runs/200/build.log"));
+        assert!(earlier_build.ends_with("runs/100/build.log"));
+        assert!(latest_test.ends_with("runs/100/test.log"));
+        assert_eq!(
+            missing.to_string(),
+            "no logs of job 'test' found in run 200"
+        );
+    }
+}
@@ -1,17 +1,55 @@
+pub mod history;
 pub mod image;
+pub mod lint;
+pub mod list;
+pub mod logs;
 pub mod print;
 pub mod prune;
 pub mod run;
+pub mod shell;
 
 use crate::gitlab::error::GitLabError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum CommandError {
-    #[error("unknown job '{0}'")]
-    UnknownJob(String),
+    #[error("unknown job '{name}'")]
+    UnknownJob {
+        name: String,
+        // Names of existing jobs that are close to the unknown one.
+        suggestions: Vec<String>,
+    },
+    #[error(
+        "no logs of job '{job}' found{}",
+        run.as_ref().map(|id| format!(" in run {}", id)).unwrap_or_default()
+    )]
+    NoLogs { job: String, run: Option<String> },
+    #[error("{0} job(s) did not pass")]
+    UnsuccessfulJobs(usize),
     #[error(transparent)]
     IO(#[from] std::io::Error),
     #[error(transparent)]
     GitLab(#[from] GitLabError),
 }
+
+impl CommandError {
+    // Suggests job names that are only a few typos away, closest first.
+    pub fn unknown_job<'a>(name: &str, job_names: impl Iterator<Item = &'a String>) -> Self {
+        let maximum_distance = (name.chars().count() / 3).max(1);
+        let mut candidates = job_names
+            .map(|candidate| (strsim::damerau_levenshtein(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= maximum_distance)
+            .collect::<Vec<_>>();
+
+        candidates.sort_by_key(|(distance, _)| *distance);
+
+        CommandError::UnknownJob {
+            name: name.into(),
+            suggestions: candidates
+                .into_iter()
+                .take(3)
+                .map(|(_, candidate)| candidate.clone())
+                .collect(),
+        }
+    }
+}
@@ -1,23 +1,226 @@
 use crate::commands::CommandError;
+use crate::core::convert_job;
 use crate::file::FileAccess;
 use crate::git::GitDetails;
-use crate::gitlab::read_gitlab_configuration;
-use clap::Args;
+use crate::gitlab::configuration::{GitLabConfiguration, ListOfStrings};
+use crate::gitlab::error::GitLabError;
+use crate::gitlab::origin::{trace_origins, Origin, Origins};
+use crate::gitlab::{merge_jobs, read_unmerged_configuration, GitLabAccess};
+use clap::{Args, ValueEnum};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(Args)]
-pub struct Print;
+pub struct Print {
+    /// Only print this job.
+    pub job: Option<String>,
+    /// Print the job as it will be run, with where each value comes from.
+    #[clap(long, requires = "job")]
+    pub resolved: bool,
+    #[clap(long, value_enum, default_value_t = PrintFormat::Yaml)]
+    pub format: PrintFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PrintFormat {
+    Yaml,
+    Json,
+}
+
+// A value of a resolved job together with where it has been defined.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Annotated<T> {
+    pub value: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ResolvedJob {
+    pub image: Annotated<String>,
+    pub before_script: Annotated<Vec<String>>,
+    pub script: Annotated<Vec<String>>,
+    pub after_script: Annotated<Vec<String>>,
+    pub variables: IndexMap<String, Annotated<String>>,
+    pub artifacts: Vec<String>,
+    pub required_artifacts: BTreeMap<String, Vec<String>>,
+}
 
 pub async fn command(
     path_to_config_file: String,
     file_access: &impl FileAccess,
     git: &GitDetails,
-    gitlab_host: &String,
+    gitlab: &GitLabAccess,
+    args: &Print,
 ) -> Result<(), CommandError> {
-    let configuration =
-        read_gitlab_configuration(path_to_config_file, file_access, git, gitlab_host).await?;
-    let content = serde_yaml::to_string(&configuration).unwrap();
+    let mut configuration =
+        read_unmerged_configuration(path_to_config_file, file_access, git, gitlab).await?;
+    // Origins can only be traced as long as jobs haven't been merged with their templates.
+    let origins = match (&args.job, args.resolved) {
+        (Some(name), true) => {
+            let job = configuration
+                .jobs
+                .get(name)
+                .ok_or_else(|| CommandError::unknown_job(name, configuration.jobs.keys()))?;
+
+            Some(
+                trace_origins(job, &configuration, git)
+                    .map_err(|e| GitLabError::job(name, job, e))?,
+            )
+        }
+        _ => None,
+    };
+
+    merge_jobs(&mut configuration)?;
+
+    let content = match (&args.job, origins) {
+        (None, _) => format_value(&configuration, args.format),
+        (Some(name), origins) => {
+            let job = configuration
+                .jobs
+                .get(name)
+                .ok_or_else(|| CommandError::unknown_job(name, configuration.jobs.keys()))?;
+
+            match origins {
+                Some(origins) => {
+                    let resolved = resolve(name, &configuration, &origins)?;
+
+                    format_value(&resolved, args.format)
+                }
+                None => format_value(&IndexMap::from([(name, job)]), args.format),
+            }
+        }
+    };
 
     println!("{}", content);
 
     Ok(())
 }
+
+fn format_value(value: &impl Serialize, format: PrintFormat) -> String {
+    match format {
+        PrintFormat::Yaml => serde_yaml::to_string(value).unwrap(),
+        PrintFormat::Json => serde_json::to_string_pretty(value).unwrap(),
+    }
+}
+
+// Expects the merged configuration.
+fn resolve(
+    name: &str,
+    configuration: &GitLabConfiguration,
+    origins: &Origins,
+) -> Result<ResolvedJob, GitLabError> {
+    let job = &configuration.jobs[name];
+    let converted =
+        convert_job(job, &configuration.jobs).map_err(|e| GitLabError::job(name, job, e))?;
+    let lines = |script: &Option<ListOfStrings>| {
+        script
+            .as_ref()
+            .map(|list| list.0.clone())
+            .unwrap_or_default()
+    };
+    let mut variables = IndexMap::new();
+
+    // Later definitions override earlier ones, but the first definition determines the order.
+    for (key, value) in &converted.variables {
+        variables.insert(
+            key.clone(),
+            Annotated {
+                value: value.clone(),
+                origin: origins.variables.get(key).cloned(),
+            },
+        );
+    }
+
+    Ok(ResolvedJob {
+        image: Annotated {
+            value: converted.image,
+            origin: origins.image.clone(),
+        },
+        before_script: Annotated {
+            value: lines(&job.before_script),
+            origin: origins.before_script.clone(),
+        },
+        script: Annotated {
+            value: lines(&job.script),
+            origin: origins.script.clone(),
+        },
+        after_script: Annotated {
+            value: lines(&job.after_script),
+            origin: origins.after_script.clone(),
+        },
+        variables,
+        artifacts: converted.artifacts,
+        required_artifacts: converted.required_artifacts.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::read_configuration;
+
+    fn resolve_job(content: &str, name: &str) -> ResolvedJob {
+        let git = GitDetails::default();
+        let mut configuration = read_configuration(content.as_bytes(), "ci.yml", &git).unwrap();
+        let origins = trace_origins(&configuration.jobs[name], &configuration, &git).unwrap();
+        merge_jobs(&mut configuration).unwrap();
+
+        resolve(name, &configuration, &origins).unwrap()
+    }
+
+    #[test]
+    fn annotates_resolved_values_with_their_origin() {
+        let job = resolve_job(
+            "
+            variables:
+              NAME: global
+              OTHER: global
+            default:
+              image: alpine
+            .template:
+              script: make
+            job:
+              extends: .template
+              variables:
+                NAME: job
+            ",
+            "job",
+        );
+
+        assert_eq!(job.image.value, "alpine");
+        assert_eq!(job.image.origin, Some(Origin::Default));
+        assert_eq!(job.script.value, vec!["make".to_string()]);
+        assert_eq!(
+            job.script.origin,
+            Some(Origin::Template(".template".into()))
+        );
+        assert_eq!(job.before_script.origin, None);
+        assert_eq!(job.variables["NAME"].value, "job");
+        assert_eq!(job.variables["NAME"].origin, Some(Origin::Job));
+        assert_eq!(job.variables["OTHER"].origin, Some(Origin::Global));
+        assert_eq!(
+            job.variables["CI_PROJECT_DIR"].origin,
+            Some(Origin::Predefined)
+        );
+    }
+
+    #[test]
+    fn serialises_origins_next_to_values() {
+        let job = resolve_job(
+            "
+            job:
+              image: alpine
+              script: make
+            ",
+            "job",
+        );
+
+        let json = serde_json::to_value(&job).unwrap();
+
+        assert_eq!(json["image"]["value"], "alpine");
+        assert_eq!(json["image"]["origin"], "job");
+        assert!(json["after_script"].get("origin").is_none());
+    }
+}
@@ -1,70 +1,689 @@
 use crate::commands::CommandError;
-use crate::core::CiDefinition;
-use crate::io::processes::ProcessesToExecute;
-use crate::io::prompt::Prompts;
+use crate::core::{CiDefinition, Job};
+use crate::git::SourceMode;
+use crate::gitlab::expansion::interpolate;
+use crate::io::checkout::Checkout;
+use crate::io::history::{log_file, record_job, JobRecord};
+use crate::io::log::{JobLog, LogOptions};
+use crate::io::processes::{JobStopper, ProcessesToExecute};
+use crate::io::prompt::{InfoLines, Prompts};
+use crate::io::tui::{lock, Action, JobStatus, PanePrompt, PaneWriter, Pipeline, Screen};
+use crate::io::variables::mask;
 use crate::Context;
-use clap::Args;
+use clap::{Args, ValueEnum};
+use regex::Regex;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-#[derive(Args)]
+#[derive(Args, Default)]
 pub struct Run {
-    /// The job name.
-    pub job: String,
+    /// The job name. Pick from all jobs if not given.
+    pub job: Option<String>,
+    /// Pick several jobs to run one after another.
+    #[clap(short, long, conflicts_with = "job")]
+    pub multiple: bool,
+    /// Set a variable, overriding the CI definition. `KEY` alone takes the value from the environment.
+    #[clap(short = 'e', long = "env", value_name = "KEY=VALUE", value_parser = parse_variable)]
+    pub variables: Vec<(String, String)>,
+    /// Read variables from a dotenv file. Variables given with `--env` take precedence.
+    #[clap(long, value_name = "FILE")]
+    pub env_file: Vec<String>,
+    /// Where jobs run.
+    #[clap(long, value_enum, default_value_t)]
+    pub executor: Executor,
+    /// Prefix output lines with the name of their job.
+    #[clap(long)]
+    pub prefix: bool,
+    /// Prefix output lines with the time passed since their job started.
+    #[clap(long)]
+    pub timestamps: bool,
+    /// Show all jobs by stage in a terminal UI, with their logs and keys to cancel, retry or open a
+    /// shell in them.
+    #[clap(long)]
+    pub tui: bool,
+    /// Open a shell in the job's container when its script fails, to look around where it failed.
+    #[clap(long, conflicts_with = "tui")]
+    pub debug_on_failure: bool,
+    /// Pause before every line of the script, to run, skip or edit it, or to open a shell first.
+    #[clap(long, conflicts_with = "tui")]
+    pub step: bool,
+    /// Pause before the given line of the script, counting from 1 with `before_script` included.
+    #[clap(long, value_name = "LINE", conflicts_with = "tui")]
+    pub break_at: Vec<usize>,
+    /// Pause before lines of the script matching the regular expression.
+    #[clap(long, value_name = "REGEX", conflicts_with = "tui")]
+    pub break_on: Vec<Regex>,
+    /// What of the project's changes jobs get. Defaults to `committed` with `--ref`, else `working`.
+    #[clap(long, value_enum)]
+    pub source_mode: Option<SourceMode>,
+    /// Run for another commit, branch or tag than `HEAD`.
+    #[clap(long = "ref", value_name = "REVISION")]
+    pub revision: Option<String>,
 }
 
-pub fn command<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+impl Run {
+    pub fn source_mode(&self) -> SourceMode {
+        source_mode_for(self.source_mode, self.revision.as_deref())
+    }
+}
+
+// Uncommitted changes are made on top of `HEAD`, they would hardly apply to another revision.
+pub fn source_mode_for(source_mode: Option<SourceMode>, revision: Option<&str>) -> SourceMode {
+    source_mode.unwrap_or(match revision {
+        Some(_) => SourceMode::Committed,
+        None => SourceMode::Working,
+    })
+}
+
+#[derive(Default)]
+struct RunOptions {
+    log: LogOptions,
+    debug_on_failure: bool,
+    breakpoints: Breakpoints,
+}
+
+// Where to pause while a job's script runs. Lines count from 1.
+#[derive(Default)]
+struct Breakpoints {
+    step: bool,
+    lines: Vec<usize>,
+    patterns: Vec<Regex>,
+}
+
+impl Breakpoints {
+    fn is_empty(&self) -> bool {
+        !self.step && self.lines.is_empty() && self.patterns.is_empty()
+    }
+
+    // Without stepping through every line.
+    fn is_at(&self, number: usize, line: &str) -> bool {
+        self.lines.contains(&number) || self.patterns.iter().any(|pattern| pattern.is_match(line))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    Run,
+    Skip,
+    Edit,
+    Shell,
+    Continue,
+    Abort,
+}
+
+const STEPS: [(Step, &str); 6] = [
+    (Step::Run, "Run it"),
+    (Step::Skip, "Skip it"),
+    (Step::Edit, "Edit it"),
+    (Step::Shell, "Open a shell first"),
+    (Step::Continue, "Run it and continue to the next breakpoint"),
+    (Step::Abort, "Abort the job"),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Executor {
+    /// In containers of the job's image.
+    #[default]
+    Docker,
+    /// Straight on the host, in a temporary git worktree. Ignores the job's image.
+    Shell,
+}
+
+pub fn parse_variable(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some(("", _)) => Err("missing variable name".into()),
+        Some((key, value)) => Ok((key.into(), value.into())),
+        None => std::env::var(value)
+            .map(|v| (value.to_string(), v))
+            .map_err(|_| format!("`{}` is not set in the environment", value)),
+    }
+}
+
+pub fn command<PROMPTS: Prompts, PROCESSES: ProcessesToExecute + Send>(
     prompt: &mut PROMPTS,
     processes: &mut PROCESSES,
     context: &Context,
     definition: &CiDefinition,
-    job_name: String,
+    args: &Run,
 ) -> Result<(), CommandError> {
-    if let Some(job) = definition.jobs.get(&job_name) {
-        if processes.image_needs_to_be_built(&context.image_tag)? {
-            prompt.info("Building Fake CI image first");
-            processes.build_image(&context.image_tag)?;
+    let job_names = match &args.job {
+        Some(job_name) => vec![job_name.clone()],
+        None => pick_jobs(prompt, definition, args.multiple),
+    };
+
+    if job_names.is_empty() {
+        prompt.info("No job selected");
+    }
+
+    let options = RunOptions {
+        log: LogOptions {
+            prefix: args.prefix,
+            timestamps: args.timestamps,
+            // The panes of the TUI render colours themselves.
+            colours: args.tui || std::io::stdout().is_terminal(),
+        },
+        debug_on_failure: args.debug_on_failure,
+        breakpoints: Breakpoints {
+            step: args.step,
+            lines: args.break_at.clone(),
+            patterns: args.break_on.clone(),
+        },
+    };
+
+    if args.tui && !job_names.is_empty() {
+        if let Some(unknown) = job_names
+            .iter()
+            .find(|name| !definition.jobs.contains_key(*name))
+        {
+            return Err(CommandError::unknown_job(unknown, definition.jobs.keys()));
         }
-        prompt.info("Checking out code");
 
-        processes.prune_checkout_container()?;
-        let checkout_container_id = processes.start_checkout_container(context)?;
+        return run_with_tui(processes, context, definition, &job_names, &options);
+    }
 
-        processes.checkout_code(&checkout_container_id, context)?;
+    for job_name in job_names {
+        run_job(
+            prompt,
+            processes,
+            context,
+            definition,
+            &job_name,
+            &options,
+            &mut std::io::stdout(),
+        )?;
+    }
 
-        if !job.required_artifacts.is_empty() {
-            prompt.info("Preparing artifacts");
+    Ok(())
+}
 
-            processes.prepare_artifacts(&checkout_container_id, &job.required_artifacts)?;
-        } else {
-            prompt.info("No artifacts to prepare");
-        }
+fn pick_jobs<PROMPTS: Prompts>(
+    prompt: &mut PROMPTS,
+    definition: &CiDefinition,
+    multiple: bool,
+) -> Vec<String> {
+    let names = definition.jobs.keys().cloned().collect::<Vec<_>>();
+
+    if names.is_empty() {
+        return vec![];
+    }
 
-        prompt.info("Running job");
+    let selection = if multiple {
+        prompt.select_multiple("Which jobs should run?", &names)
+    } else {
+        prompt
+            .select("Which job should run?", &names)
+            .into_iter()
+            .collect()
+    };
 
-        processes.prune_job_container()?;
-        let job_container_id = processes.start_job_container(job, &checkout_container_id)?;
-        processes.run_job(&job_container_id, job)?;
+    selection
+        .into_iter()
+        .filter_map(|index| names.get(index).cloned())
+        .collect()
+}
 
-        if !job.artifacts.is_empty() {
-            prompt.info("Extracting artifacts");
-            processes.extract_artifacts(&job_container_id, &job_name, job)?;
-        } else {
-            prompt.info("No artifacts to be extracted");
+fn run_job<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+    prompt: &mut PROMPTS,
+    processes: &mut PROCESSES,
+    context: &Context,
+    definition: &CiDefinition,
+    job_name: &str,
+    options: &RunOptions,
+    terminal: &mut dyn Write,
+) -> Result<(), CommandError> {
+    let Some(job) = definition.jobs.get(job_name) else {
+        return Err(CommandError::unknown_job(job_name, definition.jobs.keys()));
+    };
+
+    let started = Instant::now();
+    let result = execute_job(prompt, processes, context, job_name, job, options, terminal);
+
+    if let Some(directory) = &context.run_directory {
+        let record = JobRecord::new(job_name, job, &result, started.elapsed());
+        record_job(Path::new(directory), &context.git_sha, record)?;
+    }
+    result?;
+
+    Ok(())
+}
+
+// Returns the files that have been extracted as artifacts.
+fn execute_job<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+    prompt: &mut PROMPTS,
+    processes: &mut PROCESSES,
+    context: &Context,
+    job_name: &str,
+    job: &Job,
+    options: &RunOptions,
+    terminal: &mut dyn Write,
+) -> Result<Vec<String>, std::io::Error> {
+    let checkout_container_id = prepare_job(prompt, processes, context, job)?;
+
+    prompt.info("Running job");
+    let job_container_id = start_job(prompt, processes, job, &checkout_container_id)?;
+
+    let mut log = JobLog::new(
+        job_name,
+        options.log,
+        terminal,
+        open_log_file(context, job_name)?,
+    );
+    let result = if options.breakpoints.is_empty() {
+        processes.run_job(&job_container_id, job, &mut log)
+    } else {
+        step_through(
+            prompt,
+            processes,
+            &job_container_id,
+            job,
+            &options.breakpoints,
+            &mut log,
+        )
+    };
+    drop(log);
+
+    if result.is_err() && options.debug_on_failure {
+        prompt.info("Job failed, opening a shell in its container. Exit it to continue.");
+        processes.open_shell(job)?;
+    }
+    result?;
+
+    if !job.artifacts.is_empty() {
+        prompt.info("Extracting artifacts");
+        processes.extract_artifacts(&job_container_id, job_name, job)
+    } else {
+        prompt.info("No artifacts to be extracted");
+        Ok(vec![])
+    }
+}
+
+// Runs the script line by line, pausing at breakpoints to ask what to do with the next line. The
+// line is shown with its variables expanded.
+fn step_through<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+    prompt: &mut PROMPTS,
+    processes: &mut PROCESSES,
+    container_id: &str,
+    job: &Job,
+    breakpoints: &Breakpoints,
+    output: &mut dyn Write,
+) -> Result<(), std::io::Error> {
+    let choices = STEPS.map(|(_, label)| label.to_string());
+    let mut stepping = breakpoints.step;
+
+    for (index, original) in job.script.iter().enumerate() {
+        let number = index + 1;
+        let mut line = original.clone();
+
+        if stepping || breakpoints.is_at(number, &interpolate(&line, &job.variables)) {
+            loop {
+                let shown = mask(&interpolate(&line, &job.variables), &job.masked_values);
+                prompt.info(&format!("Line {}/{}: {}", number, job.script.len(), shown));
+
+                let step = prompt
+                    .select("What should happen with it?", &choices)
+                    .and_then(|index| STEPS.get(index))
+                    .map_or(Step::Abort, |(step, _)| *step);
+
+                match step {
+                    Step::Run => break,
+                    Step::Skip => {
+                        line.clear();
+                        break;
+                    }
+                    Step::Edit => {
+                        if let Some(edited) = prompt.input("Line", &line) {
+                            line = edited;
+                        }
+                    }
+                    Step::Shell => processes.open_shell(job)?,
+                    Step::Continue => {
+                        stepping = false;
+                        break;
+                    }
+                    Step::Abort => return Err(std::io::Error::other("job has been aborted")),
+                }
+            }
+        }
+
+        if !line.trim().is_empty() {
+            processes.run_line(container_id, job, &line, output)?;
         }
+    }
+
+    Ok(())
+}
+
+// Everything a job needs before it can start: the image of Fake CI, the code and artifacts of
+// other jobs. Returns the checkout container, which has all of it.
+pub fn prepare_job<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+    prompt: &mut PROMPTS,
+    processes: &mut PROCESSES,
+    context: &Context,
+    job: &Job,
+) -> Result<String, std::io::Error> {
+    if processes.image_needs_to_be_built(&context.image_tag)? {
+        prompt.info("Building Fake CI image first");
+        processes.build_image(&context.image_tag, &mut InfoLines::new(prompt))?;
+    }
+    prompt.info("Checking out code");
 
-        Ok(())
+    processes.prune_checkout_container()?;
+    let checkout_container_id = processes.start_checkout_container(context)?;
+
+    processes.checkout_code(
+        &checkout_container_id,
+        context,
+        &Checkout::of(&job.variables),
+    )?;
+
+    if !job.required_artifacts.is_empty() {
+        prompt.info("Preparing artifacts");
+
+        processes.prepare_artifacts(&checkout_container_id, &job.required_artifacts)?;
     } else {
-        Err(CommandError::UnknownJob(job_name.clone()))
+        prompt.info("No artifacts to prepare");
     }
+
+    Ok(checkout_container_id)
+}
+
+// Returns the job container, with the files of file variables written to it.
+pub fn start_job<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+    prompt: &mut PROMPTS,
+    processes: &mut PROCESSES,
+    job: &Job,
+    checkout_container_id: &str,
+) -> Result<String, std::io::Error> {
+    processes.prune_job_container()?;
+    let job_container_id =
+        processes.start_job_container(job, checkout_container_id, &mut InfoLines::new(prompt))?;
+
+    if !job.files.is_empty() {
+        processes.write_files(&job_container_id, &job.files)?;
+    }
+
+    Ok(job_container_id)
+}
+
+enum Request {
+    // Jobs have been queued again.
+    Wake,
+    Shell(String),
+    Stop,
+}
+
+// Jobs run one after another on a thread of their own, while the terminal shows their progress
+// and takes commands.
+fn run_with_tui<PROCESSES: ProcessesToExecute + Send>(
+    processes: &mut PROCESSES,
+    context: &Context,
+    definition: &CiDefinition,
+    job_names: &[String],
+    options: &RunOptions,
+) -> Result<(), CommandError> {
+    let jobs = job_names
+        .iter()
+        .map(|name| (name.clone(), definition.jobs[name].stage.clone()))
+        .collect::<Vec<_>>();
+    let pipeline = Arc::new(Mutex::new(Pipeline::new(&definition.stages, &jobs)));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let stop_job = processes.job_stopper();
+    let (requests, received) = mpsc::channel();
+    let (shell_closed, closed_shells) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        let worker = Worker {
+            pipeline: pipeline.clone(),
+            cancelled: cancelled.clone(),
+            received,
+            shell_closed,
+        };
+        scope.spawn(|| worker.work_through(processes, context, definition, options));
+
+        let result = show(&pipeline, &cancelled, &stop_job, &requests, &closed_shells);
+
+        // The worker has to stop in any case, or the scope never ends.
+        stop(&pipeline, &cancelled, &stop_job);
+        let _ = requests.send(Request::Stop);
+
+        result
+    })?;
+
+    let unsuccessful = lock(&pipeline).unsuccessful_count();
+
+    match unsuccessful {
+        0 => Ok(()),
+        count => Err(CommandError::UnsuccessfulJobs(count)),
+    }
+}
+
+fn show(
+    pipeline: &Mutex<Pipeline>,
+    cancelled: &AtomicBool,
+    stop_job: &JobStopper,
+    requests: &Sender<Request>,
+    closed_shells: &Receiver<Result<(), String>>,
+) -> Result<(), std::io::Error> {
+    let mut screen = Screen::enter()?;
+    let mut stopping = false;
+
+    loop {
+        screen.draw(&lock(pipeline))?;
+
+        if stopping && !lock(pipeline).is_busy() {
+            return Ok(());
+        }
+        retry_stopping(pipeline, cancelled, stop_job);
+        let Some(action) = screen.next_action(Duration::from_millis(100))? else {
+            continue;
+        };
+
+        let mut state = lock(pipeline);
+        let selected = state.selected_job().to_string();
+        state.message = None;
+
+        match action {
+            Action::Up | Action::Down | Action::Left | Action::Right => state.select(action),
+            Action::ScrollUp | Action::ScrollDown | Action::Follow => state.scroll(action, 10),
+            Action::Cancel => match state.status(&selected) {
+                Some(JobStatus::Running) => {
+                    cancelled.store(true, Ordering::SeqCst);
+                    if let Err(error) = stop_job() {
+                        state.message = Some(format!("Cannot stop {}: {}", selected, error));
+                    }
+                }
+                Some(JobStatus::Pending) => state.set_status(&selected, JobStatus::Cancelled),
+                _ => {}
+            },
+            Action::Retry | Action::Shell if stopping => {}
+            Action::Retry => {
+                if state.retry_selected() {
+                    let _ = requests.send(Request::Wake);
+                }
+            }
+            Action::Shell if state.is_busy() => {
+                state.message = Some("Shells can be opened once all jobs are done.".into());
+            }
+            Action::Shell => {
+                drop(state);
+                screen.suspend()?;
+                let _ = requests.send(Request::Shell(selected));
+                let closed = closed_shells.recv();
+                screen.resume()?;
+
+                if let Ok(Err(message)) = closed {
+                    lock(pipeline).message = Some(message);
+                }
+            }
+            Action::Quit => {
+                drop(state);
+                stop(pipeline, cancelled, stop_job);
+                lock(pipeline).message = Some("Stopping the running job.".into());
+                stopping = true;
+            }
+        }
+    }
+}
+
+// Cancels the running job and all jobs that haven't run yet.
+fn stop(pipeline: &Mutex<Pipeline>, cancelled: &AtomicBool, stop_job: &JobStopper) {
+    let mut pipeline = lock(pipeline);
+
+    pipeline.finish_pending(JobStatus::Cancelled);
+    if pipeline.is_busy() {
+        cancelled.store(true, Ordering::SeqCst);
+        let _ = stop_job();
+    }
+}
+
+// A job cancelled while it's still being prepared only has something to stop once its script
+// runs. The pipeline stays locked meanwhile, so that the worker can't move on to the next job.
+fn retry_stopping(pipeline: &Mutex<Pipeline>, cancelled: &AtomicBool, stop_job: &JobStopper) {
+    let pipeline = lock(pipeline);
+
+    if cancelled.load(Ordering::SeqCst) && pipeline.is_busy() {
+        let _ = stop_job();
+    }
+}
+
+struct Worker {
+    pipeline: Arc<Mutex<Pipeline>>,
+    cancelled: Arc<AtomicBool>,
+    received: Receiver<Request>,
+    shell_closed: Sender<Result<(), String>>,
+}
+
+impl Worker {
+    fn work_through<PROCESSES: ProcessesToExecute>(
+        self,
+        processes: &mut PROCESSES,
+        context: &Context,
+        definition: &CiDefinition,
+        options: &RunOptions,
+    ) {
+        // Only the container of the job that ran last is still around.
+        let mut last_job = None;
+
+        loop {
+            let next = lock(&self.pipeline).next_pending();
+
+            if let Some(name) = next {
+                self.cancelled.store(false, Ordering::SeqCst);
+                lock(&self.pipeline).set_status(&name, JobStatus::Running);
+
+                let mut prompt = PanePrompt {
+                    pipeline: self.pipeline.clone(),
+                    job: name.clone(),
+                };
+                let mut pane = PaneWriter {
+                    pipeline: self.pipeline.clone(),
+                    job: name.clone(),
+                    cancelled: self.cancelled.clone(),
+                };
+                let result = run_job(
+                    &mut prompt,
+                    processes,
+                    context,
+                    definition,
+                    &name,
+                    options,
+                    &mut pane,
+                );
+                last_job = Some(name.clone());
+
+                let cancelled = self.cancelled.swap(false, Ordering::SeqCst);
+                let mut pipeline = lock(&self.pipeline);
+                match result {
+                    Ok(()) => pipeline.set_status(&name, JobStatus::Passed),
+                    Err(_) if cancelled => pipeline.set_status(&name, JobStatus::Cancelled),
+                    Err(error) => {
+                        pipeline.append(&name, &format!("{}\n", error));
+                        pipeline.set_status(&name, JobStatus::Failed);
+                        // Like GitLab, later jobs don't run once one has failed.
+                        pipeline.finish_pending(JobStatus::Skipped);
+                    }
+                }
+
+                continue;
+            }
+
+            match self.received.recv() {
+                Ok(Request::Wake) => {}
+                Ok(Request::Shell(name)) => {
+                    let result = match &last_job {
+                        Some(last) if *last == name => processes
+                            .open_shell(&definition.jobs[&name])
+                            .map_err(|error| error.to_string()),
+                        Some(last) => Err(format!(
+                            "Only {} still has its container, retry {} first.",
+                            last, name
+                        )),
+                        None => Err(format!("{} hasn't run yet.", name)),
+                    };
+                    let _ = self.shell_closed.send(result);
+                }
+                Ok(Request::Stop) | Err(_) => return,
+            }
+        }
+    }
+}
+
+// Logs are kept next to the run's record, when there is one.
+fn open_log_file(
+    context: &Context,
+    job_name: &str,
+) -> Result<Option<Box<dyn Write>>, std::io::Error> {
+    let Some(directory) = &context.run_directory else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(directory)?;
+    let file = File::create(log_file(Path::new(directory), job_name))?;
+
+    Ok(Some(Box::new(file)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::core::{CiDefinition, Job};
+    use crate::io::history::read_runs;
     use crate::io::processes::tests::ProcessesSpy;
     use crate::io::prompt::tests::{FakePrompt, SpyPrompt};
+    use crate::test_support::TemporaryDirectory;
+    use indexmap::IndexMap;
     use std::collections::HashMap;
 
+    fn job_named(name: &str) -> Run {
+        Run {
+            job: Some(name.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn takes_only_committed_changes_of_other_revisions_by_default() {
+        let other_revision = Run {
+            revision: Some("v1.0".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(Run::default().source_mode(), SourceMode::Working);
+        assert_eq!(other_revision.source_mode(), SourceMode::Committed);
+        assert_eq!(
+            Run {
+                source_mode: Some(SourceMode::Staged),
+                ..other_revision
+            }
+            .source_mode(),
+            SourceMode::Staged
+        );
+    }
+
     #[test]
     fn returns_error_if_job_name_is_unknown() {
         let mut prompt = FakePrompt::always_confirming();
@@ -77,10 +696,13 @@ mod tests {
             &mut processes,
             &context,
             &definition,
-            "unknown job".into(),
+            &job_named("unknown job"),
         );
 
-        assert!(matches!(result.err().unwrap(), CommandError::UnknownJob(_)));
+        assert!(matches!(
+            result.err().unwrap(),
+            CommandError::UnknownJob { .. }
+        ));
     }
 
     #[test]
@@ -90,7 +712,8 @@ mod tests {
         let context = Context::default();
         let job = Job::default();
         let definition = CiDefinition {
-            jobs: HashMap::from([("job".into(), job)]),
+            jobs: IndexMap::from([("job".into(), job)]),
+            ..Default::default()
         };
 
         command(
@@ -98,7 +721,7 @@ mod tests {
             &mut processes,
             &context,
             &definition,
-            "job".into(),
+            &job_named("job"),
         )
         .unwrap();
 
@@ -113,7 +736,8 @@ mod tests {
         let context = Context::default();
         let job = Job::default();
         let definition = CiDefinition {
-            jobs: HashMap::from([("job".into(), job)]),
+            jobs: IndexMap::from([("job".into(), job)]),
+            ..Default::default()
         };
 
         command(
@@ -121,7 +745,7 @@ mod tests {
             &mut processes,
             &context,
             &definition,
-            "job".into(),
+            &job_named("job"),
         )
         .unwrap();
 
@@ -144,7 +768,8 @@ mod tests {
             ..Default::default()
         };
         let definition = CiDefinition {
-            jobs: HashMap::from([("job".into(), job)]),
+            jobs: IndexMap::from([("job".into(), job)]),
+            ..Default::default()
         };
 
         command(
@@ -152,7 +777,7 @@ mod tests {
             &mut processes,
             &context,
             &definition,
-            "job".into(),
+            &job_named("job"),
         )
         .unwrap();
 
@@ -169,7 +794,8 @@ mod tests {
             ..Default::default()
         };
         let definition = CiDefinition {
-            jobs: HashMap::from([("job".into(), job)]),
+            jobs: IndexMap::from([("job".into(), job)]),
+            ..Default::default()
         };
 
         command(
@@ -177,10 +803,375 @@ mod tests {
             &mut processes,
             &context,
             &definition,
-            "job".into(),
+            &job_named("job"),
         )
         .unwrap();
 
         assert_eq!(processes.extract_artifacts_call_count, 1);
     }
+
+    #[test]
+    fn records_jobs_of_the_run_with_their_artifacts() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy::new();
+        let directory = TemporaryDirectory::new("run");
+        let project = &directory.path;
+        let run_directory = project.join(".fake-ci/runs/100");
+        let context = Context {
+            git_sha: "abc".into(),
+            run_directory: Some(run_directory.display().to_string()),
+            ..Default::default()
+        };
+        let job = Job {
+            artifacts: vec!["file-1".into()],
+            ..Default::default()
+        };
+        let definition = CiDefinition {
+            jobs: IndexMap::from([("job".into(), job)]),
+            ..Default::default()
+        };
+
+        command(
+            &mut prompt,
+            &mut processes,
+            &context,
+            &definition,
+            &job_named("job"),
+        )
+        .unwrap();
+        let runs = read_runs(project).unwrap();
+        let log_exists = log_file(&run_directory, "job").exists();
+
+        let record = &runs[0].1;
+        assert_eq!(record.git_sha, "abc");
+        assert_eq!(record.jobs[0].name, "job");
+        assert!(record.jobs[0].succeeded);
+        assert_eq!(record.jobs[0].artifacts, vec!["file-1"]);
+        assert!(log_exists);
+    }
+
+    #[test]
+    fn opens_a_shell_in_failed_jobs_when_debugging() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy {
+            job_fails: true,
+            ..Default::default()
+        };
+        let definition = CiDefinition {
+            jobs: IndexMap::from([("job".into(), Job::default())]),
+            ..Default::default()
+        };
+        let args = Run {
+            debug_on_failure: true,
+            ..job_named("job")
+        };
+
+        let result = command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &definition,
+            &args,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(processes.open_shell_call_count, 1);
+    }
+
+    fn scripted_definition() -> CiDefinition {
+        let job = Job {
+            script: vec!["echo $NAME".into(), "make".into(), "make test".into()],
+            variables: vec![("NAME".into(), "value".into())],
+            ..Default::default()
+        };
+
+        CiDefinition {
+            jobs: IndexMap::from([("job".into(), job)]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn steps_through_lines_to_skip_or_edit_them() {
+        // Edit and run the first line, skip the second, run the third.
+        let mut prompt = FakePrompt::selecting(vec![2, 0, 1, 0]);
+        prompt.input = Some("echo edited".into());
+        let mut processes = ProcessesSpy::new();
+        let args = Run {
+            step: true,
+            ..job_named("job")
+        };
+
+        let result = command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &scripted_definition(),
+            &args,
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(processes.run_job_call_count, 0);
+        assert_eq!(processes.lines_run, vec!["echo edited", "make test"]);
+    }
+
+    #[test]
+    fn pauses_only_at_breakpoints() {
+        // Skips every line it stops at.
+        let mut prompt = FakePrompt::selecting(vec![1]);
+        let mut processes = ProcessesSpy::new();
+        let args = Run {
+            break_at: vec![1],
+            break_on: vec![Regex::new("make test$").unwrap()],
+            ..job_named("job")
+        };
+
+        command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &scripted_definition(),
+            &args,
+        )
+        .unwrap();
+
+        assert_eq!(processes.lines_run, vec!["make"]);
+    }
+
+    #[test]
+    fn breakpoints_match_lines_with_expanded_variables() {
+        let mut prompt = FakePrompt::selecting(vec![1]);
+        let mut processes = ProcessesSpy::new();
+        let args = Run {
+            break_on: vec![Regex::new("echo value").unwrap()],
+            ..job_named("job")
+        };
+
+        command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &scripted_definition(),
+            &args,
+        )
+        .unwrap();
+
+        assert_eq!(processes.lines_run, vec!["make", "make test"]);
+    }
+
+    #[test]
+    fn continues_to_the_next_breakpoint_or_aborts() {
+        // Continue at the first line, abort at the breakpoint on the third one.
+        let mut prompt = FakePrompt::selecting(vec![4, 5]);
+        let mut processes = ProcessesSpy::new();
+        let args = Run {
+            step: true,
+            break_at: vec![3],
+            ..job_named("job")
+        };
+
+        let result = command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &scripted_definition(),
+            &args,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(processes.lines_run, vec!["echo $NAME", "make"]);
+    }
+
+    #[test]
+    fn opens_shells_before_running_a_line() {
+        let mut prompt = FakePrompt::selecting(vec![3, 4]);
+        let mut processes = ProcessesSpy::new();
+        let args = Run {
+            break_at: vec![2],
+            ..job_named("job")
+        };
+
+        command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &scripted_definition(),
+            &args,
+        )
+        .unwrap();
+
+        assert_eq!(processes.open_shell_call_count, 1);
+        assert_eq!(processes.lines_run.len(), 3);
+    }
+
+    #[test]
+    fn does_not_open_shells_for_failed_jobs_by_default() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy {
+            job_fails: true,
+            ..Default::default()
+        };
+        let definition = CiDefinition {
+            jobs: IndexMap::from([("job".into(), Job::default())]),
+            ..Default::default()
+        };
+
+        let result = command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &definition,
+            &job_named("job"),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(processes.open_shell_call_count, 0);
+    }
+
+    #[test]
+    fn suggests_similar_job_names_for_unknown_jobs() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy::new();
+        let context = Context::default();
+        let definition = CiDefinition {
+            jobs: IndexMap::from([
+                ("unit-tests".into(), Job::default()),
+                ("lint".into(), Job::default()),
+            ]),
+            ..Default::default()
+        };
+
+        let result = command(
+            &mut prompt,
+            &mut processes,
+            &context,
+            &definition,
+            &job_named("unit-test"),
+        );
+
+        match result.err().unwrap() {
+            CommandError::UnknownJob { suggestions, .. } => {
+                assert_eq!(suggestions, vec!["unit-tests".to_string()])
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn asks_which_job_to_run_when_no_name_is_given() {
+        let mut prompt = FakePrompt::selecting(vec![1]);
+        let mut processes = ProcessesSpy::new();
+        let context = Context::default();
+        let definition = CiDefinition {
+            jobs: IndexMap::from([
+                ("first".into(), Job::default()),
+                ("second".into(), Job::default()),
+            ]),
+            ..Default::default()
+        };
+        let args = Run::default();
+
+        command(&mut prompt, &mut processes, &context, &definition, &args).unwrap();
+
+        assert!(prompt.has_been_asked_to_select);
+        assert_eq!(processes.run_job_call_count, 1);
+    }
+
+    #[test]
+    fn runs_all_selected_jobs_one_after_another() {
+        let mut prompt = FakePrompt::selecting(vec![0, 1]);
+        let mut processes = ProcessesSpy::new();
+        let context = Context::default();
+        let definition = CiDefinition {
+            jobs: IndexMap::from([
+                ("first".into(), Job::default()),
+                ("second".into(), Job::default()),
+            ]),
+            ..Default::default()
+        };
+        let args = Run {
+            multiple: true,
+            ..Default::default()
+        };
+
+        command(&mut prompt, &mut processes, &context, &definition, &args).unwrap();
+
+        assert_eq!(processes.run_job_call_count, 2);
+    }
+
+    #[test]
+    fn does_not_run_anything_when_selection_is_cancelled() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy::new();
+        let context = Context::default();
+        let definition = CiDefinition {
+            jobs: IndexMap::from([("job".into(), Job::default())]),
+            ..Default::default()
+        };
+        let args = Run::default();
+
+        command(&mut prompt, &mut processes, &context, &definition, &args).unwrap();
+
+        assert_eq!(processes.run_job_call_count, 0);
+    }
+
+    #[test]
+    fn parses_variables_given_on_the_command_line() {
+        assert_eq!(
+            parse_variable("KEY=some=value"),
+            Ok(("KEY".into(), "some=value".into()))
+        );
+        assert_eq!(parse_variable("EMPTY="), Ok(("EMPTY".into(), "".into())));
+        assert!(parse_variable("=value").is_err());
+        assert!(parse_variable("FAKE_CI_SURELY_UNSET_VARIABLE").is_err());
+    }
+
+    #[test]
+    fn stops_running_jobs_without_output() {
+        let mut processes = ProcessesSpy {
+            job_hangs: true,
+            ..Default::default()
+        };
+        let job = Job {
+            stage: "test".into(),
+            ..Default::default()
+        };
+        let definition = CiDefinition {
+            stages: vec!["test".into()],
+            jobs: IndexMap::from([("job".into(), job)]),
+        };
+        let pipeline = Arc::new(Mutex::new(Pipeline::new(
+            &definition.stages,
+            &[("job".into(), "test".into())],
+        )));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let stop_job = processes.job_stopper();
+        let (requests, received) = mpsc::channel();
+        let (shell_closed, _) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            let worker = Worker {
+                pipeline: pipeline.clone(),
+                cancelled: cancelled.clone(),
+                received,
+                shell_closed,
+            };
+            scope.spawn(|| {
+                worker.work_through(
+                    &mut processes,
+                    &Context::default(),
+                    &definition,
+                    &RunOptions::default(),
+                )
+            });
+            while lock(&pipeline).status("job") != Some(JobStatus::Running) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            stop(&pipeline, &cancelled, &stop_job);
+            requests.send(Request::Stop).unwrap();
+        });
+
+        assert_eq!(lock(&pipeline).status("job"), Some(JobStatus::Cancelled));
+    }
 }
@@ -0,0 +1,118 @@
+use crate::commands::run::{parse_variable, prepare_job, source_mode_for, start_job, Executor};
+use crate::commands::CommandError;
+use crate::core::CiDefinition;
+use crate::git::SourceMode;
+use crate::io::processes::ProcessesToExecute;
+use crate::io::prompt::Prompts;
+use crate::Context;
+use clap::Args;
+
+#[derive(Args, Default)]
+pub struct Shell {
+    /// The job whose environment to open a shell in.
+    pub job: String,
+    /// Set a variable, overriding the CI definition. `KEY` alone takes the value from the environment.
+    #[clap(short = 'e', long = "env", value_name = "KEY=VALUE", value_parser = parse_variable)]
+    pub variables: Vec<(String, String)>,
+    /// Read variables from a dotenv file. Variables given with `--env` take precedence.
+    #[clap(long, value_name = "FILE")]
+    pub env_file: Vec<String>,
+    /// Where the job would run.
+    #[clap(long, value_enum, default_value_t)]
+    pub executor: Executor,
+    /// What of the project's changes the job gets. Defaults to `committed` with `--ref`, else `working`.
+    #[clap(long, value_enum)]
+    pub source_mode: Option<SourceMode>,
+    /// Open the shell for another commit, branch or tag than `HEAD`.
+    #[clap(long = "ref", value_name = "REVISION")]
+    pub revision: Option<String>,
+}
+
+impl Shell {
+    pub fn source_mode(&self) -> SourceMode {
+        source_mode_for(self.source_mode, self.revision.as_deref())
+    }
+}
+
+// Prepares everything like for running the job, but opens a shell instead of running its script.
+pub fn command<PROMPTS: Prompts, PROCESSES: ProcessesToExecute>(
+    prompt: &mut PROMPTS,
+    processes: &mut PROCESSES,
+    context: &Context,
+    definition: &CiDefinition,
+    args: &Shell,
+) -> Result<(), CommandError> {
+    let job = definition
+        .jobs
+        .get(&args.job)
+        .ok_or_else(|| CommandError::unknown_job(&args.job, definition.jobs.keys()))?;
+
+    let checkout_container_id = prepare_job(prompt, processes, context, job)?;
+    start_job(prompt, processes, job, &checkout_container_id)?;
+
+    prompt.info("Opening a shell in the job's container, its script doesn't run");
+    processes.open_shell(job)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Job;
+    use crate::io::processes::tests::ProcessesSpy;
+    use crate::io::prompt::tests::FakePrompt;
+    use indexmap::IndexMap;
+
+    fn definition() -> CiDefinition {
+        CiDefinition {
+            jobs: IndexMap::from([("job".into(), Job::default())]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn opens_a_shell_in_the_job_container_without_running_the_job() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy::new();
+        let args = Shell {
+            job: "job".into(),
+            ..Default::default()
+        };
+
+        command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &definition(),
+            &args,
+        )
+        .unwrap();
+
+        assert_eq!(processes.checkout_code_call_count, 1);
+        assert_eq!(processes.start_job_container_call_count, 1);
+        assert_eq!(processes.open_shell_call_count, 1);
+        assert_eq!(processes.run_job_call_count, 0);
+    }
+
+    #[test]
+    fn returns_error_if_job_name_is_unknown() {
+        let mut prompt = FakePrompt::always_confirming();
+        let mut processes = ProcessesSpy::new();
+        let args = Shell {
+            job: "unknown".into(),
+            ..Default::default()
+        };
+
+        let result = command(
+            &mut prompt,
+            &mut processes,
+            &Context::default(),
+            &definition(),
+            &args,
+        );
+
+        assert!(matches!(result, Err(CommandError::UnknownJob { .. })));
+        assert_eq!(processes.open_shell_call_count, 0);
+    }
+}
@@ -3,31 +3,116 @@ use crate::file::FileAccess;
 use crate::git::GitDetails;
 use crate::gitlab;
 use crate::gitlab::configuration::{GitLabConfiguration, ListOfStrings, OneOrMoreNeeds};
-use crate::gitlab::read_gitlab_configuration;
+use crate::gitlab::error::GitLabError;
+use crate::gitlab::expansion::{escape, expand, resolve_variables};
+use crate::gitlab::lint::{available_stages, DEFAULT_STAGE};
+use crate::gitlab::{read_gitlab_configuration, GitLabAccess};
+use crate::io::docker::DIRECTORIES;
+use crate::settings::structure::ProjectVariable;
+use indexmap::IndexMap;
 use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct CiDefinition {
-    pub jobs: HashMap<String, Job>,
+    // All stages in the order they run in, including `.pre` and `.post`.
+    pub stages: Vec<String>,
+    pub jobs: IndexMap<String, Job>,
+}
+
+impl CiDefinition {
+    // Project variables win over all variables of the definition.
+    pub fn add_project_variables(&mut self, variables: &[(String, ProjectVariable)]) {
+        for job in self.jobs.values_mut() {
+            for (name, variable) in variables {
+                let value = if variable.file {
+                    let path = format!("{}/{}", DIRECTORIES.file_variables, name);
+                    job.files.push((path.clone(), variable.value.clone()));
+                    path
+                } else {
+                    variable.value.clone()
+                };
+
+                if variable.masked {
+                    job.masked_values.push(variable.value.clone());
+                }
+                job.project_variables.push(name.clone());
+
+                let value = if variable.raw { escape(&value) } else { value };
+
+                job.variables.push((name.clone(), value));
+            }
+        }
+    }
+
+    // Pipeline variables, e.g. from the command line, win over all other variables.
+    pub fn add_pipeline_variables(&mut self, variables: &[(String, String)]) {
+        for job in self.jobs.values_mut() {
+            job.variables.extend(variables.iter().cloned());
+        }
+    }
+
+    // Resolves references between variables and expands them in keywords, like GitLab does
+    // before handing jobs to a runner. Needs to happen after all variables have been added.
+    // Of the keywords GitLab expands, jobs only keep `image` and the paths of `artifacts`. Fake CI
+    // doesn't run services, caches or environments, and artifact names don't matter to it.
+    pub fn expand_variables(&mut self) -> Result<(), GitLabError> {
+        for (name, job) in self.jobs.iter_mut() {
+            job.variables = resolve_variables(&job.variables).map_err(|e| GitLabError::Job {
+                name: name.clone(),
+                source: None,
+                error: Box::new(e),
+            })?;
+            job.image = expand(&job.image, &job.variables);
+            job.artifacts = job
+                .artifacts
+                .iter()
+                .map(|path| expand(path, &job.variables))
+                .collect();
+        }
+
+        // Artifacts of needed jobs are expanded with the variables of the job that creates them.
+        let all_artifacts = self
+            .jobs
+            .iter()
+            .map(|(name, job)| (name.clone(), job.artifacts.clone()))
+            .collect::<HashMap<_, _>>();
+
+        for job in self.jobs.values_mut() {
+            for (needed_job, paths) in job.required_artifacts.iter_mut() {
+                if let Some(artifacts) = all_artifacts.get(needed_job) {
+                    *paths = artifacts.clone();
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct Job {
+    pub stage: String,
     pub image: String,
     pub script: Vec<String>,
     pub variables: Vec<(String, String)>,
     pub artifacts: Vec<String>,
     pub required_artifacts: HashMap<String, Vec<String>>,
+    // Contents of file variables by the path their variable points to.
+    pub files: Vec<(String, String)>,
+    // Values to redact from the job's output.
+    pub masked_values: Vec<String>,
+    // Names of variables from the settings, including secrets.
+    pub project_variables: Vec<String>,
 }
 
 pub async fn read_ci_definition(
     path_to_config_file: String,
     file_access: &impl FileAccess,
     git: &GitDetails,
-    gitlab_host: &String,
+    gitlab: &GitLabAccess,
 ) -> Result<CiDefinition, FakeCiError> {
     let configuration =
-        read_gitlab_configuration(path_to_config_file, file_access, git, gitlab_host).await?;
+        read_gitlab_configuration(path_to_config_file, file_access, git, gitlab).await?;
     let definition = convert_configuration(&configuration)?;
 
     Ok(definition)
@@ -37,16 +122,24 @@ fn convert_configuration(configuration: &GitLabConfiguration) -> Result<CiDefini
     let jobs = configuration
         .jobs
         .iter()
-        .map(|(key, value)| Ok((key.clone(), convert_job(value, &configuration.jobs)?)))
-        .collect::<Result<HashMap<_, _>, FakeCiError>>()?;
+        .map(|(key, value)| {
+            let job = convert_job(value, &configuration.jobs)
+                .map_err(|e| GitLabError::job(key, value, e))?;
+
+            Ok((key.clone(), job))
+        })
+        .collect::<Result<IndexMap<_, _>, FakeCiError>>()?;
 
-    Ok(CiDefinition { jobs })
+    Ok(CiDefinition {
+        stages: available_stages(configuration),
+        jobs,
+    })
 }
 
-fn convert_job(
+pub fn convert_job(
     job: &gitlab::configuration::Job,
-    other_jobs: &HashMap<String, gitlab::configuration::Job>,
-) -> Result<Job, FakeCiError> {
+    other_jobs: &IndexMap<String, gitlab::configuration::Job>,
+) -> Result<Job, GitLabError> {
     let mut final_script = vec![];
 
     final_script.extend(content_or_default(&job.before_script));
@@ -57,7 +150,9 @@ fn convert_job(
 
     if let Some(OneOrMoreNeeds(needs)) = &job.needs {
         for need in needs.iter() {
-            let other_job = other_jobs.get(&need.job).unwrap();
+            let other_job = other_jobs
+                .get(&need.job)
+                .ok_or_else(|| GitLabError::NeededJobNotFound(need.job.clone()))?;
 
             if let Some(job_artifacts) = &other_job.artifacts {
                 required.insert(need.job.clone(), job_artifacts.paths.clone());
@@ -66,6 +161,10 @@ fn convert_job(
     }
 
     Ok(Job {
+        stage: job
+            .stage
+            .clone()
+            .unwrap_or_else(|| DEFAULT_STAGE.to_string()),
         image: job.image.as_ref().cloned().unwrap_or_default(),
         script: final_script,
         variables: job.variables.clone(),
@@ -75,6 +174,7 @@ fn convert_job(
             .map(|artifacts| artifacts.paths.clone())
             .unwrap_or_default(),
         required_artifacts: required,
+        ..Default::default()
     })
 }
 
@@ -89,6 +189,203 @@ fn content_or_default(maybe_list: &Option<ListOfStrings>) -> Vec<String> {
 pub mod tests {
     use super::*;
 
+    mod test_pipeline_variables {
+        use super::*;
+
+        fn definition() -> CiDefinition {
+            CiDefinition {
+                jobs: IndexMap::from([("job".to_string(), Job::default())]),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn writes_file_variables_to_files() {
+            let mut definition = definition();
+            let variable = ProjectVariable {
+                value: "content".into(),
+                file: true,
+                ..Default::default()
+            };
+
+            definition.add_project_variables(&[("KUBECONFIG".into(), variable)]);
+
+            let job = &definition.jobs["job"];
+            assert_eq!(
+                job.variables,
+                vec![("KUBECONFIG".into(), "/job.tmp/KUBECONFIG".into())]
+            );
+            assert_eq!(
+                job.files,
+                vec![("/job.tmp/KUBECONFIG".into(), "content".into())]
+            );
+        }
+
+        #[test]
+        fn keeps_track_of_masked_variables_and_escapes_raw_ones() {
+            let mut definition = definition();
+            let variable = ProjectVariable {
+                value: "secret-$value".into(),
+                masked: true,
+                raw: true,
+                ..Default::default()
+            };
+
+            definition.add_project_variables(&[("TOKEN".into(), variable)]);
+
+            let job = &definition.jobs["job"];
+            assert_eq!(job.masked_values, vec!["secret-$value".to_string()]);
+            assert_eq!(job.project_variables, vec!["TOKEN".to_string()]);
+            assert_eq!(
+                job.variables,
+                vec![("TOKEN".into(), "secret-$$value".into())]
+            );
+        }
+
+        #[test]
+        fn expands_variables_in_keywords() {
+            let mut definition = CiDefinition {
+                jobs: IndexMap::from([(
+                    "job".to_string(),
+                    Job {
+                        image: "ruby:$RUBY_VERSION".into(),
+                        artifacts: vec!["${OUTPUT}/report.xml".into()],
+                        variables: vec![
+                            ("RUBY_VERSION".into(), "${MAJOR}.2".into()),
+                            ("MAJOR".into(), "3".into()),
+                            ("OUTPUT".into(), "target".into()),
+                        ],
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            };
+
+            definition.expand_variables().unwrap();
+
+            let job = &definition.jobs["job"];
+            assert_eq!(job.image, "ruby:3.2");
+            assert_eq!(job.artifacts, vec!["target/report.xml".to_string()]);
+            assert_eq!(job.variables[0], ("RUBY_VERSION".into(), "3.2".into()));
+        }
+
+        #[test]
+        fn expands_artifacts_of_needed_jobs_with_their_variables() {
+            let mut definition = CiDefinition {
+                jobs: IndexMap::from([
+                    (
+                        "build".to_string(),
+                        Job {
+                            artifacts: vec!["$OUTPUT".into()],
+                            variables: vec![("OUTPUT".into(), "target".into())],
+                            ..Default::default()
+                        },
+                    ),
+                    (
+                        "test".to_string(),
+                        Job {
+                            required_artifacts: HashMap::from([(
+                                "build".into(),
+                                vec!["$OUTPUT".into()],
+                            )]),
+                            ..Default::default()
+                        },
+                    ),
+                ]),
+                ..Default::default()
+            };
+
+            definition.expand_variables().unwrap();
+
+            assert_eq!(
+                definition.jobs["test"].required_artifacts["build"],
+                vec!["target".to_string()]
+            );
+        }
+
+        #[test]
+        fn appends_pipeline_variables_so_that_they_take_precedence() {
+            let mut definition = CiDefinition {
+                jobs: IndexMap::from([(
+                    "job".to_string(),
+                    Job {
+                        variables: vec![("KEY".into(), "definition".into())],
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            };
+
+            definition.add_pipeline_variables(&[("KEY".into(), "pipeline".into())]);
+
+            assert_eq!(
+                definition.jobs["job"].variables,
+                vec![
+                    ("KEY".into(), "definition".into()),
+                    ("KEY".into(), "pipeline".into())
+                ]
+            );
+        }
+    }
+
+    mod test_precedence {
+        use super::*;
+        use crate::file::StubFiles;
+        use crate::settings::structure::{Secrets, Settings};
+        use crate::settings::variables::{pipeline_variables, project_variables};
+
+        #[test]
+        fn command_line_and_env_files_win_over_settings() {
+            let mut definition = CiDefinition {
+                jobs: IndexMap::from([("job".to_string(), Job::default())]),
+                ..Default::default()
+            };
+            let file_access = StubFiles::with_file(".env", "KEY=env-file\n");
+            let settings = Settings {
+                variables: IndexMap::from([
+                    (
+                        "KEY".into(),
+                        ProjectVariable {
+                            value: "settings".into(),
+                            ..Default::default()
+                        },
+                    ),
+                    (
+                        "SETTINGS".into(),
+                        ProjectVariable {
+                            value: "settings".into(),
+                            ..Default::default()
+                        },
+                    ),
+                ]),
+                ..Default::default()
+            };
+            let project_variables =
+                project_variables(&settings, &Secrets::default(), "main").unwrap();
+            let pipeline_variables = pipeline_variables(
+                &[".env".into()],
+                &[("KEY".into(), "command-line".into())],
+                &file_access,
+            )
+            .unwrap();
+
+            definition.add_project_variables(&project_variables);
+            definition.add_pipeline_variables(&pipeline_variables);
+            definition.expand_variables().unwrap();
+
+            let variables = &definition.jobs["job"].variables;
+            let value_of = |name: &str| {
+                variables
+                    .iter()
+                    .rev()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            assert_eq!(value_of("KEY"), Some("command-line"));
+            assert_eq!(value_of("SETTINGS"), Some("settings"));
+        }
+    }
+
     mod test_gitlab_conversion {
         use super::*;
         use crate::gitlab;
@@ -97,7 +394,7 @@ pub mod tests {
         #[test]
         fn converts_gitlab_jobs() {
             let gitlab_configuration = GitLabConfiguration {
-                jobs: HashMap::from([
+                jobs: IndexMap::from([
                     ("job1".to_string(), gitlab::configuration::Job::default()),
                     ("job2".to_string(), gitlab::configuration::Job::default()),
                 ]),
@@ -111,7 +408,7 @@ pub mod tests {
 
         #[test]
         fn copies_job_image() {
-            let other_jobs = HashMap::new();
+            let other_jobs = IndexMap::new();
             let gitlab_job = gitlab::configuration::Job {
                 image: Some("image:name".into()),
                 ..Default::default()
@@ -124,7 +421,7 @@ pub mod tests {
 
         #[test]
         fn copies_job_variables() {
-            let other_jobs = HashMap::new();
+            let other_jobs = IndexMap::new();
             let gitlab_job = gitlab::configuration::Job {
                 variables: vec![("VARIABLE".into(), "value".into())],
                 ..Default::default()
@@ -137,7 +434,7 @@ pub mod tests {
 
         #[test]
         fn combines_before_script_main_script_and_after_script_into_one() {
-            let other_jobs = HashMap::new();
+            let other_jobs = IndexMap::new();
             let gitlab_job = gitlab::configuration::Job {
                 before_script: Some(ListOfStrings(vec!["before-script".into()])),
                 script: Some(ListOfStrings(vec!["script".into()])),
@@ -159,7 +456,7 @@ pub mod tests {
 
         #[test]
         fn keeps_list_of_artifacts_to_extract() {
-            let other_jobs = HashMap::new();
+            let other_jobs = IndexMap::new();
             let gitlab_job = gitlab::configuration::Job {
                 artifacts: Some(gitlab::configuration::Artifacts {
                     paths: vec!["file-1".into(), "file-2".into()],
@@ -176,9 +473,27 @@ pub mod tests {
             );
         }
 
+        #[test]
+        fn fails_when_needed_job_does_not_exist() {
+            let other_jobs = IndexMap::new();
+            let gitlab_job = gitlab::configuration::Job {
+                needs: Some(OneOrMoreNeeds(vec![Needs {
+                    job: "missing-job".into(),
+                    artifacts: true,
+                }])),
+                ..Default::default()
+            };
+
+            let result = convert_job(&gitlab_job, &other_jobs);
+
+            assert!(
+                matches!(result, Err(GitLabError::NeededJobNotFound(name)) if name == "missing-job")
+            );
+        }
+
         #[test]
         fn knows_which_artifacts_it_needs_from_other_jobs() {
-            let other_jobs = HashMap::from([(
+            let other_jobs = IndexMap::from([(
                 "other-job".to_string(),
                 gitlab::configuration::Job {
                     artifacts: Some(Artifacts {
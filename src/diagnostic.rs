@@ -0,0 +1,377 @@
+use crate::commands::CommandError;
+use crate::error::FakeCiError;
+use crate::gitlab::error::GitLabError;
+use crate::gitlab::lint::Problem;
+use crate::gitlab::source::{label, Source};
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Readable report with snippets of the offending YAML.
+    #[default]
+    Human,
+    /// One JSON object per line, e.g. for editor integrations.
+    Json,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    Error,
+}
+
+// A problem in a configuration file, rendered similar to compiler errors.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    #[serde(flatten)]
+    pub span: Option<Span>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+// Lines and columns start at 1.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    // Points at the keyword of a job, or at the job itself if it doesn't have that keyword.
+    fn at_job(mut self, name: &str, source: Option<&Source>, keyword: Option<&str>) -> Self {
+        let Some(source) = source else {
+            return self;
+        };
+        let (line, column, length) = match keyword.and_then(|k| source.keywords.get(k)) {
+            Some(position) => (position.line, position.column, keyword.unwrap_or("").len()),
+            None => (source.line, source.column, name.len()),
+        };
+
+        self.snippet = source.line_text(line).map(String::from);
+        self.span = Some(Span {
+            file: source.file.clone(),
+            line,
+            column,
+            length,
+        });
+
+        self
+    }
+
+    fn with_hint(mut self, hint: Option<String>) -> Self {
+        self.hint = hint;
+        self
+    }
+
+    pub fn format(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Human => self.render(),
+            ErrorFormat::Json => serde_json::to_string(self).unwrap_or_default(),
+        }
+    }
+
+    //     error: job `build`: unknown keyword `scirpt`
+    //      --> .gitlab-ci.yml:6:3
+    //       |
+    //     6 |   scirpt: make
+    //       |   ^^^^^^
+    //       = hint: see https://docs.gitlab.com/ee/ci/yaml/#job-keywords
+    fn render(&self) -> String {
+        let mut lines = vec![format!("error: {}", self.message)];
+
+        if let Some(span) = &self.span {
+            let gutter = " ".repeat(span.line.to_string().len());
+
+            lines.push(format!(
+                "{}--> {}:{}:{}",
+                gutter, span.file, span.line, span.column
+            ));
+
+            if let Some(snippet) = &self.snippet {
+                lines.push(format!("{} |", gutter));
+                lines.push(format!("{} | {}", span.line, snippet));
+                lines.push(format!(
+                    "{} | {}{}",
+                    gutter,
+                    " ".repeat(span.column.saturating_sub(1)),
+                    "^".repeat(span.length.max(1))
+                ));
+            }
+
+            if let Some(hint) = &self.hint {
+                lines.push(format!("{} = hint: {}", gutter, hint));
+            }
+        } else if let Some(hint) = &self.hint {
+            lines.push(format!("  = hint: {}", hint));
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl From<&Problem> for Diagnostic {
+    fn from(problem: &Problem) -> Self {
+        let Some(name) = &problem.name else {
+            return Diagnostic::new(problem.message.clone()).with_hint(problem.hint.clone());
+        };
+
+        Diagnostic::new(format!("{}: {}", label(name), problem.message))
+            .at_job(name, problem.source.as_ref(), problem.keyword.as_deref())
+            .with_hint(problem.hint.clone())
+    }
+}
+
+impl From<&GitLabError> for Diagnostic {
+    fn from(error: &GitLabError) -> Self {
+        match error {
+            GitLabError::Syntax {
+                file,
+                line_text,
+                error,
+            } => {
+                let mut diagnostic =
+                    Diagnostic::new(format!("syntax error in {}: {}", file, error));
+
+                if let Some(location) = error.location() {
+                    diagnostic.span = Some(Span {
+                        file: file.clone(),
+                        line: location.line(),
+                        column: location.column(),
+                        length: 1,
+                    });
+                    diagnostic.snippet = line_text.clone();
+                }
+
+                diagnostic
+            }
+            GitLabError::Job {
+                name,
+                source,
+                error,
+            } => {
+                let (keyword, hint) = match error.as_ref() {
+                    GitLabError::TemplateNotFound(_) => (
+                        Some("extends"),
+                        Some("define the template or remove it from `extends`".to_string()),
+                    ),
+                    GitLabError::NeededJobNotFound(_) => (
+                        Some("needs"),
+                        Some("define the job or remove it from `needs`".to_string()),
+                    ),
+                    _ => (None, None),
+                };
+
+                Diagnostic::new(format!("{}: {}", label(name), error))
+                    .at_job(name, source.as_deref(), keyword)
+                    .with_hint(hint)
+            }
+            e => Diagnostic::new(format!(
+                "Ran into an issue while parsing configuration file: {}",
+                e
+            )),
+        }
+    }
+}
+
+impl From<&FakeCiError> for Diagnostic {
+    fn from(error: &FakeCiError) -> Self {
+        match error {
+            FakeCiError::File(e) => Diagnostic::new(e.to_string()),
+            FakeCiError::Git(e) => Diagnostic::new(format!("Couldn't gather git details: {}", e)),
+            FakeCiError::GitLab(e) => Diagnostic::from(e),
+            FakeCiError::Other(e) => Diagnostic::new(format!("Unexpected error: {}", e)),
+            FakeCiError::IO(e) => Diagnostic::new(format!("Unexpected IO error: {}", e)),
+            FakeCiError::Command(CommandError::GitLab(e)) => Diagnostic::from(e),
+            FakeCiError::Command(e @ CommandError::UnknownJob { suggestions, .. }) => {
+                let hint = match suggestions.as_slice() {
+                    [] => "see `fake-ci list` for all jobs".to_string(),
+                    names => format!(
+                        "did you mean {}?",
+                        names
+                            .iter()
+                            .map(|name| format!("`{}`", name))
+                            .collect::<Vec<_>>()
+                            .join(" or ")
+                    ),
+                };
+
+                Diagnostic::new(format!("Error running command: {}", e)).with_hint(Some(hint))
+            }
+            FakeCiError::Command(e @ CommandError::NoLogs { .. }) => {
+                Diagnostic::new(format!("Error running command: {}", e))
+                    .with_hint(Some("see `fake-ci history` for past runs".into()))
+            }
+            FakeCiError::Command(e) => Diagnostic::new(format!("Error running command: {}", e)),
+            FakeCiError::Settings(e) => Diagnostic::new(format!("Error reading settings: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::source::Position;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn source() -> Source {
+        Source {
+            file: ".gitlab-ci.yml".into(),
+            line: 4,
+            column: 1,
+            keywords: HashMap::from([("scirpt".into(), Position { line: 6, column: 3 })]),
+            content: Arc::from("stages:\n  - build\n\nbuild:\n  script: make\n  scirpt: make\n"),
+        }
+    }
+
+    #[test]
+    fn renders_snippet_with_caret_under_the_keyword() {
+        let problem = Problem {
+            name: Some("build".into()),
+            source: Some(source()),
+            keyword: Some("scirpt".into()),
+            message: "unknown keyword `scirpt`".into(),
+            hint: Some("check the spelling".into()),
+        };
+
+        let output = Diagnostic::from(&problem).format(ErrorFormat::Human);
+
+        assert_eq!(
+            output,
+            "\
+error: job `build`: unknown keyword `scirpt`
+ --> .gitlab-ci.yml:6:3
+  |
+6 |   scirpt: make
+  |   ^^^^^^
+  = hint: check the spelling"
+        );
+    }
+
+    #[test]
+    fn points_at_the_job_when_keyword_is_unknown() {
+        let problem = Problem {
+            name: Some("build".into()),
+            source: Some(source()),
+            keyword: None,
+            message: "missing `script`".into(),
+            hint: None,
+        };
+
+        let diagnostic = Diagnostic::from(&problem);
+
+        assert_eq!(
+            diagnostic.span,
+            Some(Span {
+                file: ".gitlab-ci.yml".into(),
+                line: 4,
+                column: 1,
+                length: 5
+            })
+        );
+        assert_eq!(diagnostic.snippet, Some("build:".into()));
+    }
+
+    #[test]
+    fn renders_only_the_message_without_a_location() {
+        let diagnostic = Diagnostic::new("something went wrong");
+
+        assert_eq!(
+            diagnostic.format(ErrorFormat::Human),
+            "error: something went wrong"
+        );
+    }
+
+    #[test]
+    fn renders_json() {
+        let problem = Problem {
+            name: Some("build".into()),
+            source: Some(source()),
+            keyword: Some("scirpt".into()),
+            message: "unknown keyword `scirpt`".into(),
+            hint: None,
+        };
+
+        let output = Diagnostic::from(&problem).format(ErrorFormat::Json);
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["message"], "job `build`: unknown keyword `scirpt`");
+        assert_eq!(json["file"], ".gitlab-ci.yml");
+        assert_eq!(json["line"], 6);
+        assert_eq!(json["column"], 3);
+        assert_eq!(json["length"], 6);
+        assert_eq!(json["snippet"], "  scirpt: make");
+        assert!(json.get("hint").is_none());
+    }
+
+    #[test]
+    fn locates_syntax_errors() {
+        let content = "stages: build\n  other: value\n";
+        let error = serde_yaml::from_str::<serde_yaml::Value>(content).unwrap_err();
+        let error = GitLabError::syntax("ci.yml", content, error);
+
+        let diagnostic = Diagnostic::from(&error);
+        let span = diagnostic.span.unwrap();
+
+        assert_eq!(span.file, "ci.yml");
+        assert_eq!(span.line, 2);
+        assert_eq!(diagnostic.snippet, Some("  other: value".into()));
+    }
+
+    #[test]
+    fn suggests_similar_job_names() {
+        let error = FakeCiError::Command(CommandError::UnknownJob {
+            name: "tset".into(),
+            suggestions: vec!["test".into(), "tests".into()],
+        });
+
+        assert_eq!(
+            Diagnostic::from(&error).format(ErrorFormat::Human),
+            "\
+error: Error running command: unknown job 'tset'
+  = hint: did you mean `test` or `tests`?"
+        );
+    }
+
+    #[test]
+    fn points_at_the_keyword_of_failing_jobs() {
+        let mut source = source();
+        source
+            .keywords
+            .insert("extends".into(), Position { line: 5, column: 3 });
+        let job = crate::gitlab::configuration::Job {
+            source: Some(source),
+            ..Default::default()
+        };
+        let error = GitLabError::job(
+            "build",
+            &job,
+            GitLabError::TemplateNotFound(".missing".into()),
+        );
+
+        let diagnostic = Diagnostic::from(&error);
+
+        assert_eq!(
+            diagnostic.message,
+            "job `build`: template '.missing' not found"
+        );
+        assert_eq!(diagnostic.span.unwrap().line, 5);
+        assert!(diagnostic.hint.is_some());
+    }
+}
@@ -1,11 +1,16 @@
 use async_trait::async_trait;
-use reqwest::IntoUrl;
+use duct::cmd;
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::{IntoUrl, StatusCode};
 #[cfg(test)]
 use std::collections::HashMap;
 use std::env::current_dir;
 use std::io::{Cursor, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use thiserror::Error;
+use url::Url;
 
 #[derive(Error, Debug)]
 pub enum FileAccessError {
@@ -13,6 +18,8 @@ pub enum FileAccessError {
     CannotRead(String, #[source] Box<dyn std::error::Error + Send + Sync>),
     #[error("file not found {0}")]
     NotFound(String),
+    #[error("not authorised to read {0} (is a GitLab token configured in .fake-ci.yml?)")]
+    Unauthorised(String),
     #[cfg(test)]
     #[error("file {0} has not been stubbed")]
     NotStubbed(String),
@@ -44,6 +51,10 @@ impl FileAccessError {
     pub fn not_found<URL: IntoUrl>(url: &URL) -> Self {
         FileAccessError::NotFound(full_url(url))
     }
+
+    pub fn unauthorised<URL: IntoUrl>(url: &URL) -> Self {
+        FileAccessError::Unauthorised(full_url(url))
+    }
 }
 
 #[async_trait(?Send)]
@@ -58,11 +69,73 @@ pub trait FileAccess {
         url: URL,
     ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError>;
 
+    fn read_repository_file<P: AsRef<Path>>(
+        &self,
+        repository: P,
+        r#ref: &str,
+        path: &str,
+    ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError>;
+
+    // All files below `directory`, recursively, relative to `directory`.
+    fn list_local_files<P: AsRef<Path>>(
+        &self,
+        directory: P,
+    ) -> Result<Vec<String>, FileAccessError>;
+
     fn read_current_directory(&self) -> Result<String, FileAccessError>;
 }
 
 #[derive(Default)]
-pub struct RealFileSystem;
+pub struct RealFileSystem {
+    credentials: Option<Credentials>,
+}
+
+type TokenSource =
+    Box<dyn Fn() -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> + Send + Sync>;
+
+// A token is only ever sent to the host it belongs to and never to other remote includes.
+// It's resolved when a file is first read from that host, since that can ask for a password.
+struct Credentials {
+    host: Url,
+    source: TokenSource,
+    token: OnceLock<Option<String>>,
+}
+
+impl RealFileSystem {
+    pub fn with_token<F, E>(host: &str, source: F) -> Result<Self, FileAccessError>
+    where
+        F: Fn() -> Result<Option<String>, E> + Send + Sync + 'static,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let host = Url::parse(host).map_err(|e| FileAccessError::cannot_read(host, e))?;
+
+        Ok(Self {
+            credentials: Some(Credentials {
+                host,
+                source: Box::new(move || source().map_err(Into::into)),
+                token: OnceLock::new(),
+            }),
+        })
+    }
+
+    fn token_for(&self, url: &Url) -> Result<Option<&str>, FileAccessError> {
+        let Some(credentials) = self
+            .credentials
+            .as_ref()
+            .filter(|credentials| credentials.host.origin() == url.origin())
+        else {
+            return Ok(None);
+        };
+
+        if credentials.token.get().is_none() {
+            let token =
+                (credentials.source)().map_err(|e| FileAccessError::cannot_read_remote(url, e))?;
+            let _ = credentials.token.set(token);
+        }
+
+        Ok(credentials.token.get().and_then(|token| token.as_deref()))
+    }
+}
 
 #[async_trait(?Send)]
 impl FileAccess for RealFileSystem {
@@ -85,14 +158,44 @@ impl FileAccess for RealFileSystem {
         &self,
         url: URL,
     ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError> {
-        let response = reqwest::get(url.as_str())
+        let parsed_url =
+            Url::parse(url.as_str()).map_err(|e| FileAccessError::cannot_read_remote(&url, e))?;
+        // Redirects are not followed because GitLab answers requests for private files
+        // by redirecting to its sign-in page, which would otherwise be read as the file.
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()
+            .map_err(|e| FileAccessError::cannot_read_remote(&url, e))?;
+        let mut request = client.get(parsed_url.clone());
+
+        if let Some(token) = self.token_for(&parsed_url)? {
+            request = request.header("PRIVATE-TOKEN", token);
+        }
+
+        let response = request
+            .send()
             .await
             .map_err(|e| FileAccessError::cannot_read_remote(&url, e))?;
+        let status = response.status();
+
+        if status == StatusCode::UNAUTHORIZED
+            || status == StatusCode::FORBIDDEN
+            || (status.is_redirection() && redirects_to_sign_in(&response))
+        {
+            return Err(FileAccessError::unauthorised(&url));
+        }
 
-        if response.status().as_u16() > 400 {
+        if status == StatusCode::NOT_FOUND {
             return Err(FileAccessError::not_found(&url));
         }
 
+        if !status.is_success() {
+            return Err(FileAccessError::cannot_read_remote(
+                &url,
+                format!("HTTP status {}", status),
+            ));
+        }
+
         let response = response
             .bytes()
             .await
@@ -101,6 +204,85 @@ impl FileAccess for RealFileSystem {
         Ok(Box::new(Cursor::new(response.to_vec())))
     }
 
+    fn read_repository_file<P: AsRef<Path>>(
+        &self,
+        repository: P,
+        r#ref: &str,
+        path: &str,
+    ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError> {
+        let location = repository_file_location(&repository, r#ref, path);
+        let output = cmd!("git", "show", format!("{}:{}", r#ref, path))
+            .dir(repository.as_ref())
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(|e| FileAccessError::cannot_read(&location, e))?;
+
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+
+            if message.contains("does not exist") || message.contains("exists on disk, but not in")
+            {
+                return Err(FileAccessError::NotFound(location));
+            }
+
+            return Err(FileAccessError::cannot_read(
+                &location,
+                message.trim().to_string(),
+            ));
+        }
+
+        Ok(Box::new(Cursor::new(output.stdout)))
+    }
+
+    // Only what git knows about: tracked files and untracked ones that aren't ignored. Build
+    // output like `target/` or `node_modules/` is neither walked nor matched.
+    fn list_local_files<P: AsRef<Path>>(
+        &self,
+        directory: P,
+    ) -> Result<Vec<String>, FileAccessError> {
+        let directory = directory.as_ref();
+        if !directory.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let output = cmd!(
+            "git",
+            "ls-files",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+            "-z"
+        )
+        .dir(directory)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| FileAccessError::cannot_read(directory, e))?;
+
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(FileAccessError::cannot_read(
+                directory,
+                message.trim().to_string(),
+            ));
+        }
+
+        // Tracked files that have been deleted are listed as well.
+        let mut files = output
+            .stdout
+            .split(|byte| *byte == 0)
+            .map(|file| String::from_utf8_lossy(file).to_string())
+            .filter(|file| !file.is_empty() && directory.join(file).is_file())
+            .collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+
+        Ok(files)
+    }
+
     fn read_current_directory(&self) -> Result<String, FileAccessError> {
         let current_path = current_dir().map_err(|e| FileAccessError::cannot_read(".", e))?;
 
@@ -108,6 +290,144 @@ impl FileAccess for RealFileSystem {
     }
 }
 
+// Reads files of the project from the working tree, or as of a revision with `git show`, where an
+// empty revision stands for the index. Files outside of the project, and remote ones, are always
+// read as they are.
+pub struct ProjectFiles<'a, F> {
+    files: &'a F,
+    root: PathBuf,
+    revision: Option<String>,
+}
+
+impl<'a, F: FileAccess> ProjectFiles<'a, F> {
+    pub fn new(files: &'a F, root: impl Into<PathBuf>, revision: Option<String>) -> Self {
+        ProjectFiles {
+            files,
+            root: root.into(),
+            revision,
+        }
+    }
+
+    // For files that aren't committed, like secrets.
+    pub fn working_tree(&self) -> &F {
+        self.files
+    }
+
+    // The revision and the path relative to the project, unless read from the working tree.
+    fn in_revision(&self, path: &Path) -> Option<(&str, String)> {
+        let revision = self.revision.as_deref()?;
+        let relative = path.strip_prefix(&self.root).ok()?;
+
+        Some((revision, relative.to_string_lossy().to_string()))
+    }
+}
+
+#[async_trait(?Send)]
+impl<F: FileAccess> FileAccess for ProjectFiles<'_, F> {
+    fn read_local_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError> {
+        match self.in_revision(path.as_ref()) {
+            Some((revision, relative)) => self
+                .files
+                .read_repository_file(&self.root, revision, &relative),
+            None => self.files.read_local_file(path),
+        }
+    }
+
+    async fn read_remote_file<URL: IntoUrl>(
+        &self,
+        url: URL,
+    ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError> {
+        self.files.read_remote_file(url).await
+    }
+
+    fn read_repository_file<P: AsRef<Path>>(
+        &self,
+        repository: P,
+        r#ref: &str,
+        path: &str,
+    ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError> {
+        self.files.read_repository_file(repository, r#ref, path)
+    }
+
+    fn list_local_files<P: AsRef<Path>>(
+        &self,
+        directory: P,
+    ) -> Result<Vec<String>, FileAccessError> {
+        let Some((revision, relative)) = self.in_revision(directory.as_ref()) else {
+            return self.files.list_local_files(directory);
+        };
+        let prefix = match relative.as_str() {
+            "" => String::new(),
+            relative => format!("{}/", relative.trim_end_matches('/')),
+        };
+        // Paths are relative to the root, where git runs.
+        let pathspec = if prefix.is_empty() { "." } else { &prefix };
+        let expression = match revision {
+            "" => cmd!("git", "ls-files", "--cached", "-z", "--", pathspec),
+            revision => cmd!(
+                "git",
+                "ls-tree",
+                "-r",
+                "--name-only",
+                "-z",
+                revision,
+                "--",
+                pathspec
+            ),
+        };
+
+        let output = expression
+            .dir(&self.root)
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(|e| FileAccessError::cannot_read(directory.as_ref(), e))?;
+
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(FileAccessError::cannot_read(
+                directory.as_ref(),
+                message.trim().to_string(),
+            ));
+        }
+
+        let mut files = output
+            .stdout
+            .split(|byte| *byte == 0)
+            .filter_map(|file| {
+                String::from_utf8_lossy(file)
+                    .strip_prefix(&prefix)
+                    .map(String::from)
+            })
+            .filter(|file| !file.is_empty())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        Ok(files)
+    }
+
+    fn read_current_directory(&self) -> Result<String, FileAccessError> {
+        self.files.read_current_directory()
+    }
+}
+
+fn repository_file_location<P: AsRef<Path>>(repository: P, r#ref: &str, path: &str) -> String {
+    format!("{}:{} ({})", r#ref, path, file_path(repository))
+}
+
+fn redirects_to_sign_in(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(|location| location.contains("/users/sign_in"))
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 #[derive(Default)]
 pub struct StubFiles {
@@ -129,6 +449,16 @@ impl StubFiles {
     pub fn add_remote_file(&mut self, url: &str, content: &str) {
         self.add_file(url, content);
     }
+
+    pub fn add_repository_file(
+        &mut self,
+        repository: &str,
+        r#ref: &str,
+        path: &str,
+        content: &str,
+    ) {
+        self.add_file(&repository_file_location(repository, r#ref, path), content);
+    }
 }
 
 #[cfg(test)]
@@ -155,7 +485,313 @@ impl FileAccess for StubFiles {
         self.read_local_file(url.as_str())
     }
 
+    fn read_repository_file<P: AsRef<Path>>(
+        &self,
+        repository: P,
+        r#ref: &str,
+        path: &str,
+    ) -> Result<Box<Cursor<Vec<u8>>>, FileAccessError> {
+        self.read_local_file(repository_file_location(repository, r#ref, path))
+    }
+
+    fn list_local_files<P: AsRef<Path>>(
+        &self,
+        directory: P,
+    ) -> Result<Vec<String>, FileAccessError> {
+        let directory = file_path(directory);
+        let prefix = match directory.as_str() {
+            "" | "." => "".to_string(),
+            directory => format!("{}/", directory.trim_end_matches('/')),
+        };
+
+        Ok(self
+            .file_contents
+            .keys()
+            .filter_map(|file_name| file_name.strip_prefix(&prefix))
+            .map(String::from)
+            .collect())
+    }
+
     fn read_current_directory(&self) -> Result<String, FileAccessError> {
         Ok(".".into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Serves exactly one request with the given raw response and hands back the request headers.
+    fn serve_once(response: &'static str) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+                request.push_str(&line);
+            }
+
+            stream.write_all(response.as_bytes()).unwrap();
+            sender.send(request).unwrap();
+        });
+
+        (address, receiver)
+    }
+
+    const OK_RESPONSE: &str =
+        "HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\nstages:";
+
+    #[test]
+    fn reads_files_from_git_repositories() {
+        let file_access = RealFileSystem::default();
+
+        let content = file_access
+            .read_repository_file(env!("CARGO_MANIFEST_DIR"), "HEAD", "Cargo.toml")
+            .unwrap();
+
+        assert!(String::from_utf8(content.into_inner())
+            .unwrap()
+            .contains("fake-ci"));
+    }
+
+    #[test]
+    fn reports_missing_files_in_git_repositories() {
+        let file_access = RealFileSystem::default();
+
+        let result = file_access.read_repository_file(
+            env!("CARGO_MANIFEST_DIR"),
+            "HEAD",
+            "file-that-does-not-exist.yml",
+        );
+
+        assert!(matches!(result, Err(FileAccessError::NotFound(_))));
+    }
+
+    #[test]
+    fn lists_files_recursively_relative_to_directory() {
+        let file_access = RealFileSystem::default();
+
+        let files = file_access
+            .list_local_files(format!("{}/src", env!("CARGO_MANIFEST_DIR")))
+            .unwrap();
+
+        assert!(files.contains(&"main.rs".to_string()));
+        assert!(files.contains(&"gitlab/mod.rs".to_string()));
+    }
+
+    #[test]
+    fn lists_no_ignored_files() {
+        let file_access = RealFileSystem::default();
+
+        let files = file_access
+            .list_local_files(env!("CARGO_MANIFEST_DIR"))
+            .unwrap();
+
+        assert!(files.contains(&"Cargo.toml".to_string()));
+        assert!(!files.iter().any(|file| file.starts_with("target/")));
+    }
+
+    #[tokio::test]
+    async fn reads_remote_files() {
+        let (address, _) = serve_once(OK_RESPONSE);
+        let file_access = RealFileSystem::default();
+
+        let content = file_access
+            .read_remote_file(format!("{address}/file.yml"))
+            .await
+            .unwrap();
+
+        assert_eq!(content.into_inner(), b"stages:".to_vec());
+    }
+
+    #[tokio::test]
+    async fn sends_token_to_the_gitlab_host() {
+        let (address, request) = serve_once(OK_RESPONSE);
+        let file_access = RealFileSystem::with_token(&address, || {
+            Ok::<_, FileAccessError>(Some("the-token".into()))
+        })
+        .unwrap();
+
+        file_access
+            .read_remote_file(format!("{address}/file.yml"))
+            .await
+            .unwrap();
+
+        assert!(request
+            .recv()
+            .unwrap()
+            .to_lowercase()
+            .contains("private-token: the-token"));
+    }
+
+    #[tokio::test]
+    async fn does_not_resolve_or_send_token_for_other_hosts() {
+        let (address, request) = serve_once(OK_RESPONSE);
+        let resolved = Arc::new(AtomicBool::new(false));
+        let file_access = RealFileSystem::with_token("https://gitlab.example.com", {
+            let resolved = resolved.clone();
+            move || {
+                resolved.store(true, Ordering::SeqCst);
+                Ok::<_, FileAccessError>(Some("the-token".into()))
+            }
+        })
+        .unwrap();
+
+        file_access
+            .read_remote_file(format!("{address}/file.yml"))
+            .await
+            .unwrap();
+
+        assert!(!request
+            .recv()
+            .unwrap()
+            .to_lowercase()
+            .contains("private-token"));
+        assert!(!resolved.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn reports_unauthorised_requests() {
+        let (address, _) = serve_once(
+            "HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        );
+        let file_access = RealFileSystem::default();
+
+        let result = file_access
+            .read_remote_file(format!("{address}/file.yml"))
+            .await;
+
+        assert!(matches!(result, Err(FileAccessError::Unauthorised(_))));
+    }
+
+    #[tokio::test]
+    async fn reports_redirects_to_the_sign_in_page_as_unauthorised() {
+        let (address, _) = serve_once(
+            "HTTP/1.1 302 Found\r\nlocation: /users/sign_in\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        );
+        let file_access = RealFileSystem::default();
+
+        let result = file_access
+            .read_remote_file(format!("{address}/file.yml"))
+            .await;
+
+        assert!(matches!(result, Err(FileAccessError::Unauthorised(_))));
+    }
+
+    #[tokio::test]
+    async fn reports_missing_files() {
+        let (address, _) =
+            serve_once("HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+        let file_access = RealFileSystem::default();
+
+        let result = file_access
+            .read_remote_file(format!("{address}/file.yml"))
+            .await;
+
+        assert!(matches!(result, Err(FileAccessError::NotFound(_))));
+    }
+
+    mod test_project_files {
+        use super::*;
+        use crate::test_support::{git, TemporaryDirectory};
+
+        // A committed, a staged and an uncommitted version of the configuration, and an include
+        // that has only been staged.
+        fn project(name: &str) -> TemporaryDirectory {
+            let project = TemporaryDirectory::new(name);
+            let directory = &project.path;
+            std::fs::create_dir_all(directory.join("ci")).unwrap();
+
+            git(directory, &["init", "--quiet"]);
+            std::fs::write(directory.join(".gitlab-ci.yml"), "committed").unwrap();
+            std::fs::write(directory.join("ci/build.yml"), "build").unwrap();
+            git(directory, &["add", "."]);
+            git(directory, &["commit", "--quiet", "-m", "initial"]);
+            std::fs::write(directory.join(".gitlab-ci.yml"), "staged").unwrap();
+            std::fs::write(directory.join("ci/test.yml"), "test").unwrap();
+            git(directory, &["add", "."]);
+            std::fs::write(directory.join(".gitlab-ci.yml"), "working").unwrap();
+            std::fs::write(directory.join("ci/deploy.yml"), "deploy").unwrap();
+
+            project
+        }
+
+        fn read(files: &impl FileAccess, path: PathBuf) -> String {
+            String::from_utf8(files.read_local_file(path).unwrap().into_inner()).unwrap()
+        }
+
+        #[test]
+        fn reads_files_of_the_project_as_committed_staged_or_as_they_are() {
+            let project = project("read");
+            let directory = &project.path;
+            let file_access = RealFileSystem::default();
+            let committed = ProjectFiles::new(&file_access, directory, Some("HEAD".into()));
+            let staged = ProjectFiles::new(&file_access, directory, Some("".into()));
+
+            let working = ProjectFiles::new(&file_access, directory, None);
+
+            let contents = (
+                read(&committed, directory.join(".gitlab-ci.yml")),
+                read(&staged, directory.join(".gitlab-ci.yml")),
+                read(&working, directory.join(".gitlab-ci.yml")),
+            );
+            let missing = committed.read_local_file(directory.join("ci/test.yml"));
+
+            assert_eq!(
+                contents,
+                ("committed".into(), "staged".into(), "working".into())
+            );
+            assert!(matches!(missing, Err(FileAccessError::NotFound(_))));
+        }
+
+        #[test]
+        fn reads_files_outside_of_the_project_as_they_are() {
+            let project = project("outside");
+            let directory = &project.path;
+            let outside = directory.with_extension("yml");
+            std::fs::write(&outside, "outside").unwrap();
+            let file_access = RealFileSystem::default();
+            let committed = ProjectFiles::new(&file_access, directory, Some("HEAD".into()));
+
+            let content = read(&committed, outside.clone());
+            std::fs::remove_file(&outside).unwrap();
+
+            assert_eq!(content, "outside");
+        }
+
+        #[test]
+        fn lists_files_of_the_project_as_committed_or_staged() {
+            let project = project("list");
+            let directory = &project.path;
+            let file_access = RealFileSystem::default();
+            let committed = ProjectFiles::new(&file_access, directory, Some("HEAD".into()));
+            let staged = ProjectFiles::new(&file_access, directory, Some("".into()));
+
+            let listed = (
+                committed.list_local_files(directory.join("ci")).unwrap(),
+                staged.list_local_files(directory.join("ci")).unwrap(),
+                committed.list_local_files(directory).unwrap(),
+            );
+
+            assert_eq!(listed.0, vec!["build.yml"]);
+            assert_eq!(listed.1, vec!["build.yml", "test.yml"]);
+            assert_eq!(listed.2, vec![".gitlab-ci.yml", "ci/build.yml"]);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use clap::ValueEnum;
 use duct::cmd;
 use thiserror::Error;
 
@@ -7,6 +8,10 @@ pub enum GitError {
     Branch(#[source] Box<dyn std::error::Error + Send + Sync>),
     #[error("unable to get latest SHA {0}")]
     Sha(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("unable to get repository root {0}")]
+    Root(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("unknown revision `{0}`")]
+    Revision(String),
 }
 
 impl GitError {
@@ -17,31 +22,169 @@ impl GitError {
     pub fn sha(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
         GitError::Sha(error.into())
     }
+
+    pub fn root(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        GitError::Root(error.into())
+    }
+}
+
+// Which changes of the project a job gets on top of the commit it runs for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SourceMode {
+    /// Only what has been committed, like what's going to be pushed.
+    Committed,
+    /// Committed and staged changes.
+    Staged,
+    /// Everything in the working tree, including untracked files that aren't ignored.
+    #[default]
+    Working,
+}
+
+impl SourceMode {
+    // What files of the project, like the CI configuration, are read from with `git show`: the
+    // commit, or the index as an empty revision. None for the working tree. Uncommitted changes
+    // belong to `HEAD`, so other revisions are read as committed.
+    pub fn revision_of_files(self, commit: &str, other_revision: bool) -> Option<String> {
+        match self {
+            SourceMode::Working if !other_revision => None,
+            SourceMode::Staged if !other_revision => Some(String::new()),
+            _ => Some(commit.into()),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct GitDetails {
     pub branch_name: String,
+    // Set when running for a tag, which pipelines have no branch for.
+    pub tag: Option<String>,
     pub sha: String,
     pub short_sha: String,
+    pub root: String,
+}
+
+impl GitDetails {
+    // What `CI_COMMIT_REF_NAME` is: the tag or the branch.
+    pub fn ref_name(&self) -> &str {
+        self.tag.as_deref().unwrap_or(&self.branch_name)
+    }
 }
 
-pub fn read_details() -> Result<GitDetails, GitError> {
-    let sha = cmd!("git", "rev-parse", "HEAD")
+// Details of the given revision, of `HEAD` if there's none.
+pub fn read_details(revision: Option<&str>) -> Result<GitDetails, GitError> {
+    let current_branch = cmd!("git", "rev-parse", "--abbrev-ref", "HEAD")
+        .read()
+        .map_err(GitError::branch)?;
+
+    let Some(revision) = revision else {
+        return read_commit("HEAD", current_branch, None);
+    };
+
+    let commit = format!("{}^{{commit}}", revision);
+    cmd!("git", "rev-parse", "--quiet", "--verify", &commit)
+        .stdout_null()
+        .run()
+        .map_err(|_| GitError::Revision(revision.into()))?;
+
+    let full_name = cmd!("git", "rev-parse", "--symbolic-full-name", revision)
+        .read()
+        .unwrap_or_default();
+    let (branch_name, tag) = ref_of(&full_name, current_branch);
+
+    read_commit(&commit, branch_name, tag)
+}
+
+fn read_commit(
+    commit: &str,
+    branch_name: String,
+    tag: Option<String>,
+) -> Result<GitDetails, GitError> {
+    let sha = cmd!("git", "rev-parse", commit)
         .read()
         .map_err(GitError::sha)?;
 
-    let short_sha = cmd!("git", "rev-parse", "--short", "HEAD")
+    let short_sha = cmd!("git", "rev-parse", "--short", commit)
         .read()
         .map_err(GitError::sha)?;
 
-    let branch_name = cmd!("git", "rev-parse", "--abbrev-ref", "HEAD")
+    let root = cmd!("git", "rev-parse", "--show-toplevel")
         .read()
-        .map_err(GitError::branch)?;
+        .map_err(GitError::root)?;
 
     Ok(GitDetails {
         branch_name,
+        tag,
         sha,
         short_sha,
+        root,
     })
 }
+
+// Branch and tag a revision stands for, by its full name. Plain commits are taken as being on the
+// current branch.
+fn ref_of(full_name: &str, current_branch: String) -> (String, Option<String>) {
+    if let Some(tag) = full_name.strip_prefix("refs/tags/") {
+        return (String::new(), Some(tag.into()));
+    }
+    if let Some(branch) = full_name.strip_prefix("refs/heads/") {
+        return (branch.into(), None);
+    }
+    // `refs/remotes/origin/main` is `main`.
+    if let Some((_, branch)) = full_name
+        .strip_prefix("refs/remotes/")
+        .and_then(|name| name.split_once('/'))
+    {
+        return (branch.into(), None);
+    }
+
+    (current_branch, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_branches_and_tags_from_full_names() {
+        let current = || "main".to_string();
+
+        assert_eq!(
+            ref_of("refs/heads/feature/x", current()),
+            ("feature/x".into(), None)
+        );
+        assert_eq!(
+            ref_of("refs/remotes/origin/fix", current()),
+            ("fix".into(), None)
+        );
+        assert_eq!(
+            ref_of("refs/tags/v1.0", current()),
+            ("".into(), Some("v1.0".into()))
+        );
+    }
+
+    #[test]
+    fn reads_files_from_where_the_source_mode_takes_them() {
+        assert_eq!(SourceMode::Working.revision_of_files("abc", false), None);
+        assert_eq!(
+            SourceMode::Staged.revision_of_files("abc", false),
+            Some("".into())
+        );
+        assert_eq!(
+            SourceMode::Committed.revision_of_files("abc", false),
+            Some("abc".into())
+        );
+        assert_eq!(
+            SourceMode::Working.revision_of_files("abc", true),
+            Some("abc".into())
+        );
+        assert_eq!(
+            SourceMode::Staged.revision_of_files("abc", true),
+            Some("abc".into())
+        );
+    }
+
+    #[test]
+    fn takes_plain_commits_as_being_on_the_current_branch() {
+        assert_eq!(ref_of("", "main".into()), ("main".into(), None));
+    }
+}
@@ -1,11 +1,11 @@
 use crate::gitlab::deserialise::{
-    hashmap_of_jobs, hashmap_of_templates, list_of_string_tuples_to_map,
-    map_to_list_of_string_tuples, seq_string_or_struct, str_or_map_to_list_of_maps,
-    string_or_seq_string,
+    list_of_string_tuples_to_map, map_of_jobs, map_of_templates, map_to_list_of_string_tuples,
+    seq_string_or_struct, str_or_map_to_list_of_maps, string_or_seq_string,
 };
+use crate::gitlab::source::Source;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
-use std::collections::HashMap;
 use std::str::FromStr;
 
 #[rustfmt::skip]
@@ -52,13 +52,18 @@ pub struct GitLabConfiguration {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub workflow: Option<Value>,
 
-    #[serde(deserialize_with = "hashmap_of_jobs")]
+    #[serde(deserialize_with = "map_of_jobs")]
     #[serde(flatten)]
-    pub jobs: HashMap<String, Job>,
+    pub jobs: IndexMap<String, Job>,
 
-    #[serde(deserialize_with = "hashmap_of_templates")]
+    #[serde(deserialize_with = "map_of_templates")]
     #[serde(flatten)]
-    pub templates: HashMap<String, Job>,
+    pub templates: IndexMap<String, Job>,
+
+    // The configuration as written, merged with its includes, for validating it against GitLab's
+    // schema. Unknown keys and values of the wrong type have been dropped from everything above.
+    #[serde(skip)]
+    pub raw: Value,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
@@ -71,6 +76,10 @@ pub struct GlobalDefaults {
     pub before_script: Option<ListOfStrings>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
+
+    // All other keywords, whether GitLab knows them or not.
+    #[serde(flatten, skip_serializing_if = "IndexMap::is_empty")]
+    pub other_keywords: IndexMap<String, Value>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
@@ -82,24 +91,19 @@ pub struct Artifacts {
     pub name: String,
     #[serde(default = "default_when")]
     pub when: When,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub paths: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum When {
+    #[default]
     OnSuccess,
     OnFailure,
     Always,
 }
 
-impl Default for When {
-    fn default() -> Self {
-        Self::OnSuccess
-    }
-}
-
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[serde(untagged)]
 pub enum Include {
@@ -159,6 +163,8 @@ pub struct Job {
     pub needs: Option<OneOrMoreNeeds>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub script: Option<ListOfStrings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
     #[serde(
         default,
         deserialize_with = "map_to_list_of_string_tuples",
@@ -166,13 +172,24 @@ pub struct Job {
         skip_serializing_if = "Vec::is_empty"
     )]
     pub variables: Vec<(String, String)>,
+    // Kept as plain string, so that invalid values can be reported by `lint`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+
+    // Where the job has been defined, if it was read from a file.
+    #[serde(skip)]
+    pub source: Option<Source>,
+
+    // All other keywords, whether GitLab knows them or not.
+    #[serde(flatten, skip_serializing_if = "IndexMap::is_empty")]
+    pub other_keywords: IndexMap<String, Value>,
 }
 
 // Wrapping was necessary to get the custom deserializer work with an `Option`
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct OneOrMoreNeeds(#[serde(deserialize_with = "seq_string_or_struct")] pub Vec<Needs>);
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default, Clone)]
 pub struct Needs {
     pub job: String,
     #[serde(default = "default_true")]
@@ -612,6 +629,71 @@ mod tests {
             );
         }
 
+        #[test]
+        fn deserialises_long_form_of_variables() {
+            let yaml = "
+                variables:
+                  EXPANDED:
+                    value: $OTHER
+                  LITERAL:
+                    value: $OTHER
+                    expand: false
+            ";
+            let config = serde_yaml::from_str::<GitLabConfiguration>(yaml).unwrap();
+
+            assert_eq!(
+                config.variables,
+                vec![
+                    ("EXPANDED".into(), "$OTHER".into()),
+                    ("LITERAL".into(), "$$OTHER".into())
+                ]
+            );
+        }
+
+        #[test]
+        fn deserialises_variables_with_description_and_options() {
+            let yaml = "
+                variables:
+                  DEPLOY_ENVIRONMENT:
+                    value: staging
+                    description: Where to deploy to
+                    options: [production, staging]
+                  CERTIFICATE:
+                    value: |
+                      -----BEGIN CERTIFICATE-----
+                      \"$(quoted)\"
+                      -----END CERTIFICATE-----
+            ";
+            let config = serde_yaml::from_str::<GitLabConfiguration>(yaml).unwrap();
+
+            assert_eq!(
+                config.variables,
+                vec![
+                    ("DEPLOY_ENVIRONMENT".into(), "staging".into()),
+                    (
+                        "CERTIFICATE".into(),
+                        "-----BEGIN CERTIFICATE-----\n\"$(quoted)\"\n-----END CERTIFICATE-----\n"
+                            .into()
+                    )
+                ]
+            );
+        }
+
+        #[test]
+        fn fails_on_values_not_being_one_of_the_options() {
+            let yaml = "
+                variables:
+                  DEPLOY_ENVIRONMENT:
+                    value: testing
+                    options: [production, staging]
+            ";
+            let error = serde_yaml::from_str::<GitLabConfiguration>(yaml).unwrap_err();
+
+            assert!(error
+                .to_string()
+                .contains("value of variable `DEPLOY_ENVIRONMENT` must be one of its options"));
+        }
+
         #[test]
         fn deserialises_variables_in_stable_key_order() {
             let yaml = "
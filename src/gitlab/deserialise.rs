@@ -1,8 +1,9 @@
 use crate::gitlab::configuration::{Include, Job};
+use crate::gitlab::expansion::escape;
+use indexmap::IndexMap;
 use serde::de::{DeserializeSeed, Error, MapAccess, Visitor};
 use serde::{de, Deserialize, Deserializer, Serializer};
 use serde_yaml::Value;
-use std::collections::HashMap;
 use std::fmt;
 use std::marker::PhantomData;
 use std::str::FromStr;
@@ -126,6 +127,16 @@ where
     deserializer.deserialize_seq(SeqStringOrStruct(PhantomData))
 }
 
+fn primitive_to_string(value: &Value) -> Result<String, &'static str> {
+    match value {
+        Value::Null => Ok("null".into()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => Ok(s.clone()),
+        _ => Err("Can only put primitive types into list"),
+    }
+}
+
 pub fn map_to_list_of_string_tuples<'de, D>(
     deserializer: D,
 ) -> Result<Vec<(String, String)>, D::Error>
@@ -149,11 +160,42 @@ where
 
             while let Some((key, value)) = access.next_entry::<String, Value>()? {
                 let value = match value {
-                    Value::Null => "null".into(),
-                    Value::Bool(b) => b.to_string(),
-                    Value::Number(n) => n.to_string(),
-                    Value::String(s) => s,
-                    _ => return Err(A::Error::custom("Can only put primitive types into list")),
+                    // The long form: `NAME: { value: ..., description: ..., options: [...], expand: false }`
+                    // The description is only shown when running pipelines manually in GitLab.
+                    Value::Mapping(mapping) => {
+                        let options = match mapping.get("options") {
+                            Some(Value::Sequence(options)) => options
+                                .iter()
+                                .map(primitive_to_string)
+                                .collect::<Result<Vec<_>, _>>()
+                                .map_err(A::Error::custom)?,
+                            Some(_) => {
+                                return Err(A::Error::custom(format!(
+                                    "options of variable `{}` must be a list",
+                                    key
+                                )))
+                            }
+                            None => vec![],
+                        };
+                        let value = mapping
+                            .get("value")
+                            .map(primitive_to_string)
+                            .unwrap_or_else(|| Ok(String::new()))
+                            .map_err(A::Error::custom)?;
+
+                        if !options.is_empty() && !options.contains(&value) {
+                            return Err(A::Error::custom(format!(
+                                "value of variable `{}` must be one of its options",
+                                key
+                            )));
+                        }
+
+                        match mapping.get("expand") {
+                            Some(Value::Bool(false)) => escape(&value),
+                            _ => value,
+                        }
+                    }
+                    value => primitive_to_string(&value).map_err(A::Error::custom)?,
                 };
 
                 values.push((key, value));
@@ -208,14 +250,14 @@ where
 }
 
 #[macro_export]
-macro_rules! deserialize_job_hashmap_conditionally {
+macro_rules! deserialize_job_map_conditionally {
     ($function_name:ident, $partition_predicate:expr) => {
-        pub fn $function_name<'de, D>(deserializer: D) -> Result<HashMap<String, Job>, D::Error>
+        pub fn $function_name<'de, D>(deserializer: D) -> Result<IndexMap<String, Job>, D::Error>
         where
             D: Deserializer<'de>,
         {
             struct MyMapVisitor {
-                marker: PhantomData<fn() -> HashMap<String, Job>>,
+                marker: PhantomData<fn() -> IndexMap<String, Job>>,
             }
 
             impl MyMapVisitor {
@@ -227,7 +269,7 @@ macro_rules! deserialize_job_hashmap_conditionally {
             }
 
             impl<'de> Visitor<'de> for MyMapVisitor {
-                type Value = HashMap<String, Job>;
+                type Value = IndexMap<String, Job>;
 
                 fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                     formatter.write_str("a map")
@@ -237,7 +279,7 @@ macro_rules! deserialize_job_hashmap_conditionally {
                 where
                     M: MapAccess<'de>,
                 {
-                    let mut map = HashMap::with_capacity(access.size_hint().unwrap_or(0));
+                    let mut map = IndexMap::with_capacity(access.size_hint().unwrap_or(0));
 
                     while let Some((key, value)) = access.next_entry::<String, Job>()? {
                         if $partition_predicate(&key) {
@@ -254,8 +296,8 @@ macro_rules! deserialize_job_hashmap_conditionally {
     };
 }
 
-deserialize_job_hashmap_conditionally!(hashmap_of_templates, |key: &String| key.starts_with('.'));
-deserialize_job_hashmap_conditionally!(hashmap_of_jobs, |key: &String| !key.starts_with('.'));
+deserialize_job_map_conditionally!(map_of_templates, |key: &String| key.starts_with('.'));
+deserialize_job_map_conditionally!(map_of_jobs, |key: &String| !key.starts_with('.'));
 
 pub fn list_of_string_tuples_to_map<S>(
     list: &Vec<(String, String)>,
@@ -264,7 +306,8 @@ pub fn list_of_string_tuples_to_map<S>(
 where
     S: Serializer,
 {
-    let mut map = HashMap::new();
+    // Later definitions of the same variable win, but keep the position of the first one.
+    let mut map = IndexMap::new();
 
     for (key, value) in list {
         map.insert(key, value);
@@ -1,24 +1,71 @@
 use crate::file::FileAccessError;
+use crate::gitlab::configuration::Job;
+use crate::gitlab::source::{describe, Source};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum GitLabError {
-    #[error(transparent)]
-    Parse(#[from] Box<dyn std::error::Error + Send + Sync>),
+    #[error("syntax error in {file}: {error}")]
+    Syntax {
+        file: String,
+        // The line the error has been found on.
+        line_text: Option<String>,
+        #[source]
+        error: serde_yaml::Error,
+    },
     #[error("cannot adjust URL")]
     AdjustUrl(),
     #[error("cannot create URL {0}")]
     CreateUrl(#[source] Box<dyn std::error::Error + Send + Sync>),
     #[error("template '{0}' not found")]
     TemplateNotFound(String),
+    #[error("needs unknown job '{0}'")]
+    NeededJobNotFound(String),
+    #[error("{}: {error}", describe(.name, .source.as_deref()))]
+    Job {
+        name: String,
+        source: Option<Box<Source>>,
+        #[source]
+        error: Box<GitLabError>,
+    },
+    #[error("wildcard include '{0}' is only supported for files of the local repository")]
+    UnsupportedWildcard(String),
+    #[error("include cycle detected: {0}")]
+    IncludeCycle(String),
+    #[error("maximum of {0} includes reached")]
+    TooManyIncludes(usize),
+    #[error("circular variable reference: {0}")]
+    VariableCycle(String),
+    #[error("invalid rule `{0}`: {1}")]
+    InvalidRule(String, String),
+    #[error("cannot validate against CI schema: {0}")]
+    Schema(String),
     #[error(transparent)]
     File(#[from] FileAccessError),
 }
 
 impl GitLabError {
-    pub fn parse(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
-        GitLabError::Parse(error.into())
+    pub fn syntax(file: &str, content: &str, error: serde_yaml::Error) -> Self {
+        let line_text = error
+            .location()
+            .and_then(|location| content.lines().nth(location.line().checked_sub(1)?))
+            .map(String::from);
+
+        GitLabError::Syntax {
+            file: file.into(),
+            line_text,
+            error,
+        }
+    }
+
+    pub fn job(name: &str, job: &Job, error: GitLabError) -> Self {
+        GitLabError::Job {
+            name: name.into(),
+            source: job.source.clone().map(Box::new),
+            error: Box::new(error),
+        }
     }
+
     pub fn adjust_url(_: ()) -> Self {
         GitLabError::AdjustUrl()
     }
@@ -27,6 +74,10 @@ impl GitLabError {
         GitLabError::CreateUrl(error.into())
     }
 
+    pub fn schema(error: impl std::fmt::Display) -> Self {
+        GitLabError::Schema(error.to_string())
+    }
+
     pub fn file(error: FileAccessError) -> Self {
         GitLabError::File(error)
     }
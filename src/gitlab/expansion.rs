@@ -0,0 +1,304 @@
+use crate::gitlab::error::GitLabError;
+use indexmap::IndexMap;
+
+// Expands `$VAR` and `${VAR}` in a value the way GitLab does for keywords like `image`.
+// `$$` is a literal `$`, unknown variables expand to an empty string.
+// Expects variables that have been resolved already, later definitions win.
+pub fn expand(value: &str, variables: &[(String, String)]) -> String {
+    let lookup = |name: &str| -> Result<Option<String>, GitLabError> {
+        Ok(Some(find(variables, name).unwrap_or_default()))
+    };
+
+    expand_with(value, Escapes::Unescape, lookup).unwrap_or_default()
+}
+
+fn find(variables: &[(String, String)], name: &str) -> Option<String> {
+    variables
+        .iter()
+        .rev()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.clone())
+}
+
+// Resolves references between variables, e.g. `URL: https://$HOST/api`, regardless of the order
+// they have been defined in. Values are taken literally afterwards, so `$$` becomes `$`.
+// Later definitions of a variable win, just like in GitLab.
+pub fn resolve_variables(
+    variables: &[(String, String)],
+) -> Result<Vec<(String, String)>, GitLabError> {
+    let mut definitions = IndexMap::new();
+
+    for (name, value) in variables {
+        definitions.insert(name.as_str(), value.as_str());
+    }
+
+    let mut resolution = Resolution {
+        definitions: &definitions,
+        resolved: IndexMap::new(),
+        visiting: vec![],
+    };
+
+    for name in definitions.keys() {
+        resolution.resolve(name)?;
+    }
+
+    Ok(definitions
+        .keys()
+        .map(|name| (name.to_string(), resolution.resolved[*name].clone()))
+        .collect())
+}
+
+struct Resolution<'a> {
+    definitions: &'a IndexMap<&'a str, &'a str>,
+    resolved: IndexMap<String, String>,
+    // Variables currently being resolved, to detect references in cycles.
+    visiting: Vec<String>,
+}
+
+impl Resolution<'_> {
+    fn resolve(&mut self, name: &str) -> Result<String, GitLabError> {
+        if let Some(value) = self.resolved.get(name) {
+            return Ok(value.clone());
+        }
+        let Some(definition) = self.definitions.get(name).copied() else {
+            return Ok(String::new());
+        };
+        if self.visiting.iter().any(|visiting| visiting == name) {
+            let mut cycle = self.visiting.clone();
+            cycle.push(name.to_string());
+
+            return Err(GitLabError::VariableCycle(cycle.join(" -> ")));
+        }
+
+        self.visiting.push(name.to_string());
+        let value = expand_with(definition, Escapes::Unescape, |reference| {
+            self.resolve(reference).map(Some)
+        })?;
+        self.visiting.pop();
+        self.resolved.insert(name.to_string(), value.clone());
+
+        Ok(value)
+    }
+}
+
+// What becomes of `$$`: a `$` once expanded, or kept for a shell to handle.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escapes {
+    Unescape,
+    Keep,
+}
+
+// References the lookup has no value for are kept as written.
+fn expand_with(
+    value: &str,
+    escapes: Escapes,
+    mut lookup: impl FnMut(&str) -> Result<Option<String>, GitLabError>,
+) -> Result<String, GitLabError> {
+    let mut result = String::new();
+    let mut characters = value.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        if character != '$' {
+            result.push(character);
+            continue;
+        }
+
+        match characters.peek() {
+            Some('$') => {
+                characters.next();
+                result.push('$');
+                if escapes == Escapes::Keep {
+                    result.push('$');
+                }
+            }
+            Some('{') => {
+                let rest = characters.clone().skip(1).collect::<String>();
+
+                match rest.split_once('}') {
+                    Some((name, _)) if is_name(name) => {
+                        match lookup(name)? {
+                            Some(value) => result.push_str(&value),
+                            None => result.push_str(&format!("${{{}}}", name)),
+                        }
+                        // `{`, the name and `}`
+                        for _ in 0..name.chars().count() + 2 {
+                            characters.next();
+                        }
+                    }
+                    _ => result.push('$'),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+
+                while let Some(c) = characters.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+
+                match lookup(&name)? {
+                    Some(value) => result.push_str(&value),
+                    None => result.push_str(&format!("${}", name)),
+                }
+            }
+            _ => result.push('$'),
+        }
+    }
+
+    Ok(result)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Shows a line of a script the way the shell is going to see it, with the job's variables filled
+// in. Unlike `expand()`, everything else stays as written: the shell handles `$$` and variables
+// that are set by the script itself.
+pub fn interpolate(line: &str, variables: &[(String, String)]) -> String {
+    let lookup = |name: &str| -> Result<Option<String>, GitLabError> { Ok(find(variables, name)) };
+
+    expand_with(line, Escapes::Keep, lookup).unwrap_or_else(|_| line.to_string())
+}
+
+// Makes a value be taken literally, for `expand: false` and raw variables.
+pub fn escape(value: &str) -> String {
+    value.replace('$', "$$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::variables;
+
+    mod test_interpolate {
+        use super::*;
+
+        #[test]
+        fn fills_in_known_variables_only() {
+            let variables = variables(&[("NAME", "value")]);
+
+            assert_eq!(
+                interpolate("echo $NAME ${NAME} $OTHER ${OTHER}", &variables),
+                "echo value value $OTHER ${OTHER}"
+            );
+        }
+
+        #[test]
+        fn leaves_dollar_signs_to_the_shell() {
+            let variables = variables(&[("NAME", "value")]);
+
+            assert_eq!(
+                interpolate("kill $$; echo $$NAME 5$", &variables),
+                "kill $$; echo $$NAME 5$"
+            );
+        }
+    }
+
+    mod test_expand {
+        use super::*;
+
+        #[test]
+        fn returns_same_value_when_nothing_is_expanded() {
+            assert_eq!(expand("the-value", &[]), "the-value");
+        }
+
+        #[test]
+        fn expands_both_forms_of_references() {
+            let variables = variables(&[("NAME", "value")]);
+
+            assert_eq!(
+                expand("$NAME-${NAME}_suffix $NAME_suffix", &variables),
+                "value-value_suffix "
+            );
+        }
+
+        #[test]
+        fn expands_unknown_variables_to_nothing() {
+            assert_eq!(expand("image:$UNKNOWN", &[]), "image:");
+        }
+
+        #[test]
+        fn keeps_escaped_and_lone_dollar_signs() {
+            assert_eq!(expand("$$NAME costs 5$ ${", &[]), "$NAME costs 5$ ${");
+        }
+
+        #[test]
+        fn does_not_run_any_shell_code() {
+            assert_eq!(
+                expand("\"$(touch /tmp/x)\" `id`", &[]),
+                "\"$(touch /tmp/x)\" `id`"
+            );
+        }
+
+        #[test]
+        fn uses_the_last_definition() {
+            let variables = variables(&[("NAME", "first"), ("NAME", "second")]);
+
+            assert_eq!(expand("$NAME", &variables), "second");
+        }
+    }
+
+    mod test_resolve {
+        use super::*;
+
+        #[test]
+        fn resolves_nested_references_regardless_of_order() {
+            let resolved = resolve_variables(&variables(&[
+                ("URL", "https://$HOST/api"),
+                ("HOST", "${DOMAIN}:8080"),
+                ("DOMAIN", "example.com"),
+            ]))
+            .unwrap();
+
+            assert_eq!(
+                resolved,
+                variables(&[
+                    ("URL", "https://example.com:8080/api"),
+                    ("HOST", "example.com:8080"),
+                    ("DOMAIN", "example.com"),
+                ])
+            );
+        }
+
+        #[test]
+        fn later_definitions_win() {
+            let resolved = resolve_variables(&variables(&[
+                ("NAME", "global"),
+                ("GREETING", "hello $NAME"),
+                ("NAME", "job"),
+            ]))
+            .unwrap();
+
+            assert_eq!(
+                resolved,
+                variables(&[("NAME", "job"), ("GREETING", "hello job")])
+            );
+        }
+
+        #[test]
+        fn takes_escaped_values_literally() {
+            let resolved = resolve_variables(&variables(&[
+                ("RAW", &escape("pa$$word $HOME")),
+                ("OTHER", "$RAW"),
+            ]))
+            .unwrap();
+
+            assert_eq!(
+                resolved,
+                variables(&[("RAW", "pa$$word $HOME"), ("OTHER", "pa$$word $HOME")])
+            );
+        }
+
+        #[test]
+        fn detects_cycles() {
+            let result = resolve_variables(&variables(&[("A", "$B"), ("B", "x${A}")]));
+
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "circular variable reference: A -> B -> A"
+            );
+        }
+    }
+}
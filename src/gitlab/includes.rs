@@ -0,0 +1,115 @@
+use crate::gitlab::error::GitLabError;
+use std::collections::HashSet;
+
+// https://docs.gitlab.com/ee/ci/yaml/includes.html#include-limits
+pub const MAXIMUM_NUMBER_OF_INCLUDES: usize = 150;
+
+// Keeps track of every file included so far, identified by its canonical location, as well as
+// the chain of files that lead to the one currently being processed.
+//
+// The same file can be included multiple times, but GitLab only uses it once and ignores
+// the duplicates. A file including itself, directly or through other files, is an error.
+#[derive(Default)]
+pub struct IncludeTracker {
+    chain: Vec<String>,
+    included: HashSet<String>,
+}
+
+pub enum Visit {
+    New,
+    Duplicate,
+}
+
+impl IncludeTracker {
+    pub fn visit(&mut self, location: &str) -> Result<Visit, GitLabError> {
+        if self.chain.iter().any(|ancestor| ancestor == location) {
+            let mut cycle = self.chain.clone();
+            cycle.push(location.to_string());
+
+            return Err(GitLabError::IncludeCycle(cycle.join(" -> ")));
+        }
+
+        if self.included.contains(location) {
+            return Ok(Visit::Duplicate);
+        }
+
+        if self.included.len() >= MAXIMUM_NUMBER_OF_INCLUDES {
+            return Err(GitLabError::TooManyIncludes(MAXIMUM_NUMBER_OF_INCLUDES));
+        }
+
+        self.included.insert(location.to_string());
+
+        Ok(Visit::New)
+    }
+
+    pub fn enter(&mut self, location: &str) {
+        self.chain.push(location.to_string());
+    }
+
+    pub fn leave(&mut self) {
+        self.chain.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_new_locations() {
+        let mut tracker = IncludeTracker::default();
+
+        assert!(matches!(tracker.visit("a.yml").unwrap(), Visit::New));
+        assert!(matches!(tracker.visit("b.yml").unwrap(), Visit::New));
+    }
+
+    #[test]
+    fn identifies_duplicates() {
+        let mut tracker = IncludeTracker::default();
+
+        tracker.visit("a.yml").unwrap();
+
+        assert!(matches!(tracker.visit("a.yml").unwrap(), Visit::Duplicate));
+    }
+
+    #[test]
+    fn reports_cycles_with_the_full_chain() {
+        let mut tracker = IncludeTracker::default();
+
+        tracker.visit("a.yml").unwrap();
+        tracker.enter("a.yml");
+        tracker.visit("b.yml").unwrap();
+        tracker.enter("b.yml");
+
+        let error = tracker.visit("a.yml").err().unwrap();
+
+        assert!(
+            matches!(error, GitLabError::IncludeCycle(chain) if chain == "a.yml -> b.yml -> a.yml")
+        );
+    }
+
+    #[test]
+    fn does_not_report_cycles_for_files_that_are_not_in_the_chain_anymore() {
+        let mut tracker = IncludeTracker::default();
+
+        tracker.visit("a.yml").unwrap();
+        tracker.enter("a.yml");
+        tracker.leave();
+
+        assert!(matches!(tracker.visit("a.yml").unwrap(), Visit::Duplicate));
+    }
+
+    #[test]
+    fn enforces_the_include_limit() {
+        let mut tracker = IncludeTracker::default();
+
+        for index in 0..MAXIMUM_NUMBER_OF_INCLUDES {
+            tracker.visit(&format!("{index}.yml")).unwrap();
+        }
+
+        assert!(matches!(
+            tracker.visit("one-too-many.yml"),
+            Err(GitLabError::TooManyIncludes(150))
+        ));
+    }
+}
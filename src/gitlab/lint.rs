@@ -0,0 +1,526 @@
+use crate::gitlab::configuration::{GitLabConfiguration, Job, OneOrMoreNeeds};
+use crate::gitlab::merge::templates_by_precedence;
+use crate::gitlab::source::{describe, Source};
+use std::collections::HashMap;
+use std::fmt;
+
+// https://docs.gitlab.com/ee/ci/yaml/#job-keywords
+const JOB_KEYWORDS: &[&str] = &[
+    "after_script",
+    "allow_failure",
+    "artifacts",
+    "before_script",
+    "cache",
+    "coverage",
+    "dast_configuration",
+    "dependencies",
+    "environment",
+    "except",
+    "extends",
+    "hooks",
+    "id_tokens",
+    "identity",
+    "image",
+    "inherit",
+    "interruptible",
+    "manual_confirmation",
+    "needs",
+    "only",
+    "pages",
+    "parallel",
+    "release",
+    "resource_group",
+    "retry",
+    "rules",
+    "run",
+    "script",
+    "secrets",
+    "services",
+    "stage",
+    "start_in",
+    "tags",
+    "timeout",
+    "trigger",
+    "variables",
+    "when",
+];
+
+// https://docs.gitlab.com/ee/ci/jobs/#job-name-limitations
+const RESERVED_JOB_NAMES: &[&str] = &[
+    "image",
+    "services",
+    "stages",
+    "types",
+    "before_script",
+    "after_script",
+    "variables",
+    "cache",
+    "include",
+    "true",
+    "false",
+    "nil",
+];
+
+// https://docs.gitlab.com/ee/ci/yaml/#when
+const WHEN_VALUES: &[&str] = &[
+    "on_success",
+    "on_failure",
+    "never",
+    "always",
+    "manual",
+    "delayed",
+];
+
+// https://docs.gitlab.com/ee/ci/yaml/#stages
+const DEFAULT_STAGES: &[&str] = &["build", "test", "deploy"];
+pub const DEFAULT_STAGE: &str = "test";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    // The job or template with the problem. Problems of global keywords don't have one.
+    pub name: Option<String>,
+    pub source: Option<Source>,
+    // The keyword of the job the problem is about, if it's not about the job as a whole.
+    pub keyword: Option<String>,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(
+                f,
+                "{}: {}",
+                describe(name, self.source.as_ref()),
+                self.message
+            ),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// Collects the problems of a single job or template.
+struct Report<'a> {
+    name: &'a str,
+    job: &'a Job,
+    problems: &'a mut Vec<Problem>,
+}
+
+impl Report<'_> {
+    fn problem(&mut self, keyword: Option<&str>, message: String, hint: Option<String>) {
+        self.problems.push(Problem {
+            name: Some(self.name.to_string()),
+            source: self.job.source.clone(),
+            keyword: keyword.map(String::from),
+            message,
+            hint,
+        });
+    }
+}
+
+// Validates a configuration the way GitLab would before creating a pipeline.
+// Expects jobs as they have been written, i.e. before templates got merged into them.
+// All problems are collected instead of stopping at the first one.
+pub fn lint(configuration: &GitLabConfiguration) -> Vec<Problem> {
+    let stages = available_stages(configuration);
+    let mut problems = vec![];
+    let mut job_stages = HashMap::new();
+
+    for (name, job) in &configuration.jobs {
+        let templates = templates_of(job, configuration);
+        let stage = effective(job, &templates, |j| j.stage.as_ref())
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_STAGE.to_string());
+
+        job_stages.insert(name.as_str(), stage);
+    }
+
+    for (name, job) in &configuration.jobs {
+        let mut report = Report {
+            name,
+            job,
+            problems: &mut problems,
+        };
+
+        if RESERVED_JOB_NAMES.contains(&name.as_str()) {
+            report.problem(
+                None,
+                format!(
+                    "`{}` is a reserved keyword and cannot be used as job name",
+                    name
+                ),
+                Some("rename the job".into()),
+            );
+        }
+
+        check_keywords(&mut report);
+
+        let templates = match templates_by_precedence(job, &configuration.templates) {
+            Ok(templates) => templates
+                .into_iter()
+                .map(|(_, template)| template)
+                .collect(),
+            Err(e) => {
+                report.problem(Some("extends"), e.to_string(), None);
+                vec![]
+            }
+        };
+
+        let has_script = effective(job, &templates, |j| j.script.as_ref()).is_some();
+        let is_trigger = effective(job, &templates, |j| j.other_keywords.get("trigger")).is_some();
+
+        if !has_script && !is_trigger {
+            report.problem(
+                None,
+                "missing `script`".into(),
+                Some("add `script` to the job or extend a template that has one".into()),
+            );
+        }
+
+        let stage = &job_stages[name.as_str()];
+        let stage_index = stages.iter().position(|s| s == stage);
+
+        if stage_index.is_none() {
+            report.problem(
+                Some("stage"),
+                format!(
+                    "stage `{}` is not defined in `stages` ({})",
+                    stage,
+                    stages.join(", ")
+                ),
+                Some(format!("add `{}` to `stages`", stage)),
+            );
+        }
+
+        if let Some(when) = effective(job, &templates, |j| j.when.as_ref()) {
+            check_when(when, &mut report);
+        }
+
+        if let Some(OneOrMoreNeeds(needs)) = effective(job, &templates, |j| j.needs.as_ref()) {
+            for need in needs {
+                let Some(needed_stage) = job_stages.get(need.job.as_str()) else {
+                    report.problem(
+                        Some("needs"),
+                        format!("needs unknown job `{}`", need.job),
+                        None,
+                    );
+                    continue;
+                };
+                let needed_stage_index = stages.iter().position(|s| s == needed_stage);
+
+                if let (Some(own), Some(needed)) = (stage_index, needed_stage_index) {
+                    if needed > own {
+                        report.problem(
+                            Some("needs"),
+                            format!("needs job `{}` of later stage `{}`", need.job, needed_stage),
+                            Some("jobs can only need jobs of the same or earlier stages".into()),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for (name, template) in &configuration.templates {
+        let mut report = Report {
+            name,
+            job: template,
+            problems: &mut problems,
+        };
+
+        check_keywords(&mut report);
+
+        if let Some(when) = &template.when {
+            check_when(when, &mut report);
+        }
+    }
+
+    problems
+}
+
+pub fn available_stages(configuration: &GitLabConfiguration) -> Vec<String> {
+    let stages = if configuration.stages.is_empty() {
+        DEFAULT_STAGES.iter().map(|s| s.to_string()).collect()
+    } else {
+        configuration.stages.clone()
+    };
+    let mut all_stages = vec![".pre".to_string()];
+
+    all_stages.extend(stages.into_iter().filter(|s| s != ".pre" && s != ".post"));
+    all_stages.push(".post".into());
+
+    all_stages
+}
+
+// Templates the job extends, closest one first.
+fn templates_of<'a>(job: &Job, configuration: &'a GitLabConfiguration) -> Vec<&'a Job> {
+    templates_by_precedence(job, &configuration.templates)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(_, template)| template)
+        .collect()
+}
+
+fn effective<'a, T>(
+    job: &'a Job,
+    templates: &[&'a Job],
+    keyword: impl Fn(&'a Job) -> Option<&'a T>,
+) -> Option<&'a T> {
+    keyword(job).or_else(|| templates.iter().find_map(|template| keyword(template)))
+}
+
+fn check_keywords(report: &mut Report) {
+    for keyword in report.job.other_keywords.keys() {
+        if !JOB_KEYWORDS.contains(&keyword.as_str()) {
+            report.problem(
+                Some(keyword),
+                format!("unknown keyword `{}`", keyword),
+                Some("see https://docs.gitlab.com/ee/ci/yaml/#job-keywords".into()),
+            );
+        }
+    }
+}
+
+fn check_when(when: &str, report: &mut Report) {
+    if !WHEN_VALUES.contains(&when) {
+        report.problem(
+            Some("when"),
+            format!("invalid `when` value `{}`", when),
+            Some(format!("use one of: {}", WHEN_VALUES.join(", "))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_yaml(content: &str) -> Vec<String> {
+        let configuration = serde_yaml::from_str::<GitLabConfiguration>(content).unwrap();
+
+        lint(&configuration)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn accepts_valid_configuration() {
+        let problems = lint_yaml(
+            "
+            stages: [build, test]
+            .base:
+              script: echo
+            build:
+              stage: build
+              script: make
+            test:
+              extends: .base
+              needs: [build]
+              when: manual
+            ",
+        );
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn reports_unknown_keywords() {
+        let problems = lint_yaml(
+            "
+            job:
+              script: echo
+              scirpt: echo
+              tags: [docker]
+            ",
+        );
+
+        assert_eq!(problems, vec!["job `job`: unknown keyword `scirpt`"]);
+    }
+
+    #[test]
+    fn reports_unknown_keywords_of_templates() {
+        let problems = lint_yaml(
+            "
+            .template:
+              imgae: alpine
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["template `.template`: unknown keyword `imgae`"]
+        );
+    }
+
+    #[test]
+    fn reports_missing_script() {
+        let problems = lint_yaml(
+            "
+            job:
+              image: alpine
+            ",
+        );
+
+        assert_eq!(problems, vec!["job `job`: missing `script`"]);
+    }
+
+    #[test]
+    fn accepts_script_from_templates() {
+        let problems = lint_yaml(
+            "
+            .parent:
+              script: echo
+            .template:
+              extends: .parent
+            job:
+              extends: .template
+            ",
+        );
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn accepts_trigger_jobs_without_script() {
+        let problems = lint_yaml(
+            "
+            job:
+              trigger: other/project
+            ",
+        );
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn reports_undefined_stages() {
+        let problems = lint_yaml(
+            "
+            stages: [build]
+            job:
+              stage: deploy
+              script: echo
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `job`: stage `deploy` is not defined in `stages` (.pre, build, .post)"]
+        );
+    }
+
+    #[test]
+    fn reports_the_default_test_stage_when_not_defined() {
+        let problems = lint_yaml(
+            "
+            stages: [build]
+            job:
+              script: echo
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `job`: stage `test` is not defined in `stages` (.pre, build, .post)"]
+        );
+    }
+
+    #[test]
+    fn reports_needs_on_undefined_jobs() {
+        let problems = lint_yaml(
+            "
+            job:
+              script: echo
+              needs: [missing]
+            ",
+        );
+
+        assert_eq!(problems, vec!["job `job`: needs unknown job `missing`"]);
+    }
+
+    #[test]
+    fn reports_needs_on_jobs_of_later_stages() {
+        let problems = lint_yaml(
+            "
+            build:
+              stage: build
+              script: echo
+              needs: [deploy]
+            deploy:
+              stage: deploy
+              script: echo
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `build`: needs job `deploy` of later stage `deploy`"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_when_values() {
+        let problems = lint_yaml(
+            "
+            job:
+              script: echo
+              when: sometimes
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `job`: invalid `when` value `sometimes`"]
+        );
+    }
+
+    #[test]
+    fn reports_reserved_job_names() {
+        let problems = lint_yaml(
+            "
+            cache:
+              script: echo
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `cache`: `cache` is a reserved keyword and cannot be used as job name"]
+        );
+    }
+
+    #[test]
+    fn reports_missing_templates() {
+        let problems = lint_yaml(
+            "
+            job:
+              extends: .missing
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                "job `job`: template '.missing' not found",
+                "job `job`: missing `script`"
+            ]
+        );
+    }
+
+    #[test]
+    fn collects_all_problems_at_once() {
+        let problems = lint_yaml(
+            "
+            first:
+              unknown: true
+            second:
+              script: echo
+              when: later
+            ",
+        );
+
+        assert_eq!(problems.len(), 3);
+    }
+}
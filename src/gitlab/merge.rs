@@ -1,6 +1,7 @@
 use crate::gitlab::configuration::{GitLabConfiguration, Job, ListOfStrings};
 use crate::gitlab::error::GitLabError;
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use serde_yaml::Value;
 
 pub fn merge_variables(source: &[(String, String)], target: &mut Vec<(String, String)>) {
     target.splice(0..0, source.to_owned());
@@ -18,9 +19,27 @@ pub fn merge_script(source: &Option<ListOfStrings>, target: &mut Option<ListOfSt
     };
 }
 
+pub fn merge_option<T: Clone>(source: &Option<T>, target: &mut Option<T>) {
+    if let (Some(s), t @ None) = (source, target) {
+        let _ = t.insert(s.clone());
+    };
+}
+
+// Keywords Fake CI doesn't model itself (e.g. `rules`) are taken over as a whole.
+pub fn merge_other_keywords(
+    source: &IndexMap<String, Value>,
+    target: &mut IndexMap<String, Value>,
+) {
+    for (keyword, value) in source {
+        if !target.contains_key(keyword) {
+            target.insert(keyword.clone(), value.clone());
+        }
+    }
+}
+
 pub fn collect_template_names(
     job: &Job,
-    all_templates: &HashMap<String, Job>,
+    all_templates: &IndexMap<String, Job>,
 ) -> Result<Vec<String>, GitLabError> {
     let mut collected_names = vec![];
 
@@ -41,10 +60,50 @@ pub fn collect_template_names(
     Ok(collected_names)
 }
 
+// Templates a job extends, in the order their values take precedence: the closest one first, then
+// the ones it extends in turn. Of `extends: [a, b]` the later one wins, so `b` comes before `a`.
+pub fn templates_by_precedence<'a>(
+    job: &Job,
+    all_templates: &'a IndexMap<String, Job>,
+) -> Result<Vec<(&'a String, &'a Job)>, GitLabError> {
+    let names = collect_template_names(job, all_templates)?;
+
+    Ok(names
+        .iter()
+        .rev()
+        .filter_map(|name| all_templates.get_key_value(name))
+        .collect())
+}
+
 pub fn merge_configuration(source: GitLabConfiguration, target: &mut GitLabConfiguration) {
+    if target.stages.is_empty() {
+        target.stages = source.stages;
+    }
+    if target.default.is_none() {
+        target.default = source.default;
+    }
     target.variables.splice(0..0, source.variables.to_owned());
     target.templates.extend(source.templates);
     target.jobs.extend(source.jobs);
+    merge_raw(source.raw, &mut target.raw);
+}
+
+// Includes are deep-merged into the configuration, whose own values win.
+fn merge_raw(source: Value, target: &mut Value) {
+    match (source, target) {
+        (Value::Mapping(source), Value::Mapping(target)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_raw(value, existing),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (source, target @ Value::Null) => *target = source,
+        _ => {}
+    }
 }
 
 #[cfg(test)]
@@ -105,6 +164,48 @@ mod tests {
         }
     }
 
+    mod test_options {
+        use super::*;
+
+        #[test]
+        fn keeps_value_of_target() {
+            let source = Some("build".to_string());
+            let mut target = Some("test".to_string());
+
+            merge_option(&source, &mut target);
+
+            assert_eq!(target, Some("test".into()));
+        }
+
+        #[test]
+        fn takes_value_of_source_when_not_set_yet() {
+            let source = Some("build".to_string());
+            let mut target = None;
+
+            merge_option(&source, &mut target);
+
+            assert_eq!(target, Some("build".into()));
+        }
+    }
+
+    mod test_other_keywords {
+        use super::*;
+
+        #[test]
+        fn adds_keywords_missing_in_target() {
+            let source = IndexMap::from([
+                ("rules".to_string(), Value::from("source rules")),
+                ("tags".to_string(), Value::from("source tags")),
+            ]);
+            let mut target = IndexMap::from([("tags".to_string(), Value::from("target tags"))]);
+
+            merge_other_keywords(&source, &mut target);
+
+            assert_eq!(target["rules"], Value::from("source rules"));
+            assert_eq!(target["tags"], Value::from("target tags"));
+        }
+    }
+
     mod test_scripts {
         use super::*;
 
@@ -142,11 +243,10 @@ mod tests {
     mod test_collecting_templates {
         use super::*;
         use crate::gitlab::configuration::Job;
-        use std::collections::HashMap;
 
         #[test]
         fn fails_when_template_does_not_exist() {
-            let empty_templates = HashMap::new();
+            let empty_templates = IndexMap::new();
             let job_with_templates = Job {
                 extends: Some(ListOfStrings(vec![".template-name".into()])),
                 ..Default::default()
@@ -159,7 +259,7 @@ mod tests {
 
         #[test]
         fn collects_template_names_that_are_used_by_job() {
-            let templates = HashMap::from([
+            let templates = IndexMap::from([
                 (".template-a".into(), Job::default()),
                 (".template-b".into(), Job::default()),
             ]);
@@ -180,7 +280,7 @@ mod tests {
                 ..Default::default()
             };
 
-            let templates = HashMap::from([
+            let templates = IndexMap::from([
                 (".template-a".into(), Job::default()),
                 (".template-b".into(), template_with_additional_extend),
                 (".parent".into(), Job::default()),
@@ -218,6 +318,35 @@ mod tests {
             );
         }
 
+        #[test]
+        fn takes_stages_when_target_has_none() {
+            let source = GitLabConfiguration {
+                stages: vec!["build".into()],
+                ..Default::default()
+            };
+            let mut target = GitLabConfiguration::default();
+
+            merge_configuration(source, &mut target);
+
+            assert_eq!(target.stages, vec!["build".to_string()]);
+        }
+
+        #[test]
+        fn keeps_stages_of_target() {
+            let source = GitLabConfiguration {
+                stages: vec!["build".into()],
+                ..Default::default()
+            };
+            let mut target = GitLabConfiguration {
+                stages: vec!["test".into()],
+                ..Default::default()
+            };
+
+            merge_configuration(source, &mut target);
+
+            assert_eq!(target.stages, vec!["test".to_string()]);
+        }
+
         #[test]
         fn merges_templates() {
             let mut source = GitLabConfiguration::default();
@@ -247,5 +376,44 @@ mod tests {
 
             assert_eq!(target.jobs.len(), 2);
         }
+
+        #[test]
+        fn deep_merges_what_has_been_written_keeping_values_of_the_target() {
+            let source = GitLabConfiguration {
+                raw: serde_yaml::from_str(
+                    "
+                    stages: [build]
+                    job:
+                      script: included.sh
+                      retry: 2
+                    ",
+                )
+                .unwrap(),
+                ..Default::default()
+            };
+            let mut target = GitLabConfiguration {
+                raw: serde_yaml::from_str(
+                    "
+                    job:
+                      script: own.sh
+                    ",
+                )
+                .unwrap(),
+                ..Default::default()
+            };
+
+            merge_configuration(source, &mut target);
+
+            let expected: Value = serde_yaml::from_str(
+                "
+                job:
+                  script: own.sh
+                  retry: 2
+                stages: [build]
+                ",
+            )
+            .unwrap();
+            assert_eq!(target.raw, expected);
+        }
     }
 }
@@ -1,28 +1,45 @@
 pub mod configuration;
 mod deserialise;
 pub mod error;
+pub mod expansion;
+mod includes;
+pub mod lint;
 mod merge;
+pub mod origin;
+pub mod rules;
+pub mod schema;
+pub mod source;
 pub mod variables;
+mod wildcard;
 
-use crate::file::FileAccess;
+use crate::file::{FileAccess, FileAccessError};
 use crate::git::GitDetails;
 use crate::gitlab::configuration::{GitLabConfiguration, Include};
 use crate::gitlab::error::GitLabError;
+use crate::gitlab::includes::{IncludeTracker, Visit};
 use crate::gitlab::merge::{
-    collect_template_names, merge_configuration, merge_image, merge_script, merge_variables,
+    merge_configuration, merge_image, merge_option, merge_other_keywords, merge_script,
+    merge_variables, templates_by_precedence,
 };
+use crate::gitlab::source::annotate_sources;
 use crate::gitlab::variables::predefined_variables;
+use crate::gitlab::wildcard::{fixed_directory, is_wildcard, matching_files};
 use async_recursion::async_recursion;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
 use url::Url;
 
 pub fn read_configuration<R>(
     reader: R,
+    location: &str,
     git: &GitDetails,
 ) -> Result<GitLabConfiguration, GitLabError>
 where
     R: std::io::Read,
 {
-    let mut configuration = parse(reader)?;
+    let mut configuration = parse(reader, location)?;
 
     for (key, value) in predefined_variables(git) {
         configuration.variables.push((key, value));
@@ -31,59 +48,127 @@ where
     Ok(configuration)
 }
 
+// How to reach the GitLab instance that `include:project` files are read from.
+// Projects with a local clone are read from that clone instead.
+#[derive(Default)]
+pub struct GitLabAccess {
+    pub host: String,
+    // Whether a token is configured, it's not resolved until a file is read.
+    pub authenticated: bool,
+    pub local_projects: HashMap<String, String>,
+}
+
+impl GitLabAccess {
+    fn project_file_url(&self, project: &str, r#ref: &str, file: &str) -> String {
+        let file = file.trim_start_matches('/');
+
+        if self.authenticated {
+            // Raw file URLs only work with a browser session, token authentication requires the
+            // Repository Files API: https://docs.gitlab.com/ee/api/repository_files.html
+            format!(
+                "{}/api/v4/projects/{}/repository/files/{}/raw?ref={}",
+                self.host,
+                encode(project),
+                encode(file),
+                encode(r#ref)
+            )
+        } else {
+            format!("{}/{}/-/raw/{}/{}", self.host, project, r#ref, file)
+        }
+    }
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
 pub async fn read_gitlab_configuration(
     path_to_config_file: String,
     file_access: &impl FileAccess,
     git: &GitDetails,
-    gitlab_host: &String,
+    gitlab: &GitLabAccess,
 ) -> Result<GitLabConfiguration, GitLabError> {
-    let file = file_access.read_local_file(path_to_config_file)?;
+    let mut configuration =
+        read_unmerged_configuration(path_to_config_file, file_access, git, gitlab).await?;
+    merge_jobs(&mut configuration)?;
+
+    Ok(configuration)
+}
 
-    let mut configuration = read_configuration(file, git)?;
+// Reads the configuration together with all of its includes, but leaves jobs as they have
+// been written. No templates or defaults have been applied to them yet.
+pub async fn read_unmerged_configuration(
+    path_to_config_file: String,
+    file_access: &impl FileAccess,
+    git: &GitDetails,
+    gitlab: &GitLabAccess,
+) -> Result<GitLabConfiguration, GitLabError> {
+    let file = file_access.read_local_file(&path_to_config_file)?;
+
+    let mut configuration = read_configuration(file, &path_to_config_file, git)?;
     let additional_configurations =
-        parse_all(&configuration.include, file_access, gitlab_host).await?;
-    merge_all(additional_configurations, &mut configuration)?;
+        parse_all(&configuration.include, file_access, gitlab, &git.root).await?;
+    merge_all(additional_configurations, &mut configuration);
 
     Ok(configuration)
 }
 
-fn parse<R>(reader: R) -> Result<GitLabConfiguration, GitLabError>
+fn parse<R>(reader: R, location: &str) -> Result<GitLabConfiguration, GitLabError>
 where
     R: std::io::Read,
 {
-    let configuration: GitLabConfiguration =
-        serde_yaml::from_reader(reader).map_err(GitLabError::parse)?;
+    let content = read_content(reader, location)?;
+    let mut configuration: GitLabConfiguration =
+        serde_yaml::from_str(&content).map_err(|e| GitLabError::syntax(location, &content, e))?;
+    configuration.raw = serde_yaml::from_str::<Option<serde_yaml::Value>>(&content)
+        .map_err(|e| GitLabError::syntax(location, &content, e))?
+        .unwrap_or_default();
+    configuration
+        .raw
+        .apply_merge()
+        .map_err(|e| GitLabError::syntax(location, &content, e))?;
+    annotate_sources(&mut configuration, &content, location);
 
     Ok(configuration)
 }
 
+fn read_content<R>(mut reader: R, location: &str) -> Result<String, GitLabError>
+where
+    R: std::io::Read,
+{
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .map_err(|e| GitLabError::file(FileAccessError::cannot_read(location, e)))?;
+
+    Ok(content)
+}
+
 pub fn merge_all(
     additional_configurations: Vec<GitLabConfiguration>,
     configuration: &mut GitLabConfiguration,
-) -> Result<(), GitLabError> {
+) {
     for additional_configuration in additional_configurations {
         merge_configuration(additional_configuration, configuration);
     }
-
-    merge_jobs(configuration)?;
-
-    Ok(())
 }
 
 pub fn merge_jobs(configuration: &mut GitLabConfiguration) -> Result<(), GitLabError> {
-    for (_name, job) in configuration.jobs.iter_mut() {
-        let required_template_names = collect_template_names(job, &configuration.templates)?;
-
-        for template_name in required_template_names {
-            let template = configuration
-                .templates
-                .get(&template_name)
-                .ok_or_else(|| GitLabError::TemplateNotFound(template_name.to_owned()))?;
+    for (name, job) in configuration.jobs.iter_mut() {
+        let templates = templates_by_precedence(job, &configuration.templates)
+            .map_err(|e| GitLabError::job(name, job, e))?;
 
+        // Every merge keeps what the job has already, so the closest template has to come first.
+        for (_, template) in templates {
             merge_variables(&template.variables, &mut job.variables);
             merge_script(&template.after_script, &mut job.after_script);
             merge_script(&template.before_script, &mut job.before_script);
+            merge_script(&template.script, &mut job.script);
             merge_image(&template.image, &mut job.image);
+            merge_option(&template.needs, &mut job.needs);
+            merge_option(&template.stage, &mut job.stage);
+            merge_option(&template.when, &mut job.when);
+            merge_other_keywords(&template.other_keywords, &mut job.other_keywords);
         }
 
         merge_variables(&configuration.variables, &mut job.variables);
@@ -100,127 +185,288 @@ pub fn merge_jobs(configuration: &mut GitLabConfiguration) -> Result<(), GitLabE
 
 #[derive(Clone)]
 pub enum ResolvePath {
-    Local,
+    Local(PathBuf),
     Remote(Url),
+    Project { project: String, r#ref: String },
 }
 
 pub async fn parse_all(
     includes: &Vec<Include>,
     file_access: &impl FileAccess,
-    gitlab_host: &String,
+    gitlab: &GitLabAccess,
+    repository_root: &str,
 ) -> Result<Vec<GitLabConfiguration>, GitLabError> {
     // The distinction between "local" path resolving and "remote" is that on the initial read
     // through a .gitlab-ci.yml all `include:local` (https://docs.gitlab.com/ee/ci/yaml/#includelocal)
     // includes are to be read from the local file system.
-    // Every additional pass from the included configurations is to be resolved as a remote path.
-    parse_all_with_base(includes, file_access, gitlab_host, &ResolvePath::Local).await
+    // Every additional pass from the included configurations is to be resolved as a remote path,
+    // or relative to the root of the project when included via `include:project`.
+    // Local paths are always relative to the root of the repository, regardless of the
+    // directory Fake CI is called from.
+    let resolve_path = ResolvePath::Local(PathBuf::from(repository_root));
+    let mut tracker = IncludeTracker::default();
+
+    parse_all_with_base(includes, file_access, gitlab, &resolve_path, &mut tracker).await
 }
 
 #[async_recursion(?Send)]
 pub async fn parse_all_with_base(
     includes: &Vec<Include>,
     file_access: &impl FileAccess,
-    gitlab_host: &String,
+    gitlab: &GitLabAccess,
     resolve_path: &ResolvePath,
+    tracker: &mut IncludeTracker,
 ) -> Result<Vec<GitLabConfiguration>, GitLabError> {
     let mut included_configurations = vec![];
 
     for include in includes {
         included_configurations
-            .extend(read_and_parse(include, file_access, gitlab_host, resolve_path).await?);
+            .extend(read_and_parse(include, file_access, gitlab, resolve_path, tracker).await?);
     }
 
     Ok(included_configurations)
 }
 
-async fn read_and_parse(
+// A single file to be included, after wildcards and lists of files have been resolved.
+enum IncludedFile {
+    Local(PathBuf),
+    Remote(Url),
+    Project {
+        project: String,
+        r#ref: String,
+        file: String,
+    },
+}
+
+impl IncludedFile {
+    // The same file can be referenced in different ways, e.g. `./a.yml` and `a.yml`, or through
+    // the raw URL and the API of a project. This location is the same for all of them.
+    // Local paths are already normalised when resolving the include.
+    fn canonical_location(&self) -> String {
+        match self {
+            IncludedFile::Local(path) => path.to_string_lossy().to_string(),
+            IncludedFile::Remote(url) => url.to_string(),
+            IncludedFile::Project {
+                project,
+                r#ref,
+                file,
+            } => format!("{}:{}@{}", project, file.trim_start_matches('/'), r#ref),
+        }
+    }
+}
+
+fn resolve(
     include: &Include,
     file_access: &impl FileAccess,
-    gitlab_host: &String,
     resolve_path: &ResolvePath,
-) -> Result<Vec<GitLabConfiguration>, GitLabError> {
-    let mut paths_and_configurations = vec![];
+) -> Result<Vec<(IncludedFile, ResolvePath)>, GitLabError> {
+    let files = match include {
+        Include::Local(local_include) if is_wildcard(&local_include.local) => {
+            let ResolvePath::Local(root) = resolve_path else {
+                return Err(GitLabError::UnsupportedWildcard(
+                    local_include.local.clone(),
+                ));
+            };
 
-    match include {
+            find_local_files(&local_include.local, root, file_access)?
+                .into_iter()
+                .map(|path| (IncludedFile::Local(root.join(path)), resolve_path.clone()))
+                .collect()
+        }
         Include::Local(local_include) => {
-            let content = match resolve_path {
-                ResolvePath::Local => file_access
-                    .read_local_file(&local_include.local)
-                    .map_err(GitLabError::file)?,
+            let file = match resolve_path {
+                ResolvePath::Local(root) => IncludedFile::Local(normalise(
+                    &root.join(local_include.local.trim_start_matches('/')),
+                )),
                 ResolvePath::Remote(base_url) => {
-                    let url1 = append(base_url, &local_include.local)?;
-                    file_access
-                        .read_remote_file(url1)
-                        .await
-                        .map_err(GitLabError::file)?
+                    IncludedFile::Remote(append(base_url, &local_include.local)?)
                 }
+                ResolvePath::Project { project, r#ref } => IncludedFile::Project {
+                    project: project.clone(),
+                    r#ref: r#ref.clone(),
+                    file: local_include.local.clone(),
+                },
             };
 
-            let configuration = parse(*content)?;
-
-            paths_and_configurations.push((resolve_path.clone(), configuration));
-        }
-        Include::File(file_include) => {
-            for file in &file_include.file {
-                let url = format!(
-                    "{}/{}/-/raw/{}/{}",
-                    gitlab_host, file_include.project, file_include.r#ref, file
-                );
-                let content = file_access
-                    .read_remote_file(&url)
-                    .await
-                    .map_err(GitLabError::file)?;
-                let configuration = parse(*content)?;
-
-                paths_and_configurations
-                    .push((ResolvePath::Remote(base_url(&url)?), configuration));
-            }
+            vec![(file, resolve_path.clone())]
         }
+        Include::File(file_include) => file_include
+            .file
+            .iter()
+            .map(|file| {
+                (
+                    IncludedFile::Project {
+                        project: file_include.project.clone(),
+                        r#ref: file_include.r#ref.clone(),
+                        file: file.clone(),
+                    },
+                    ResolvePath::Project {
+                        project: file_include.project.clone(),
+                        r#ref: file_include.r#ref.clone(),
+                    },
+                )
+            })
+            .collect(),
         Include::Remote(remote_include) => {
-            let content = file_access
-                .read_remote_file(&remote_include.remote)
-                .await
-                .map_err(GitLabError::file)?;
-            let configuration = parse(*content)?;
+            let url = Url::parse(&remote_include.remote).map_err(GitLabError::create_url)?;
 
-            paths_and_configurations.push((
+            vec![(
+                IncludedFile::Remote(url),
                 ResolvePath::Remote(base_url(&remote_include.remote)?),
-                configuration,
-            ));
+            )]
         }
         Include::Template(template_include) => {
             let url = format!(
                 "https://gitlab.com/gitlab-org/gitlab/-/raw/master/lib/gitlab/ci/templates/{}",
                 &template_include.template
             );
+
+            vec![(
+                IncludedFile::Remote(Url::parse(&url).map_err(GitLabError::create_url)?),
+                ResolvePath::Remote(base_url(&url)?),
+            )]
+        }
+    };
+
+    Ok(files)
+}
+
+async fn read(
+    file: &IncludedFile,
+    file_access: &impl FileAccess,
+    gitlab: &GitLabAccess,
+) -> Result<(String, Box<Cursor<Vec<u8>>>), GitLabError> {
+    match file {
+        IncludedFile::Local(path) => {
             let content = file_access
-                .read_remote_file(&url)
+                .read_local_file(path)
+                .map_err(GitLabError::file)?;
+
+            Ok((path.to_string_lossy().to_string(), content))
+        }
+        IncludedFile::Remote(url) => {
+            let content = file_access
+                .read_remote_file(url.as_str())
                 .await
                 .map_err(GitLabError::file)?;
-            let configuration = parse(*content)?;
 
-            paths_and_configurations.push((ResolvePath::Remote(base_url(&url)?), configuration));
+            Ok((url.to_string(), content))
         }
+        IncludedFile::Project {
+            project,
+            r#ref,
+            file,
+        } => read_project_file(project, r#ref, file, file_access, gitlab).await,
     }
+}
 
+async fn read_and_parse(
+    include: &Include,
+    file_access: &impl FileAccess,
+    gitlab: &GitLabAccess,
+    resolve_path: &ResolvePath,
+    tracker: &mut IncludeTracker,
+) -> Result<Vec<GitLabConfiguration>, GitLabError> {
     let mut configurations = vec![];
 
-    for (new_resolve_path, configuration) in paths_and_configurations {
+    for (file, new_resolve_path) in resolve(include, file_access, resolve_path)? {
+        let canonical_location = file.canonical_location();
+
+        if let Visit::Duplicate = tracker.visit(&canonical_location)? {
+            continue;
+        }
+
+        let (location, content) = read(&file, file_access, gitlab).await?;
+        let configuration = parse(*content, &location)?;
+
+        tracker.enter(&canonical_location);
         let more_configurations = parse_all_with_base(
             &configuration.include,
             file_access,
-            gitlab_host,
+            gitlab,
             &new_resolve_path,
+            tracker,
         )
-        .await?;
+        .await;
+        tracker.leave();
 
-        configurations.extend(more_configurations);
+        configurations.extend(more_configurations?);
         configurations.push(configuration);
     }
 
     Ok(configurations)
 }
 
+// Resolves `.` and `..` without touching the file system.
+fn normalise(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalised.pop() {
+                    normalised.push(component);
+                }
+            }
+            component => normalised.push(component),
+        }
+    }
+
+    normalised
+}
+
+fn find_local_files(
+    pattern: &str,
+    root: &Path,
+    file_access: &impl FileAccess,
+) -> Result<Vec<String>, GitLabError> {
+    let directory = fixed_directory(pattern);
+    let files = file_access
+        .list_local_files(root.join(directory))
+        .map_err(GitLabError::file)?
+        .into_iter()
+        .map(|file| match directory {
+            "" => file,
+            directory => format!("{}/{}", directory, file),
+        })
+        .collect::<Vec<_>>();
+    let matches = matching_files(pattern, &files);
+
+    if matches.is_empty() {
+        return Err(GitLabError::file(FileAccessError::NotFound(
+            pattern.to_string(),
+        )));
+    }
+
+    Ok(matches)
+}
+
+async fn read_project_file(
+    project: &str,
+    r#ref: &str,
+    file: &str,
+    file_access: &impl FileAccess,
+    gitlab: &GitLabAccess,
+) -> Result<(String, Box<Cursor<Vec<u8>>>), GitLabError> {
+    if let Some(repository) = gitlab.local_projects.get(project) {
+        let path = file.trim_start_matches('/');
+        let content = file_access
+            .read_repository_file(repository, r#ref, path)
+            .map_err(GitLabError::file)?;
+
+        return Ok((format!("{}/{} ({})", repository, path, r#ref), content));
+    }
+
+    let url = gitlab.project_file_url(project, r#ref, file);
+    let content = file_access
+        .read_remote_file(&url)
+        .await
+        .map_err(GitLabError::file)?;
+
+    Ok((url, content))
+}
+
 fn base_url(url: &str) -> Result<Url, GitLabError> {
     let mut base_url = Url::parse(url).map_err(GitLabError::create_url)?;
 
@@ -248,7 +494,7 @@ mod tests {
     use super::*;
 
     fn parse_and_merge(content: &str) -> Result<GitLabConfiguration, GitLabError> {
-        let mut configuration = parse(content.as_bytes())?;
+        let mut configuration = parse(content.as_bytes(), ".gitlab-ci.yml")?;
 
         merge_jobs(&mut configuration)?;
 
@@ -274,8 +520,12 @@ mod tests {
         fn adds_predefined_variables_to_global_variables() {
             let empty_content = "";
             let empty_git_details = GitDetails::default();
-            let configuration =
-                read_configuration(empty_content.as_bytes(), &empty_git_details).unwrap();
+            let configuration = read_configuration(
+                empty_content.as_bytes(),
+                ".gitlab-ci.yml",
+                &empty_git_details,
+            )
+            .unwrap();
 
             let all_variable_names = configuration
                 .variables
@@ -503,6 +753,32 @@ mod tests {
         }
     }
 
+    mod test_merge_precedence_of_scripts {
+        use super::*;
+        use crate::gitlab::configuration::ListOfStrings;
+
+        #[test]
+        fn uses_template_script_when_job_does_not_define_one() {
+            let content = "
+                .template:
+                  script:
+                    - template_command.sh
+
+                job:
+                  extends:
+                    - .template
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let job = configuration.jobs.get("job").unwrap();
+
+            assert_eq!(
+                job.script,
+                Some(ListOfStrings(vec!["template_command.sh".into()]))
+            );
+        }
+    }
+
     mod test_merge_precedence_of_image_names {
         use super::*;
 
@@ -565,13 +841,103 @@ mod tests {
         }
     }
 
+    mod test_merge_precedence_of_templates {
+        use super::*;
+        use crate::gitlab::configuration::ListOfStrings;
+
+        #[test]
+        fn takes_values_of_templates_closer_to_the_job_first() {
+            let content = "
+                .base:
+                  stage: build
+                  image: base:image
+                  script:
+                    - base.sh
+                  rules:
+                    - if: $BASE
+                  variables:
+                    LEVEL: base
+
+                .middle:
+                  extends: .base
+                  stage: test
+                  image: middle:image
+                  rules:
+                    - if: $MIDDLE
+                  variables:
+                    LEVEL: middle
+
+                .top:
+                  extends: .middle
+                  image: top:image
+
+                job:
+                  extends: .top
+                  script:
+                    - job.sh
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let job = configuration.jobs.get("job").unwrap();
+            let rules: serde_yaml::Value = serde_yaml::from_str("- if: $MIDDLE").unwrap();
+
+            assert_eq!(job.stage, Some("test".into()));
+            assert_eq!(job.image, Some("top:image".into()));
+            assert_eq!(job.script, Some(ListOfStrings(vec!["job.sh".into()])));
+            assert_eq!(job.other_keywords["rules"], rules);
+            assert_eq!(
+                job.variables.last(),
+                Some(&("LEVEL".to_string(), "middle".to_string()))
+            );
+        }
+
+        #[test]
+        fn takes_values_of_later_templates_in_extends_first() {
+            let content = "
+                .base:
+                  stage: build
+                  variables:
+                    LEVEL: base
+
+                .first:
+                  stage: review
+                  image: first:image
+                  script:
+                    - first.sh
+                  variables:
+                    LEVEL: first
+
+                .second:
+                  extends: .base
+                  image: second:image
+
+                job:
+                  extends:
+                    - .first
+                    - .second
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let job = configuration.jobs.get("job").unwrap();
+
+            assert_eq!(job.image, Some("second:image".into()));
+            assert_eq!(job.stage, Some("build".into()));
+            assert_eq!(job.script, Some(ListOfStrings(vec!["first.sh".into()])));
+            assert_eq!(
+                job.variables.last(),
+                Some(&("LEVEL".to_string(), "base".to_string()))
+            );
+        }
+    }
+
     mod test_include_parsing {
         use super::*;
         use crate::file::StubFiles;
+        use crate::gitlab::includes::MAXIMUM_NUMBER_OF_INCLUDES;
 
         #[tokio::test]
         async fn resolves_local_files() {
-            let dummy_host = "".to_string();
+            let dummy_host = GitLabAccess::default();
             let other_content = "
                 variables:
                   OTHER_VARIABLE: true
@@ -583,16 +949,17 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &dummy_host)
-                .await
-                .unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
 
             assert_eq!(additional_configurations.len(), 1);
         }
 
         #[tokio::test]
         async fn resolves_multiple_local_files() {
-            let dummy_host = "".to_string();
+            let dummy_host = GitLabAccess::default();
             let file_a_content = "
                 variables:
                   FILE_A: value a
@@ -612,16 +979,17 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &dummy_host)
-                .await
-                .unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
 
             assert_eq!(additional_configurations.len(), 2);
         }
 
         #[tokio::test]
         async fn resolves_nested_local_files() {
-            let dummy_host = "".to_string();
+            let dummy_host = GitLabAccess::default();
             let first_content = "
                 include:
                   local: second-file.yml
@@ -642,16 +1010,97 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &dummy_host)
-                .await
-                .unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
 
             assert_eq!(additional_configurations.len(), 2);
         }
 
+        #[tokio::test]
+        async fn resolves_local_files_relative_to_repository_root() {
+            let dummy_host = GitLabAccess::default();
+            let mut files = StubFiles::default();
+            files.add_file("/repository/ci/other.yml", "variables: {}");
+            let content = "
+                include:
+                  local: /ci/other.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "/repository")
+                    .await
+                    .unwrap();
+
+            assert_eq!(additional_configurations.len(), 1);
+        }
+
+        #[tokio::test]
+        async fn resolves_wildcard_local_files_in_alphabetical_order() {
+            let dummy_host = GitLabAccess::default();
+            let mut files = StubFiles::default();
+            files.add_file("/repository/configs/b.yml", "stages: [b]");
+            files.add_file("/repository/configs/a.yml", "stages: [a]");
+            files.add_file("/repository/configs/nested/c.yml", "stages: [c]");
+            let content = "
+                include: 'configs/*.yml'
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "/repository")
+                    .await
+                    .unwrap();
+            let stages = additional_configurations
+                .iter()
+                .flat_map(|configuration| configuration.stages.clone())
+                .collect::<Vec<_>>();
+
+            assert_eq!(stages, vec!["a".to_string(), "b".to_string()]);
+        }
+
+        #[tokio::test]
+        async fn resolves_recursive_wildcard_local_files() {
+            let dummy_host = GitLabAccess::default();
+            let mut files = StubFiles::default();
+            files.add_file(".gitlab/ci/a.yml", "stages: [a]");
+            files.add_file(".gitlab/ci/nested/b.yml", "stages: [b]");
+            let content = "
+                include:
+                  local: '.gitlab/ci/**/*.yml'
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
+
+            assert_eq!(additional_configurations.len(), 1);
+        }
+
+        #[tokio::test]
+        async fn fails_when_wildcard_matches_no_files() {
+            let dummy_host = GitLabAccess::default();
+            let files = StubFiles::default();
+            let content = "
+                include: 'configs/*.yml'
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let result = parse_all(&configuration.include, &files, &dummy_host, "").await;
+
+            assert!(result.is_err());
+        }
+
         #[tokio::test]
         async fn resolves_gitlab_project_files() {
-            let gitlab_host = "https://example-gitlab.com".to_string();
+            let gitlab_host = GitLabAccess {
+                host: "https://example-gitlab.com".into(),
+                ..Default::default()
+            };
             let file_a_content = "
                 variables:
                   FILE_A: value a
@@ -680,16 +1129,238 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &gitlab_host)
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &gitlab_host, "")
+                    .await
+                    .unwrap();
+
+            assert_eq!(additional_configurations.len(), 2);
+        }
+
+        #[tokio::test]
+        async fn resolves_gitlab_project_files_through_the_api_when_authenticated() {
+            let gitlab = GitLabAccess {
+                host: "https://example-gitlab.com".into(),
+                authenticated: true,
+                ..Default::default()
+            };
+            let mut files = StubFiles::default();
+            files.add_remote_file(
+                "https://example-gitlab.com/api/v4/projects/the%2Dgroup%2Fthe%2Dproject/repository/files/ci%2Ffile%2Eyml/raw?ref=main",
+                "variables: {}",
+            );
+
+            let content = "
+                include:
+                  project: the-group/the-project
+                  ref: main
+                  file: /ci/file.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations = parse_all(&configuration.include, &files, &gitlab, "")
+                .await
+                .unwrap();
+
+            assert_eq!(additional_configurations.len(), 1);
+        }
+
+        #[tokio::test]
+        async fn resolves_nested_local_files_relative_to_the_included_project() {
+            let gitlab_host = GitLabAccess {
+                host: "https://example-gitlab.com".into(),
+                ..Default::default()
+            };
+            let project_file = "
+                include:
+                  local: /common/variables.yml
+            ";
+            let mut files = StubFiles::default();
+            files.add_remote_file(
+                "https://example-gitlab.com/the-group/the-project/-/raw/main/ci/file.yml",
+                project_file,
+            );
+            files.add_remote_file(
+                "https://example-gitlab.com/the-group/the-project/-/raw/main/common/variables.yml",
+                "variables: {}",
+            );
+
+            let content = "
+                include:
+                  project: the-group/the-project
+                  ref: main
+                  file: ci/file.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &gitlab_host, "")
+                    .await
+                    .unwrap();
+
+            assert_eq!(additional_configurations.len(), 2);
+        }
+
+        #[tokio::test]
+        async fn resolves_gitlab_project_files_from_local_clones() {
+            let gitlab = GitLabAccess {
+                host: "https://example-gitlab.com".into(),
+                local_projects: HashMap::from([(
+                    "infra/ci-templates".into(),
+                    "../ci-templates".into(),
+                )]),
+                ..Default::default()
+            };
+            let project_file = "
+                include:
+                  local: /common/variables.yml
+            ";
+            let mut files = StubFiles::default();
+            files.add_repository_file("../ci-templates", "main", "ci/file.yml", project_file);
+            files.add_repository_file(
+                "../ci-templates",
+                "main",
+                "common/variables.yml",
+                "variables: {}",
+            );
+
+            let content = "
+                include:
+                  project: infra/ci-templates
+                  ref: main
+                  file: /ci/file.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations = parse_all(&configuration.include, &files, &gitlab, "")
                 .await
                 .unwrap();
 
             assert_eq!(additional_configurations.len(), 2);
         }
 
+        #[tokio::test]
+        async fn reports_files_including_themselves() {
+            let dummy_host = GitLabAccess::default();
+            let files = StubFiles::with_file("a.yml", "include: a.yml");
+            let content = "
+                include: a.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let error = parse_all(&configuration.include, &files, &dummy_host, "")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(error, GitLabError::IncludeCycle(chain) if chain == "a.yml -> a.yml"));
+        }
+
+        #[tokio::test]
+        async fn reports_include_cycles_with_the_full_chain() {
+            let dummy_host = GitLabAccess::default();
+            let mut files = StubFiles::default();
+            files.add_file("a.yml", "include: b.yml");
+            files.add_file("b.yml", "include: ./c.yml");
+            files.add_file("c.yml", "include: a.yml");
+            let content = "
+                include: a.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let error = parse_all(&configuration.include, &files, &dummy_host, "")
+                .await
+                .unwrap_err();
+
+            assert_eq!(
+                error.to_string(),
+                "include cycle detected: a.yml -> b.yml -> c.yml -> a.yml"
+            );
+        }
+
+        #[tokio::test]
+        async fn ignores_duplicate_includes() {
+            let dummy_host = GitLabAccess::default();
+            let mut files = StubFiles::default();
+            files.add_file("a.yml", "include: common.yml");
+            files.add_file("b.yml", "include: ./common.yml");
+            files.add_file("common.yml", "variables: {}");
+            let content = "
+                include:
+                  - local: a.yml
+                  - local: b.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
+
+            assert_eq!(additional_configurations.len(), 3);
+        }
+
+        #[tokio::test]
+        async fn fails_when_exceeding_the_maximum_number_of_includes() {
+            let dummy_host = GitLabAccess::default();
+            let mut files = StubFiles::default();
+            let mut content = "include:\n".to_string();
+
+            for index in 0..=MAXIMUM_NUMBER_OF_INCLUDES {
+                files.add_file(&format!("{index}.yml"), "variables: {}");
+                content.push_str(&format!("  - local: {index}.yml\n"));
+            }
+
+            let configuration = parse_and_merge(&content).unwrap();
+            let result = parse_all(&configuration.include, &files, &dummy_host, "").await;
+
+            assert!(matches!(result, Err(GitLabError::TooManyIncludes(_))));
+        }
+
+        #[tokio::test]
+        async fn reports_syntax_errors_with_the_location_of_the_included_file() {
+            let dummy_host = GitLabAccess::default();
+            let files = StubFiles::with_file("broken.yml", "variables: [");
+            let content = "
+                include:
+                  local: broken.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let error = parse_all(&configuration.include, &files, &dummy_host, "")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(&error, GitLabError::Syntax { file, .. } if file == "broken.yml"));
+        }
+
+        #[tokio::test]
+        async fn records_the_included_file_as_source_of_its_jobs() {
+            let dummy_host = GitLabAccess::default();
+            let files = StubFiles::with_file(
+                "ci/test.yml",
+                "variables: {}\n\ntest:\n  script: echo test\n",
+            );
+            let content = "
+                include:
+                  local: ci/test.yml
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
+            let source = additional_configurations[0].jobs["test"]
+                .source
+                .as_ref()
+                .unwrap();
+
+            assert_eq!(source.to_string(), "ci/test.yml:3");
+        }
+
         #[tokio::test]
         async fn resolves_remote_files() {
-            let dummy_host = "".to_string();
+            let dummy_host = GitLabAccess::default();
             let file_content = "
                 variables:
                   FILE: value
@@ -703,16 +1374,17 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &dummy_host)
-                .await
-                .unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
 
             assert_eq!(additional_configurations.len(), 1);
         }
 
         #[tokio::test]
         async fn resolves_template_files() {
-            let dummy_host = "".to_string();
+            let dummy_host = GitLabAccess::default();
             let file_content = "
                 variables:
                   FILE: value
@@ -726,16 +1398,20 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &dummy_host)
-                .await
-                .unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &dummy_host, "")
+                    .await
+                    .unwrap();
 
             assert_eq!(additional_configurations.len(), 1);
         }
 
         #[tokio::test]
         async fn resolves_nested_remote_files_as_local_to_the_host() {
-            let gitlab_host = "https://example-gitlab.com".to_string();
+            let gitlab_host = GitLabAccess {
+                host: "https://example-gitlab.com".into(),
+                ..Default::default()
+            };
             let first_include = "
                 include:
                   local: local-file.yml
@@ -756,9 +1432,10 @@ mod tests {
             ";
 
             let configuration = parse_and_merge(local_content).unwrap();
-            let additional_configurations = parse_all(&configuration.include, &files, &gitlab_host)
-                .await
-                .unwrap();
+            let additional_configurations =
+                parse_all(&configuration.include, &files, &gitlab_host, "")
+                    .await
+                    .unwrap();
 
             assert_eq!(additional_configurations.len(), 2);
         }
@@ -778,10 +1455,55 @@ mod tests {
             let other_configuration = parse_and_merge(other_content).unwrap();
             let mut configuration = parse_and_merge(content).unwrap();
 
-            merge_all(vec![other_configuration], &mut configuration).unwrap();
+            merge_all(vec![other_configuration], &mut configuration);
 
             assert_eq!(configuration.variables.len(), 1);
         }
+
+        #[test]
+        fn keeps_jobs_in_order_of_their_definition() {
+            let content = "
+                zeta:
+                  script: echo
+                alpha:
+                  script: echo
+                middle:
+                  script: echo
+            ";
+
+            let configuration = parse_and_merge(content).unwrap();
+            let names = configuration.jobs.keys().collect::<Vec<_>>();
+
+            assert_eq!(names, vec!["zeta", "alpha", "middle"]);
+        }
+
+        #[test]
+        fn names_the_job_and_its_source_in_errors() {
+            let content = "
+job:
+  extends: .missing
+";
+
+            let error = parse_and_merge(content).unwrap_err();
+
+            assert_eq!(
+                error.to_string(),
+                "job `job` (from .gitlab-ci.yml:2): template '.missing' not found"
+            );
+        }
+    }
+
+    mod test_path_helpers {
+        use super::*;
+
+        #[test]
+        fn normalise_resolves_relative_components() {
+            assert_eq!(
+                normalise(Path::new("/repository/./ci/../other.yml")),
+                PathBuf::from("/repository/other.yml")
+            );
+            assert_eq!(normalise(Path::new("../a.yml")), PathBuf::from("../a.yml"));
+        }
     }
 
     mod test_url_helpers {
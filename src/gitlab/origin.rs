@@ -0,0 +1,237 @@
+use crate::git::GitDetails;
+use crate::gitlab::configuration::{GitLabConfiguration, Job};
+use crate::gitlab::error::GitLabError;
+use crate::gitlab::merge::templates_by_precedence;
+use crate::gitlab::variables::predefined_variables;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+// Where the final value of a job's keyword comes from once everything has been merged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Job,
+    Template(String),
+    Default,
+    Global,
+    Predefined,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Job => write!(f, "job"),
+            Origin::Template(name) => write!(f, "template {}", name),
+            Origin::Default => write!(f, "default"),
+            Origin::Global => write!(f, "global"),
+            Origin::Predefined => write!(f, "predefined"),
+        }
+    }
+}
+
+impl Serialize for Origin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Origins {
+    pub image: Option<Origin>,
+    pub before_script: Option<Origin>,
+    pub script: Option<Origin>,
+    pub after_script: Option<Origin>,
+    pub variables: HashMap<String, Origin>,
+}
+
+// Replays what `merge_jobs` does for a single job, but records where values come from instead.
+// Expects the configuration before `merge_jobs` has been applied.
+pub fn trace_origins(
+    job: &Job,
+    configuration: &GitLabConfiguration,
+    git: &GitDetails,
+) -> Result<Origins, GitLabError> {
+    let templates = templates_by_precedence(job, &configuration.templates)?;
+    let defaults = configuration.default.as_ref();
+
+    let origin_of = |has_value: &dyn Fn(&Job) -> bool, has_default: bool| {
+        if has_value(job) {
+            Some(Origin::Job)
+        } else if let Some((name, _)) = templates.iter().find(|(_, t)| has_value(t)) {
+            Some(Origin::Template(name.to_string()))
+        } else if has_default {
+            Some(Origin::Default)
+        } else {
+            None
+        }
+    };
+
+    // Variables are collected in the order `merge_jobs` puts them in. Later ones win.
+    let predefined = predefined_variables(git);
+    let mut variables = HashMap::new();
+
+    for variable in &configuration.variables {
+        let origin = if predefined.contains(variable) {
+            Origin::Predefined
+        } else {
+            Origin::Global
+        };
+        variables.insert(variable.0.clone(), origin);
+    }
+    for (name, template) in templates.iter().rev() {
+        for (key, _) in &template.variables {
+            variables.insert(key.clone(), Origin::Template(name.to_string()));
+        }
+    }
+    for (key, _) in &job.variables {
+        variables.insert(key.clone(), Origin::Job);
+    }
+
+    Ok(Origins {
+        image: origin_of(
+            &|j| j.image.is_some(),
+            defaults.is_some_and(|d| d.image.is_some()),
+        ),
+        before_script: origin_of(
+            &|j| j.before_script.is_some(),
+            defaults.is_some_and(|d| d.before_script.is_some()),
+        ),
+        script: origin_of(&|j| j.script.is_some(), false),
+        after_script: origin_of(
+            &|j| j.after_script.is_some(),
+            defaults.is_some_and(|d| d.after_script.is_some()),
+        ),
+        variables,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::read_configuration;
+
+    fn origins(content: &str, job: &str) -> Origins {
+        let git = GitDetails::default();
+        let configuration = read_configuration(content.as_bytes(), "ci.yml", &git).unwrap();
+
+        trace_origins(&configuration.jobs[job], &configuration, &git).unwrap()
+    }
+
+    #[test]
+    fn attributes_values_to_the_job_itself() {
+        let origins = origins(
+            "
+            job:
+              image: alpine
+              script: echo
+              variables:
+                NAME: job
+            ",
+            "job",
+        );
+
+        assert_eq!(origins.image, Some(Origin::Job));
+        assert_eq!(origins.script, Some(Origin::Job));
+        assert_eq!(origins.before_script, None);
+        assert_eq!(origins.variables["NAME"], Origin::Job);
+    }
+
+    #[test]
+    fn attributes_values_to_templates_and_defaults() {
+        let origins = origins(
+            "
+            default:
+              image: ruby
+              before_script: setup
+            .template:
+              image: alpine
+              variables:
+                NAME: template
+            job:
+              extends: .template
+              script: echo
+            ",
+            "job",
+        );
+
+        assert_eq!(origins.image, Some(Origin::Template(".template".into())));
+        assert_eq!(origins.before_script, Some(Origin::Default));
+        assert_eq!(
+            origins.variables["NAME"],
+            Origin::Template(".template".into())
+        );
+    }
+
+    #[test]
+    fn attributes_overridden_values_to_the_closest_template() {
+        let origins = origins(
+            "
+            .base:
+              image: alpine
+              before_script: setup
+              variables:
+                NAME: base
+            .middle:
+              extends: .base
+              image: ruby
+              variables:
+                NAME: middle
+            job:
+              extends: .middle
+              script: echo
+            ",
+            "job",
+        );
+
+        assert_eq!(origins.image, Some(Origin::Template(".middle".into())));
+        assert_eq!(
+            origins.before_script,
+            Some(Origin::Template(".base".into()))
+        );
+        assert_eq!(
+            origins.variables["NAME"],
+            Origin::Template(".middle".into())
+        );
+    }
+
+    #[test]
+    fn attributes_variables_to_global_and_predefined_ones() {
+        let origins = origins(
+            "
+            variables:
+              GLOBAL: value
+            job:
+              script: echo
+            ",
+            "job",
+        );
+
+        assert_eq!(origins.variables["GLOBAL"], Origin::Global);
+        assert_eq!(origins.variables["CI_PROJECT_DIR"], Origin::Predefined);
+    }
+
+    #[test]
+    fn prefers_variables_of_the_job_over_global_ones() {
+        let origins = origins(
+            "
+            variables:
+              NAME: global
+            job:
+              variables:
+                NAME: job
+            ",
+            "job",
+        );
+
+        assert_eq!(origins.variables["NAME"], Origin::Job);
+    }
+
+    #[test]
+    fn displays_origins() {
+        assert_eq!(
+            Origin::Template(".base".into()).to_string(),
+            "template .base"
+        );
+        assert_eq!(Origin::Predefined.to_string(), "predefined");
+    }
+}
@@ -0,0 +1,503 @@
+use crate::gitlab::configuration::Job;
+use crate::gitlab::error::GitLabError;
+use regex::Regex;
+use serde_yaml::Value;
+
+// Decides whether GitLab would add the job to a pipeline of the current branch.
+// Expects a merged job, so that its variables include the global and predefined ones.
+// `changes` and `exists` can't be known without a pipeline, so they are assumed to match.
+pub fn is_included(job: &Job) -> Result<bool, GitLabError> {
+    let variables = &job.variables;
+
+    if let Some(rules) = job.other_keywords.get("rules") {
+        for rule in rules.as_sequence().into_iter().flatten() {
+            let matches = match rule.get("if").and_then(Value::as_str) {
+                Some(expression) => evaluate(expression, variables)?,
+                None => true,
+            };
+
+            if matches {
+                let when = rule
+                    .get("when")
+                    .and_then(Value::as_str)
+                    .or(job.when.as_deref());
+
+                return Ok(when != Some("never"));
+            }
+        }
+
+        return Ok(false);
+    }
+
+    let branch = value_of("CI_COMMIT_REF_NAME", variables).unwrap_or_default();
+    let only = match job.other_keywords.get("only") {
+        Some(only) => matches_refs(only, branch, variables)?,
+        None => true,
+    };
+    let except = match job.other_keywords.get("except") {
+        Some(except) => matches_refs(except, branch, variables)?,
+        None => false,
+    };
+
+    Ok(only && !except && job.when.as_deref() != Some("never"))
+}
+
+// `only` and `except` are either a list of refs or a mapping with `refs` and `variables`.
+// https://docs.gitlab.com/ee/ci/yaml/#only--except
+fn matches_refs(
+    keyword: &Value,
+    branch: &str,
+    variables: &[(String, String)],
+) -> Result<bool, GitLabError> {
+    let (refs, expressions) = match keyword {
+        Value::Mapping(_) => (keyword.get("refs"), keyword.get("variables")),
+        refs => (Some(refs), None),
+    };
+    let refs_match = match refs {
+        Some(refs) => strings_of(refs).iter().any(|r| matches_ref(r, branch)),
+        None => true,
+    };
+    let variables_match = match expressions {
+        Some(expressions) => {
+            let mut any = false;
+
+            for expression in strings_of(expressions) {
+                any = any || evaluate(&expression, variables)?;
+            }
+
+            any
+        }
+        None => true,
+    };
+
+    Ok(refs_match && variables_match)
+}
+
+fn matches_ref(r#ref: &str, branch: &str) -> bool {
+    match r#ref {
+        // Pipelines are always run as branch pipelines triggered by a push.
+        "branches" | "pushes" => true,
+        "api"
+        | "chat"
+        | "external"
+        | "external_pull_requests"
+        | "merge_requests"
+        | "pipelines"
+        | "schedules"
+        | "tags"
+        | "triggers"
+        | "web" => false,
+        pattern if pattern.len() > 1 && pattern.starts_with('/') => regex_of(pattern)
+            .map(|regex| regex.is_match(branch))
+            .unwrap_or(false),
+        name => name == branch,
+    }
+}
+
+fn strings_of(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(string) => vec![string.clone()],
+        Value::Sequence(values) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn value_of<'a>(name: &str, variables: &'a [(String, String)]) -> Option<&'a str> {
+    // Later definitions take precedence over earlier ones.
+    variables
+        .iter()
+        .rev()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+// Turns "/pattern/flags" into a regular expression.
+fn regex_of(literal: &str) -> Option<Regex> {
+    let end = literal.rfind('/').filter(|end| *end > 0)?;
+    let pattern = &literal[1..end];
+    let flags = &literal[end + 1..];
+    let pattern = if flags.contains('i') {
+        format!("(?i){}", pattern)
+    } else {
+        pattern.to_string()
+    };
+
+    Regex::new(&pattern).ok()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Variable(String),
+    Text(String),
+    Pattern(String),
+    Null,
+    Equals,
+    NotEquals,
+    Matches,
+    NotMatches,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+enum Operand {
+    Null,
+    Text(String),
+    Pattern(String),
+}
+
+// Evaluates expressions of `rules:if`, e.g. `$CI_COMMIT_BRANCH == "main" && $DEPLOY`.
+// https://docs.gitlab.com/ee/ci/jobs/job_control.html#cicd-variable-expressions
+pub fn evaluate(expression: &str, variables: &[(String, String)]) -> Result<bool, GitLabError> {
+    let invalid = |reason: &str| GitLabError::InvalidRule(expression.into(), reason.into());
+    let tokens = tokenize(expression).map_err(|reason| invalid(&reason))?;
+    let mut evaluation = Evaluation {
+        tokens: &tokens,
+        position: 0,
+        variables,
+    };
+    let result = evaluation.or().map_err(|reason| invalid(&reason))?;
+
+    if evaluation.position < tokens.len() {
+        return Err(invalid("unexpected trailing input"));
+    }
+
+    Ok(result)
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut characters = expression.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        let token = match character {
+            ' ' | '\t' => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '$' => {
+                let braced = characters.next_if_eq(&'{').is_some();
+                let mut name = String::new();
+
+                while let Some(c) = characters.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if braced && characters.next_if_eq(&'}').is_none() {
+                    return Err(format!("missing `}}` after `${{{}`", name));
+                }
+                if name.is_empty() {
+                    return Err("missing variable name after `$`".into());
+                }
+
+                Token::Variable(name)
+            }
+            '"' | '\'' => {
+                let mut text = String::new();
+
+                loop {
+                    match characters.next() {
+                        Some(c) if c == character => break,
+                        Some('\\') => text.extend(characters.next()),
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+
+                Token::Text(text)
+            }
+            '/' => {
+                let mut pattern = String::from("/");
+
+                loop {
+                    match characters.next() {
+                        Some('/') => break,
+                        Some('\\') if characters.peek() == Some(&'/') => {
+                            pattern.extend(characters.next())
+                        }
+                        Some(c) => pattern.push(c),
+                        None => return Err("unterminated regular expression".into()),
+                    }
+                }
+                pattern.push('/');
+                while let Some(flag) = characters.next_if(|c| c.is_ascii_alphabetic()) {
+                    pattern.push(flag);
+                }
+
+                Token::Pattern(pattern)
+            }
+            '=' | '!' | '&' | '|' => {
+                let token = match (character, characters.next()) {
+                    ('=', Some('=')) => Token::Equals,
+                    ('!', Some('=')) => Token::NotEquals,
+                    ('=', Some('~')) => Token::Matches,
+                    ('!', Some('~')) => Token::NotMatches,
+                    ('&', Some('&')) => Token::And,
+                    ('|', Some('|')) => Token::Or,
+                    _ => return Err(format!("unknown operator near `{}`", character)),
+                };
+
+                token
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut word = String::from(c);
+
+                while let Some(c) = characters.next_if(|c| c.is_ascii_alphabetic()) {
+                    word.push(c);
+                }
+                if word != "null" {
+                    return Err(format!("unexpected `{}`", word));
+                }
+
+                Token::Null
+            }
+            c => return Err(format!("unexpected `{}`", c)),
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+// Recursive descent over the tokens, evaluating while parsing.
+// `&&` binds stronger than `||`, just like in GitLab.
+struct Evaluation<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    variables: &'a [(String, String)],
+}
+
+impl Evaluation<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn next_if(&mut self, expected: Token) -> bool {
+        let found = self.tokens.get(self.position) == Some(&expected);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+
+        while self.next_if(Token::Or) {
+            result = self.and()? || result;
+        }
+
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.comparison()?;
+
+        while self.next_if(Token::And) {
+            result = self.comparison()? && result;
+        }
+
+        Ok(result)
+    }
+
+    fn comparison(&mut self) -> Result<bool, String> {
+        if self.next_if(Token::Open) {
+            let result = self.or()?;
+
+            if !self.next_if(Token::Close) {
+                return Err("missing `)`".into());
+            }
+
+            return Ok(result);
+        }
+
+        let left = self.operand()?;
+        let operator = match self.tokens.get(self.position) {
+            Some(Token::Equals | Token::NotEquals | Token::Matches | Token::NotMatches) => {
+                self.next()
+            }
+            _ => None,
+        };
+        let result = match operator {
+            Some(Token::Equals) => equals(&left, &self.operand()?),
+            Some(Token::NotEquals) => !equals(&left, &self.operand()?),
+            Some(Token::Matches) => matches(&left, &self.operand()?),
+            Some(Token::NotMatches) => !matches(&left, &self.operand()?),
+            _ => match left {
+                Operand::Null => false,
+                Operand::Text(text) => !text.is_empty(),
+                Operand::Pattern(_) => true,
+            },
+        };
+
+        Ok(result)
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let variables = self.variables;
+
+        match self.next() {
+            Some(Token::Variable(name)) => Ok(value_of(name, variables)
+                .map(|value| Operand::Text(value.into()))
+                .unwrap_or(Operand::Null)),
+            Some(Token::Text(text)) => Ok(Operand::Text(text.clone())),
+            Some(Token::Pattern(pattern)) => Ok(Operand::Pattern(pattern.clone())),
+            Some(Token::Null) => Ok(Operand::Null),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+}
+
+fn equals(left: &Operand, right: &Operand) -> bool {
+    match (left, right) {
+        (Operand::Null, Operand::Null) => true,
+        (Operand::Text(l), Operand::Text(r)) => l == r,
+        (Operand::Pattern(l), Operand::Pattern(r)) => l == r,
+        _ => false,
+    }
+}
+
+fn matches(left: &Operand, right: &Operand) -> bool {
+    // Variables can hold patterns as well, e.g. `$CI_COMMIT_BRANCH =~ $RELEASE_BRANCHES`.
+    let pattern = match right {
+        Operand::Pattern(pattern) | Operand::Text(pattern) => regex_of(pattern),
+        Operand::Null => None,
+    };
+
+    match (left, pattern) {
+        (Operand::Text(text), Some(regex)) => regex.is_match(text),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Vec<(String, String)> {
+        vec![
+            ("CI_COMMIT_REF_NAME".into(), "feature/list".into()),
+            ("CI_COMMIT_BRANCH".into(), "feature/list".into()),
+            ("EMPTY".into(), "".into()),
+            ("RELEASES".into(), "/^release-.*$/".into()),
+        ]
+    }
+
+    fn job(content: &str) -> Job {
+        let mut job = serde_yaml::from_str::<Job>(content).unwrap();
+        job.variables.splice(0..0, variables());
+        job
+    }
+
+    mod test_expressions {
+        use super::*;
+
+        fn eval(expression: &str) -> bool {
+            evaluate(expression, &variables()).unwrap()
+        }
+
+        #[test]
+        fn compares_variables_with_strings() {
+            assert!(eval("$CI_COMMIT_BRANCH == \"feature/list\""));
+            assert!(eval("$CI_COMMIT_BRANCH != 'main'"));
+            assert!(!eval("$CI_COMMIT_BRANCH == 'main'"));
+        }
+
+        #[test]
+        fn treats_undefined_variables_as_null() {
+            assert!(eval("$UNDEFINED == null"));
+            assert!(!eval("$EMPTY == null"));
+            assert!(!eval("$UNDEFINED"));
+        }
+
+        #[test]
+        fn checks_whether_variables_are_present() {
+            assert!(eval("$CI_COMMIT_BRANCH"));
+            assert!(!eval("$EMPTY"));
+        }
+
+        #[test]
+        fn matches_regular_expressions() {
+            assert!(eval("$CI_COMMIT_BRANCH =~ /^feature\\//"));
+            assert!(eval("$CI_COMMIT_BRANCH =~ /FEATURE/i"));
+            assert!(eval("$CI_COMMIT_BRANCH !~ /^release/"));
+            assert!(!eval("$UNDEFINED =~ /.*/"));
+        }
+
+        #[test]
+        fn matches_patterns_stored_in_variables() {
+            assert!(!eval("$CI_COMMIT_BRANCH =~ $RELEASES"));
+        }
+
+        #[test]
+        fn combines_conditions() {
+            assert!(eval("$CI_COMMIT_BRANCH && $UNDEFINED || $CI_COMMIT_BRANCH"));
+            assert!(!eval("$CI_COMMIT_BRANCH && ($UNDEFINED || $EMPTY)"));
+        }
+
+        #[test]
+        fn rejects_invalid_expressions() {
+            assert!(evaluate("$A ==", &[]).is_err());
+            assert!(evaluate("($A", &[]).is_err());
+            assert!(evaluate("$A = 'b'", &[]).is_err());
+            assert!(evaluate("'unterminated", &[]).is_err());
+        }
+    }
+
+    mod test_rules {
+        use super::*;
+
+        #[test]
+        fn includes_jobs_without_rules() {
+            assert!(is_included(&job("script: echo")).unwrap());
+        }
+
+        #[test]
+        fn uses_first_matching_rule() {
+            let job = job("
+                rules:
+                  - if: $CI_COMMIT_BRANCH =~ /^feature/
+                    when: never
+                  - when: always
+                ");
+
+            assert!(!is_included(&job).unwrap());
+        }
+
+        #[test]
+        fn excludes_jobs_without_matching_rule() {
+            let job = job("
+                rules:
+                  - if: $CI_COMMIT_BRANCH == 'main'
+                ");
+
+            assert!(!is_included(&job).unwrap());
+        }
+
+        #[test]
+        fn assumes_changes_to_match() {
+            let job = job("
+                rules:
+                  - changes: [src/**/*]
+                ");
+
+            assert!(is_included(&job).unwrap());
+        }
+
+        #[test]
+        fn applies_only_and_except() {
+            assert!(is_included(&job("only: [branches]")).unwrap());
+            assert!(!is_included(&job("only: [main, tags]")).unwrap());
+            assert!(is_included(&job("only: ['/^feature/']")).unwrap());
+            assert!(!is_included(&job("except: ['/^feature/']")).unwrap());
+            assert!(!is_included(&job("only: { variables: [$UNDEFINED] }")).unwrap());
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::gitlab::configuration::GitLabConfiguration;
+use crate::gitlab::error::GitLabError;
+use crate::gitlab::lint::Problem;
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::paths::PathChunk;
+use jsonschema::{Draft, JSONSchema, ValidationError};
+use serde_json::Value;
+
+// Vendored at the version pinned in `schema/SOURCE`, so that validating works offline and doesn't change
+// under our feet.
+const CI_SCHEMA: &str = include_str!("schema/ci.json");
+
+// Validates the configuration as written, merged with its includes, against GitLab's CI schema.
+// Unlike `lint`, this catches problems in keywords Fake CI doesn't model itself.
+pub fn validate(configuration: &GitLabConfiguration) -> Result<Vec<Problem>, GitLabError> {
+    let schema: Value = serde_json::from_str(CI_SCHEMA).map_err(GitLabError::schema)?;
+    let schema = JSONSchema::options()
+        .with_draft(Draft::Draft7)
+        .compile(&schema)
+        .map_err(|e| GitLabError::schema(e.to_string()))?;
+    let instance = serde_json::to_value(&configuration.raw).map_err(GitLabError::schema)?;
+
+    let problems = match schema.validate(&instance) {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .map(|error| problem_from(&error, configuration))
+            .collect(),
+    };
+
+    Ok(problems)
+}
+
+fn problem_from(error: &ValidationError, configuration: &GitLabConfiguration) -> Problem {
+    let path = error
+        .instance_path
+        .iter()
+        .map(|chunk| match chunk {
+            PathChunk::Property(name) => name.to_string(),
+            PathChunk::Index(index) => index.to_string(),
+            PathChunk::Keyword(keyword) => keyword.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let message = match &error.kind {
+        ValidationErrorKind::AdditionalProperties { unexpected } => {
+            format!("unknown keyword(s): {}", unexpected.join(", "))
+        }
+        _ => error.to_string(),
+    };
+    let job = path.first().and_then(|name| {
+        configuration
+            .jobs
+            .get(name)
+            .or_else(|| configuration.templates.get(name))
+            .map(|job| (name, job))
+    });
+
+    match job {
+        Some((name, job)) => {
+            let keyword = match &error.kind {
+                ValidationErrorKind::AdditionalProperties { unexpected } if path.len() == 1 => {
+                    unexpected.first().cloned()
+                }
+                _ => path.get(1).cloned(),
+            };
+            let message = match path.len() {
+                0..=2 => message,
+                _ => format!("{} (at `{}`)", message, path[1..].join(".")),
+            };
+
+            Problem {
+                name: Some(name.clone()),
+                source: job.source.clone(),
+                keyword,
+                message,
+                hint: Some("see https://docs.gitlab.com/ee/ci/yaml/".into()),
+            }
+        }
+        None => Problem {
+            name: None,
+            source: None,
+            keyword: None,
+            message: if path.is_empty() {
+                message
+            } else {
+                format!("`{}`: {}", path.join("."), message)
+            },
+            hint: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate_yaml(content: &str) -> Vec<String> {
+        let configuration = crate::gitlab::parse(content.as_bytes(), ".gitlab-ci.yml").unwrap();
+
+        validate(&configuration)
+            .unwrap()
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn vendored_schema_compiles() {
+        let schema: Value = serde_json::from_str(CI_SCHEMA).unwrap();
+
+        assert!(JSONSchema::compile(&schema).is_ok());
+    }
+
+    #[test]
+    fn accepts_valid_configuration() {
+        let problems = validate_yaml(
+            "
+            stages: [build]
+            variables:
+              GLOBAL: value
+            .template:
+              image: alpine
+            build:
+              extends: .template
+              stage: build
+              script:
+                - make
+              artifacts:
+                paths: [target]
+              retry: 2
+              tags: [docker]
+            ",
+        );
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn reports_keywords_unknown_to_gitlab() {
+        let problems = validate_yaml(
+            "
+            build:
+              script: make
+              retyr: 2
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `build` (from .gitlab-ci.yml:2): unknown keyword(s): retyr"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_values_of_keywords_fake_ci_does_not_model() {
+        let problems = validate_yaml(
+            "
+            build:
+              script: make
+              retry: 5
+            ",
+        );
+
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("job `build` (from .gitlab-ci.yml:2): "),
+            "{}",
+            problems[0]
+        );
+    }
+
+    #[test]
+    fn reports_keywords_unknown_to_gitlab_within_keywords_fake_ci_models() {
+        let problems = validate_yaml(
+            "
+            build:
+              script: make
+              artifacts:
+                paths: [target]
+                expier_in: 1 day
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `build` (from .gitlab-ci.yml:2): unknown keyword(s): expier_in"]
+        );
+    }
+
+    #[test]
+    fn validates_what_anchors_are_merged_into() {
+        let problems = validate_yaml(
+            "
+            .defaults: &defaults
+              retyr: 2
+            build:
+              <<: *defaults
+              script: make
+            ",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["job `build` (from .gitlab-ci.yml:4): unknown keyword(s): retyr"]
+        );
+    }
+
+    #[test]
+    fn reports_problems_outside_of_jobs_with_their_path() {
+        let problems = validate_yaml(
+            "
+            default:
+              imgae: alpine
+            ",
+        );
+
+        assert_eq!(problems, vec!["`default`: unknown keyword(s): imgae"]);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::gitlab::configuration::GitLabConfiguration;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// Where a job or template has been defined.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Source {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    // Where each keyword of the job starts.
+    pub keywords: HashMap<String, Position>,
+    // The whole file, shared between all jobs defined in it, to show snippets in diagnostics.
+    pub content: Arc<str>,
+}
+
+impl Source {
+    pub fn line_text(&self, line: usize) -> Option<&str> {
+        self.content.lines().nth(line.checked_sub(1)?)
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+// Human readable reference to a job for messages, e.g. "job `test` (from ci.yml:42)".
+pub fn describe(name: &str, source: Option<&Source>) -> String {
+    match source {
+        Some(source) => format!("{} (from {})", label(name), source),
+        None => label(name),
+    }
+}
+
+// Like `describe`, but without where the job is from, e.g. "job `test`".
+pub fn label(name: &str) -> String {
+    let kind = if name.starts_with('.') {
+        "template"
+    } else {
+        "job"
+    };
+
+    format!("{} `{}`", kind, name)
+}
+
+// Records for every job and template of a configuration in which file and on which line it starts.
+pub fn annotate_sources(configuration: &mut GitLabConfiguration, content: &str, file: &str) {
+    let positions = key_positions(content);
+    let content: Arc<str> = Arc::from(content);
+
+    for (name, job) in configuration
+        .jobs
+        .iter_mut()
+        .chain(configuration.templates.iter_mut())
+    {
+        job.source = positions.top_level.get(name).map(|position| Source {
+            file: file.to_string(),
+            line: position.line,
+            column: position.column,
+            keywords: positions.nested.get(name).cloned().unwrap_or_default(),
+            content: content.clone(),
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct KeyPositions {
+    pub top_level: HashMap<String, Position>,
+    // Keys of the mappings below top-level keys, i.e. the keywords of jobs.
+    pub nested: HashMap<String, HashMap<String, Position>>,
+}
+
+// `serde_yaml` does not expose any position information of what it deserialises.
+// Jobs and templates are always keys of the top-level mapping, so it's enough to run the
+// document through a second parser that reports where each of those keys and their own keys start.
+pub fn key_positions(content: &str) -> KeyPositions {
+    let mut receiver = KeyCollector::default();
+
+    // Syntax errors are reported by `serde_yaml` already. Whatever positions have been
+    // collected up to an error are still good to use.
+    let _ = Parser::new_from_str(content).load(&mut receiver, false);
+
+    receiver.positions
+}
+
+enum Node {
+    Mapping { expecting_key: bool },
+    Sequence,
+}
+
+#[derive(Default)]
+struct KeyCollector {
+    stack: Vec<Node>,
+    current_top_level_key: Option<String>,
+    positions: KeyPositions,
+}
+
+impl KeyCollector {
+    fn on_node(&mut self, key: Option<String>, mark: Marker) {
+        let depth = self.stack.len();
+        let in_job_mapping = depth == 2 && matches!(self.stack[0], Node::Mapping { .. });
+
+        if let Some(Node::Mapping { expecting_key }) = self.stack.last_mut() {
+            let position = Position {
+                line: mark.line(),
+                column: mark.col() + 1,
+            };
+
+            if *expecting_key && depth == 1 {
+                if let Some(key) = &key {
+                    self.positions
+                        .top_level
+                        .entry(key.clone())
+                        .or_insert(position);
+                }
+                self.current_top_level_key = key;
+            } else if *expecting_key && in_job_mapping {
+                if let (Some(parent), Some(key)) = (&self.current_top_level_key, key) {
+                    self.positions
+                        .nested
+                        .entry(parent.clone())
+                        .or_default()
+                        .entry(key)
+                        .or_insert(position);
+                }
+            }
+
+            *expecting_key = !*expecting_key;
+        }
+    }
+}
+
+impl MarkedEventReceiver for KeyCollector {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => self.on_node(Some(value), mark),
+            Event::Alias(..) => self.on_node(None, mark),
+            Event::MappingStart(..) => {
+                self.on_node(None, mark);
+                self.stack.push(Node::Mapping {
+                    expecting_key: true,
+                });
+            }
+            Event::SequenceStart(..) => {
+                self.on_node(None, mark);
+                self.stack.push(Node::Sequence);
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Option<Position> {
+        Some(Position { line, column })
+    }
+
+    #[test]
+    fn finds_positions_of_top_level_keys() {
+        let content = "stages:
+  - build
+
+build:
+  script:
+    - echo build
+
+'quoted job':
+  script: echo quoted
+";
+
+        let positions = key_positions(content);
+
+        assert_eq!(positions.top_level.get("stages").copied(), at(1, 1));
+        assert_eq!(positions.top_level.get("build").copied(), at(4, 1));
+        assert_eq!(positions.top_level.get("quoted job").copied(), at(8, 1));
+    }
+
+    #[test]
+    fn finds_positions_of_job_keywords_only() {
+        let content = "job:
+  script:
+    - echo
+  variables:
+    job: nested
+";
+
+        let positions = key_positions(content);
+        let keywords = &positions.nested["job"];
+
+        assert_eq!(positions.top_level.len(), 1);
+        assert_eq!(keywords.len(), 2);
+        assert_eq!(keywords.get("script").copied(), at(2, 3));
+        assert_eq!(keywords.get("variables").copied(), at(4, 3));
+    }
+
+    #[test]
+    fn annotates_jobs_and_templates_with_their_source() {
+        let content = "
+.template:
+  image: alpine
+
+job:
+  extends: .template
+";
+        let mut configuration = serde_yaml::from_str::<GitLabConfiguration>(content).unwrap();
+
+        annotate_sources(&mut configuration, content, "ci.yml");
+
+        let template_source = configuration.templates[".template"]
+            .source
+            .as_ref()
+            .unwrap();
+        assert_eq!(template_source.file, "ci.yml");
+        assert_eq!(template_source.line, 2);
+        assert_eq!(template_source.column, 1);
+        assert_eq!(template_source.keywords.get("image").copied(), at(3, 3));
+        assert_eq!(
+            configuration.jobs["job"]
+                .source
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "ci.yml:5"
+        );
+    }
+
+    #[test]
+    fn provides_text_of_lines() {
+        let source = Source {
+            content: Arc::from("first\nsecond\n"),
+            ..Default::default()
+        };
+
+        assert_eq!(source.line_text(2), Some("second"));
+        assert_eq!(source.line_text(0), None);
+        assert_eq!(source.line_text(3), None);
+    }
+
+    #[test]
+    fn displays_file_and_line() {
+        let source = Source {
+            file: ".gitlab/ci/test.yml".into(),
+            line: 42,
+            column: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(source.to_string(), ".gitlab/ci/test.yml:42");
+    }
+
+    #[test]
+    fn describes_jobs_and_templates() {
+        let source = Source {
+            file: "ci.yml".into(),
+            line: 3,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            describe("test", Some(&source)),
+            "job `test` (from ci.yml:3)"
+        );
+        assert_eq!(describe(".base", None), "template `.base`");
+    }
+}
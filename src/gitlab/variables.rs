@@ -5,12 +5,20 @@ use regex::Regex;
 const MAXIMUM_LENGTH_OF_SLUG: usize = 63;
 
 pub fn predefined_variables(git: &GitDetails) -> Vec<(String, String)> {
+    // Pipelines for tags have no branch.
+    let commit_ref = match &git.tag {
+        Some(tag) => ("CI_COMMIT_TAG".into(), tag.clone()),
+        None => ("CI_COMMIT_BRANCH".into(), git.branch_name.clone()),
+    };
+
     vec![
-        ("CI_COMMIT_REF_NAME".into(), git.branch_name.clone()),
-        ("CI_COMMIT_REF_SLUG".into(), ref_slug(&git.branch_name)),
+        commit_ref,
+        ("CI_COMMIT_REF_NAME".into(), git.ref_name().into()),
+        ("CI_COMMIT_REF_SLUG".into(), ref_slug(git.ref_name())),
         ("CI_COMMIT_SHA".into(), git.sha.clone()),
         ("CI_COMMIT_SHORT_SHA".into(), git.short_sha.clone()),
         ("CI_PIPELINE_ID".into(), "1000".into()),
+        ("CI_PIPELINE_SOURCE".into(), "push".into()),
         ("CI_PROJECT_DIR".into(), DIRECTORIES.job.into()),
     ]
 }
@@ -71,6 +79,7 @@ mod tests {
             branch_name: "branch-name".to_string(),
             sha: "1234567890abcde".to_string(),
             short_sha: "12345678".to_string(),
+            ..Default::default()
         };
         let variables = predefined_variables(&git);
 
@@ -88,6 +97,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn has_no_branch_for_tags() {
+        let git = GitDetails {
+            branch_name: "main".to_string(),
+            tag: Some("v1.0".to_string()),
+            ..Default::default()
+        };
+        let variables = predefined_variables(&git);
+
+        assert_eq!(value_of("CI_COMMIT_TAG", &variables), Some("v1.0".into()));
+        assert_eq!(
+            value_of("CI_COMMIT_REF_NAME", &variables),
+            Some("v1.0".into())
+        );
+        assert_eq!(value_of("CI_COMMIT_BRANCH", &variables), None);
+    }
+
     #[test]
     fn sanitizes_ref_name_for_slug() {
         // Rules as to what gets sanitized and how can be found at:
@@ -0,0 +1,125 @@
+use regex::Regex;
+
+// Wildcard paths for `include:local`: https://docs.gitlab.com/ee/ci/yaml/#includelocal
+//
+//     configs/*.yml     all .yml files in `configs`
+//     configs/**.yml    all .yml files in `configs` and any of its subfolders
+//     configs/**/*.yml  all .yml files only in subfolders of `configs`
+//
+// GitLab matches these against all files of the repository by translating `**` into
+// "any characters" and `*` into "any characters except `/`". The same is done here.
+
+pub fn is_wildcard(path: &str) -> bool {
+    path.contains('*')
+}
+
+// The directory up to the first wildcard, so that not the whole repository has to be searched.
+pub fn fixed_directory(pattern: &str) -> &str {
+    let pattern = pattern.trim_start_matches('/');
+    let up_to_wildcard = &pattern[..pattern.find('*').unwrap_or(pattern.len())];
+
+    match up_to_wildcard.rfind('/') {
+        Some(index) => &up_to_wildcard[..index],
+        None => "",
+    }
+}
+
+pub fn matching_files(pattern: &str, files: &[String]) -> Vec<String> {
+    let expression = to_regex(pattern.trim_start_matches('/'));
+    let mut matches = files
+        .iter()
+        .filter(|file| expression.is_match(file))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Results are in alphabetical order, the same as GitLab returns them in.
+    matches.sort();
+
+    matches
+}
+
+fn to_regex(pattern: &str) -> Regex {
+    let placeholder = "\u{0}";
+    let escaped = regex::escape(pattern)
+        .replace(r"\*\*", placeholder)
+        .replace(r"\*", "[^/]*")
+        .replace(placeholder, ".*");
+
+    Regex::new(&format!("^{}$", escaped)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<String> {
+        vec![
+            "configs/b.yml".into(),
+            "configs/a.yml".into(),
+            "configs/readme.md".into(),
+            "configs/nested/c.yml".into(),
+            "configs/nested/deeper/d.yml".into(),
+            "other/e.yml".into(),
+        ]
+    }
+
+    #[test]
+    fn detects_wildcards() {
+        assert!(is_wildcard("configs/*.yml"));
+        assert!(!is_wildcard("configs/file.yml"));
+    }
+
+    #[test]
+    fn single_asterisk_matches_files_of_one_directory() {
+        assert_eq!(
+            matching_files("configs/*.yml", &files()),
+            vec!["configs/a.yml".to_string(), "configs/b.yml".to_string()]
+        );
+    }
+
+    #[test]
+    fn double_asterisk_matches_files_of_directory_and_subfolders() {
+        assert_eq!(
+            matching_files("configs/**.yml", &files()),
+            vec![
+                "configs/a.yml".to_string(),
+                "configs/b.yml".to_string(),
+                "configs/nested/c.yml".to_string(),
+                "configs/nested/deeper/d.yml".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_asterisk_directory_matches_files_of_subfolders_only() {
+        assert_eq!(
+            matching_files("configs/**/*.yml", &files()),
+            vec![
+                "configs/nested/c.yml".to_string(),
+                "configs/nested/deeper/d.yml".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_leading_slash() {
+        assert_eq!(
+            matching_files("/other/*.yml", &files()),
+            vec!["other/e.yml"]
+        );
+    }
+
+    #[test]
+    fn treats_other_characters_literally() {
+        let files = vec!["a+b.yml".to_string(), "aab.yml".to_string()];
+
+        assert_eq!(matching_files("a+b*.yml", &files), vec!["a+b.yml"]);
+    }
+
+    #[test]
+    fn finds_directory_before_first_wildcard() {
+        assert_eq!(fixed_directory("/.gitlab/ci/**/*.yml"), ".gitlab/ci");
+        assert_eq!(fixed_directory("configs/*.yml"), "configs");
+        assert_eq!(fixed_directory("*.yml"), "");
+    }
+}
@@ -0,0 +1,179 @@
+// How a job's code gets checked out, by the variables GitLab Runner knows for it:
+// https://docs.gitlab.com/ee/ci/runners/configure_runners.html#git-strategy
+// Every job gets a fresh checkout, so `GIT_CLEAN_FLAGS` is left out: `git clean` would never find
+// anything to remove.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Checkout {
+    pub strategy: GitStrategy,
+    // Shallow fetches of that many commits, full ones if not set.
+    pub depth: Option<u32>,
+    pub submodules: SubmoduleStrategy,
+    // `false` fetches without updating the working tree.
+    pub checkout: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum GitStrategy {
+    // Both make a fresh clone, there's never an earlier checkout of the job to fetch into.
+    Clone,
+    #[default]
+    Fetch,
+    // No git operations, the job starts out without code.
+    None,
+    Empty,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum SubmoduleStrategy {
+    #[default]
+    None,
+    Normal,
+    Recursive,
+}
+
+impl Default for Checkout {
+    fn default() -> Self {
+        Checkout {
+            strategy: GitStrategy::default(),
+            depth: None,
+            submodules: SubmoduleStrategy::default(),
+            checkout: true,
+        }
+    }
+}
+
+impl Checkout {
+    // Unknown values are ignored, like GitLab Runner falls back to its defaults for them.
+    pub fn of(variables: &[(String, String)]) -> Self {
+        let value = |name: &str| {
+            variables
+                .iter()
+                .rev()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.trim().to_string())
+        };
+        let keyword = |name: &str| value(name).map(|value| value.to_lowercase());
+
+        Checkout {
+            strategy: match keyword("GIT_STRATEGY").as_deref() {
+                Some("clone") => GitStrategy::Clone,
+                Some("none") => GitStrategy::None,
+                Some("empty") => GitStrategy::Empty,
+                _ => GitStrategy::Fetch,
+            },
+            // `0` stands for full fetches.
+            depth: value("GIT_DEPTH")
+                .and_then(|depth| depth.parse().ok())
+                .filter(|depth| *depth > 0),
+            submodules: match keyword("GIT_SUBMODULE_STRATEGY").as_deref() {
+                Some("normal") => SubmoduleStrategy::Normal,
+                Some("recursive") => SubmoduleStrategy::Recursive,
+                _ => SubmoduleStrategy::None,
+            },
+            checkout: keyword("GIT_CHECKOUT").is_none_or(|value| value != "false"),
+        }
+    }
+
+    pub fn has_code(&self) -> bool {
+        matches!(self.strategy, GitStrategy::Clone | GitStrategy::Fetch)
+    }
+}
+
+// Initialises submodules, taking them from checkouts in `source_directory` where there are any and
+// from their URLs otherwise. Nested ones are looked up the same way with `Recursive`.
+pub fn submodule_commands(checkout: &Checkout, source_directory: &str) -> Option<String> {
+    let depth = checkout
+        .depth
+        .map(|depth| format!(" --depth {}", depth))
+        .unwrap_or_default();
+    // Local paths are only allowed as URLs of submodules when asked for explicitly.
+    let update = format!(
+        "if [ -f .gitmodules ]; then git submodule init --quiet; git config --file .gitmodules --get-regexp '\\.path$' | while read -r key path; do name=${{key#submodule.}}; name=${{name%.path}}; if [ -e \"$SOURCE/$path/.git\" ]; then git config \"submodule.$name.url\" \"$SOURCE/$path\"; fi; done; git -c protocol.file.allow=always submodule update --quiet{depth}; fi"
+    );
+    let top_level = format!("SOURCE=\"{}\"; {}", source_directory, update);
+
+    match checkout.submodules {
+        SubmoduleStrategy::None => None,
+        SubmoduleStrategy::Normal => Some(top_level),
+        SubmoduleStrategy::Recursive => Some(format!(
+            "{}; git submodule foreach --quiet --recursive \"{}\"",
+            top_level,
+            double_quoted(&format!(
+                "SOURCE=\"{}/$displaypath\"; {}",
+                source_directory, update
+            ))
+        )),
+    }
+}
+
+// Fills in LFS files from the objects the project has already, instead of downloading them.
+#[cfg_attr(test, allow(dead_code))]
+pub fn lfs_commands(project_directory: &str) -> String {
+    format!(
+        "if command -v git-lfs > /dev/null && [ -d \"{project_directory}/.git/lfs/objects\" ]; then mkdir -p .git/lfs; cp -R \"{project_directory}/.git/lfs/objects\" .git/lfs/; git lfs install --local --skip-smudge > /dev/null; git lfs checkout; fi"
+    )
+}
+
+// Escapes a value to be taken literally within double quotes.
+fn double_quoted(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('`', "\\`")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::variables;
+
+    #[test]
+    fn fetches_and_checks_out_everything_by_default() {
+        let checkout = Checkout::of(&[]);
+
+        assert_eq!(checkout, Checkout::default());
+        assert!(checkout.has_code());
+        assert_eq!(submodule_commands(&checkout, "/project"), None);
+    }
+
+    #[test]
+    fn takes_options_from_variables() {
+        let checkout = Checkout::of(&variables(&[
+            ("GIT_STRATEGY", "none"),
+            ("GIT_DEPTH", "10"),
+            ("GIT_SUBMODULE_STRATEGY", "recursive"),
+            ("GIT_CHECKOUT", "FALSE"),
+        ]));
+
+        assert_eq!(
+            checkout,
+            Checkout {
+                strategy: GitStrategy::None,
+                depth: Some(10),
+                submodules: SubmoduleStrategy::Recursive,
+                checkout: false,
+            }
+        );
+        assert!(!checkout.has_code());
+    }
+
+    #[test]
+    fn ignores_unknown_values_and_full_depths() {
+        let checkout = Checkout::of(&variables(&[
+            ("GIT_STRATEGY", "unknown"),
+            ("GIT_DEPTH", "0"),
+            ("GIT_SUBMODULE_STRATEGY", "sometimes"),
+        ]));
+
+        assert_eq!(checkout, Checkout::default());
+    }
+
+    #[test]
+    fn escapes_values_for_double_quotes() {
+        assert_eq!(
+            double_quoted("echo \"$HOME\" `pwd` \\"),
+            "echo \\\"\\$HOME\\\" \\`pwd\\` \\\\"
+        );
+    }
+}
@@ -0,0 +1,536 @@
+use crate::io::docker::DIRECTORIES;
+use crate::io::runtime::{
+    exit_status, ContainerRuntime, ContainerSpec, RuntimeError, DOCKERFILE_CONTENT,
+};
+use crate::io::shell::restore_state;
+use crate::io::variables::mask;
+use duct::cmd;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Runtimes without an API of their own, like nerdctl, driven by their Docker compatible CLI.
+pub struct Cli {
+    program: String,
+}
+
+impl Cli {
+    pub fn new(program: &str) -> Self {
+        Cli {
+            program: program.into(),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        cmd!(&self.program, "version")
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    fn ids(&self, arguments: &[&str]) -> Result<Vec<String>, RuntimeError> {
+        let output = cmd(&self.program, arguments).read()?;
+
+        Ok(output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn remove(&self, command: &[&str], ids: &[String]) -> Result<usize, RuntimeError> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut arguments = command.to_vec();
+        arguments.extend(ids.iter().map(String::as_str));
+        cmd(&self.program, arguments).stdout_null().run()?;
+
+        Ok(ids.len())
+    }
+}
+
+impl ContainerRuntime for Cli {
+    fn image_needs_to_be_built(&self, tag: &str) -> Result<bool, RuntimeError> {
+        let reference = format!("reference={}", tag);
+
+        Ok(self
+            .ids(&["image", "ls", "--quiet", "--filter", &reference])?
+            .is_empty())
+    }
+
+    fn build_image(&self, tag: &str, output: &mut dyn Write) -> Result<(), RuntimeError> {
+        let context = std::env::temp_dir().join(format!("fake-ci-build-{}", std::process::id()));
+        std::fs::create_dir_all(&context)?;
+        std::fs::write(context.join("Dockerfile"), DOCKERFILE_CONTENT)?;
+
+        let result = cmd!(&self.program, "build", "--tag", tag, &context)
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run();
+        std::fs::remove_dir_all(&context)?;
+        let result = result?;
+        output.write_all(&result.stdout)?;
+
+        if result.status.success() {
+            return Ok(());
+        }
+
+        Err(RuntimeError::BuildFailed(
+            tag.into(),
+            String::from_utf8_lossy(&result.stderr).trim().to_string(),
+        ))
+    }
+
+    fn remove_containers(&self, name: &str) -> Result<usize, RuntimeError> {
+        let filter = format!("name={}", name);
+        let ids = self.ids(&["ps", "--all", "--quiet", "--filter", &filter])?;
+
+        self.remove(&["rm", "--force"], &ids)
+    }
+
+    fn remove_volumes(&self, name: &str) -> Result<usize, RuntimeError> {
+        let filter = format!("name={}", name);
+        let names = self.ids(&["volume", "ls", "--quiet", "--filter", &filter])?;
+
+        self.remove(&["volume", "rm", "--force"], &names)
+    }
+
+    fn remove_images(&self, reference: &str) -> Result<usize, RuntimeError> {
+        let filter = format!("reference={}", reference);
+        let ids = self.ids(&["image", "ls", "--quiet", "--filter", &filter])?;
+
+        self.remove(&["image", "rm", "--force"], &ids)
+    }
+
+    // The CLI reports progress of pulling on stderr, which is kept for telling why it failed.
+    fn run_container(
+        &self,
+        name: &str,
+        spec: &ContainerSpec,
+        _output: &mut dyn Write,
+    ) -> Result<String, RuntimeError> {
+        let output = cmd(&self.program, run_arguments(name, spec))
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()?;
+
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+
+        let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if error.contains("not found") {
+            Err(RuntimeError::ImageNotFound(spec.image.clone()))
+        } else if error.contains("denied") || error.contains("unauthorized") {
+            Err(RuntimeError::PullDenied(spec.image.clone(), error))
+        } else {
+            Err(RuntimeError::Api(0, error))
+        }
+    }
+
+    fn execute(
+        &self,
+        container_id: &str,
+        commands: &str,
+        variables: &[(String, String)],
+        masked_values: &[String],
+        output: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        let env_file = EnvFile::write(variables)?;
+        write_multiline_variables(self, container_id, variables)?;
+        let arguments = vec![
+            "exec".to_string(),
+            "--env-file".into(),
+            env_file.path().display().to_string(),
+            container_id.into(),
+            "sh".into(),
+            "-c".into(),
+            format!("{}{}", source_multiline_variables(variables), commands),
+        ];
+
+        let reader = cmd(&self.program, arguments)
+            .stderr_to_stdout()
+            .unchecked()
+            .reader()?;
+        let mut lines = BufReader::new(&reader);
+        let mut line = vec![];
+
+        while lines.read_until(b'\n', &mut line)? > 0 {
+            output.write_all(mask(&String::from_utf8_lossy(&line), masked_values).as_bytes())?;
+            line.clear();
+        }
+
+        // Reading up to the end of the output waits for the process, so it has exited by now.
+        match reader.try_wait()? {
+            Some(output) => exit_status(output.status),
+            None => {
+                Err(std::io::Error::other("command kept running after its output ended").into())
+            }
+        }
+    }
+
+    fn open_shell(
+        &self,
+        container_id: &str,
+        directory: &str,
+        variables: &[(String, String)],
+    ) -> Result<(), RuntimeError> {
+        let env_file = EnvFile::write(variables)?;
+        write_multiline_variables(self, container_id, variables)?;
+        let arguments = shell_arguments(container_id, directory, &env_file, variables);
+
+        open_shell(cmd(&self.program, arguments))
+    }
+
+    fn write_file(
+        &self,
+        container_id: &str,
+        path: &str,
+        content: &str,
+    ) -> Result<(), RuntimeError> {
+        let commands =
+            format!("mkdir -p \"$(dirname '{path}')\" && cat > '{path}' && chmod a+r '{path}'");
+
+        cmd!(
+            &self.program,
+            "exec",
+            "--interactive",
+            "--user",
+            "0",
+            container_id,
+            "sh",
+            "-c",
+            commands
+        )
+        .stdin_bytes(content.as_bytes().to_vec())
+        .run()?;
+
+        Ok(())
+    }
+
+    fn kill_container(&self, name: &str) -> Result<(), RuntimeError> {
+        // Only running containers are listed without `--all`.
+        let filter = format!("name={}", name);
+        let ids = self.ids(&["ps", "--quiet", "--filter", &filter])?;
+
+        self.remove(&["kill"], &ids)?;
+
+        Ok(())
+    }
+}
+
+// Variables of jobs are handed to the CLI in a file. In the CLI's own environment, ones like
+// DOCKER_HOST or PATH would configure the CLI itself. On the command line, they'd be visible in the
+// list of processes. The file is removed again once dropped.
+pub struct EnvFile {
+    path: PathBuf,
+}
+
+impl EnvFile {
+    pub fn write(variables: &[(String, String)]) -> Result<Self, std::io::Error> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "fake-ci-env-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        let env_file = EnvFile { path };
+
+        for (name, value) in variables.iter().filter(|(_, value)| !value.contains('\n')) {
+            writeln!(file, "{}={}", name, value)?;
+        }
+
+        Ok(env_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for EnvFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Env files can't hold line breaks. Values with them (e.g. keys) are written into the container
+// instead, next to file variables, and exported by the shell from there. Single-quoted, so that
+// it takes them as they are. Never on the command line, where they'd be visible to everyone.
+const MULTILINE_VARIABLES: &str = "/job.tmp/.multiline-variables";
+
+pub fn write_multiline_variables(
+    runtime: &dyn ContainerRuntime,
+    container_id: &str,
+    variables: &[(String, String)],
+) -> Result<(), RuntimeError> {
+    let exports = multiline_variables(variables)
+        .map(|(name, value)| format!("export {}='{}'\n", name, value.replace('\'', "'\\''")))
+        .collect::<String>();
+
+    if exports.is_empty() {
+        return Ok(());
+    }
+
+    runtime.write_file(container_id, MULTILINE_VARIABLES, &exports)
+}
+
+fn source_multiline_variables(variables: &[(String, String)]) -> String {
+    match multiline_variables(variables).next() {
+        Some(_) => format!(". {}; ", MULTILINE_VARIABLES),
+        None => String::new(),
+    }
+}
+
+fn multiline_variables(variables: &[(String, String)]) -> impl Iterator<Item = &(String, String)> {
+    variables.iter().filter(|(_, value)| value.contains('\n'))
+}
+
+pub fn shell_arguments(
+    container_id: &str,
+    directory: &str,
+    env_file: &EnvFile,
+    variables: &[(String, String)],
+) -> Vec<String> {
+    vec![
+        "exec".to_string(),
+        "--interactive".into(),
+        "--tty".into(),
+        "--workdir".into(),
+        directory.into(),
+        "--env-file".into(),
+        env_file.path().display().to_string(),
+        container_id.into(),
+        "sh".into(),
+        "-c".into(),
+        format!(
+            "{}{}; command -v bash > /dev/null && exec bash || exec sh",
+            source_multiline_variables(variables),
+            restore_state(DIRECTORIES.step_state)
+        ),
+    ]
+}
+
+// Exiting the shell with an error isn't an error of Fake CI.
+pub fn open_shell(command: duct::Expression) -> Result<(), RuntimeError> {
+    command.unchecked().run()?;
+
+    Ok(())
+}
+
+fn run_arguments(name: &str, spec: &ContainerSpec) -> Vec<String> {
+    let mut arguments = vec!["run".to_string(), "--tty".into(), "--detach".into()];
+
+    for volume in &spec.volumes {
+        arguments.extend(["--volume".into(), volume.clone()]);
+    }
+    for (source, target) in &spec.binds {
+        arguments.extend(["--volume".into(), format!("{}:{}", source, target)]);
+    }
+    if let Some(container) = &spec.volumes_from {
+        arguments.extend(["--volumes-from".into(), container.clone()]);
+    }
+    for (name, value) in &spec.environment {
+        arguments.extend(["--env".into(), format!("{}={}", name, value)]);
+    }
+    arguments.extend(["--name".into(), name.into(), spec.image.clone()]);
+
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{temporary_path, Script};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn runs_containers_with_docker_compatible_arguments() {
+        let spec = ContainerSpec {
+            image: "alpine".into(),
+            volumes: vec!["/job".into()],
+            binds: vec![("/home/project".into(), "/project".into())],
+            volumes_from: Some("checkout-id".into()),
+            environment: vec![("CI_PROJECT_DIR".into(), "/job".into())],
+        };
+
+        assert_eq!(
+            run_arguments("fake-ci-job", &spec),
+            vec![
+                "run",
+                "--tty",
+                "--detach",
+                "--volume",
+                "/job",
+                "--volume",
+                "/home/project:/project",
+                "--volumes-from",
+                "checkout-id",
+                "--env",
+                "CI_PROJECT_DIR=/job",
+                "--name",
+                "fake-ci-job",
+                "alpine"
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_the_id_of_containers_that_have_been_run() {
+        // Stands in for the CLI, printing the ID of the container like `docker run --detach`.
+        let program = Script::new("run-cli", "#!/bin/sh\necho container-id\n");
+        let cli = Cli::new(program.path.to_str().unwrap());
+
+        let result = cli.run_container("fake-ci-job", &ContainerSpec::default(), &mut vec![]);
+
+        assert_eq!(result.unwrap(), "container-id");
+    }
+
+    #[test]
+    fn writes_variables_into_env_files_that_are_removed_again() {
+        let env_file = EnvFile::write(&[
+            ("TOKEN".into(), "two \"words\"".into()),
+            ("KEY".into(), "multiple\nlines".into()),
+        ])
+        .unwrap();
+        let path = env_file.path().to_path_buf();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "TOKEN=two \"words\"\n"
+        );
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        drop(env_file);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn opens_interactive_shells_with_variables_from_env_files() {
+        let variables = [
+            ("TOKEN".into(), "secret".into()),
+            ("KEY".into(), "it's\nmultiple lines".into()),
+        ];
+        let env_file = EnvFile::write(&variables).unwrap();
+        let path = env_file.path().display().to_string();
+
+        assert_eq!(
+            shell_arguments("container-id", "/job", &env_file, &variables),
+            vec![
+                "exec",
+                "--interactive",
+                "--tty",
+                "--workdir",
+                "/job",
+                "--env-file",
+                &path,
+                "container-id",
+                "sh",
+                "-c",
+                ". /job.tmp/.multiline-variables; if [ -f \"/tmp/fake-ci-step/variables\" ]; then . \"/tmp/fake-ci-step/variables\" 2> /dev/null; cd \"$(cat \"/tmp/fake-ci-step/directory\")\"; fi; command -v bash > /dev/null && exec bash || exec sh"
+            ]
+        );
+    }
+
+    #[test]
+    fn executes_commands_with_variables_and_masks_their_output() {
+        // Stands in for the CLI, printing the env file it's given and its own variable.
+        let program = Script::new(
+            "cli",
+            "#!/bin/sh\ncat \"$3\"\necho \"own: $SECRET\"\nexit 3\n",
+        );
+        let cli = Cli::new(program.path.to_str().unwrap());
+        let mut output = vec![];
+
+        let result = cli.execute(
+            "container-id",
+            "echo",
+            &[("SECRET".into(), "two \"words\"".into())],
+            &["two \"words\"".into()],
+            &mut output,
+        );
+
+        assert!(matches!(result, Err(RuntimeError::CommandFailed(3))));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "SECRET=[MASKED]\nown: \n"
+        );
+    }
+
+    #[test]
+    fn writes_multiline_variables_into_the_container_instead_of_the_command_line() {
+        // Stands in for the CLI, logging its arguments and what it's given to write.
+        let log = temporary_path("multiline-cli-log");
+        let written = temporary_path("multiline-cli-written");
+        let program = Script::new(
+            "multiline-cli",
+            &format!(
+                "#!/bin/sh\necho \"$*\" >> {}\nif [ \"$2\" = --interactive ]; then cat > {}; fi\n",
+                log.display(),
+                written.display()
+            ),
+        );
+        let cli = Cli::new(program.path.to_str().unwrap());
+
+        let result = cli.execute(
+            "container-id",
+            "echo",
+            &[("KEY".into(), "-----BEGIN KEY-----\nit's secret\n".into())],
+            &[],
+            &mut vec![],
+        );
+        let log_content = std::fs::read_to_string(&log).unwrap();
+        let written_content = std::fs::read_to_string(&written).unwrap();
+        let exported = cmd!("sh", "-c", ". \"$0\"; printf %s \"$KEY\"", &written)
+            .read()
+            .unwrap();
+        for file in [&log, &written] {
+            std::fs::remove_file(file).unwrap();
+        }
+
+        assert!(result.is_ok());
+        assert!(!log_content.contains("secret"), "{}", log_content);
+        assert!(log_content.contains("sh -c . /job.tmp/.multiline-variables; echo"));
+        assert_eq!(
+            written_content,
+            "export KEY='-----BEGIN KEY-----\nit'\\''s secret\n'\n"
+        );
+        assert_eq!(exported, "-----BEGIN KEY-----\nit's secret");
+    }
+
+    #[test]
+    fn fails_commands_killed_by_signals() {
+        let program = Script::new(
+            "killed-cli",
+            "#!/bin/sh
+echo started
+kill -9 $$
+",
+        );
+        let cli = Cli::new(program.path.to_str().unwrap());
+        let mut output = vec![];
+
+        let result = cli.execute("container-id", "echo", &[], &[], &mut output);
+
+        assert!(matches!(result, Err(RuntimeError::Killed(9))));
+        assert_eq!(String::from_utf8(output).unwrap(), "started\n");
+    }
+}
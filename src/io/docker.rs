@@ -1,7 +1,16 @@
+use crate::io::cli::{open_shell, shell_arguments, write_multiline_variables, EnvFile};
+use crate::io::docker_config::DockerConfig;
+use crate::io::http::{request, Endpoint, Response};
+use crate::io::runtime::{ContainerRuntime, ContainerSpec, RuntimeError, DOCKERFILE_CONTENT};
+use crate::io::variables::mask;
 use duct::cmd;
-use std::io::Error;
-
-const DOCKERFILE_CONTENT: &str = include_str!("../../Dockerfile");
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
 
 #[allow(dead_code)]
 pub struct Directories {
@@ -9,6 +18,8 @@ pub struct Directories {
     pub project: &'static str,
     pub job: &'static str,
     pub artifacts: &'static str,
+    pub file_variables: &'static str,
+    pub step_state: &'static str,
 }
 
 pub const DIRECTORIES: Directories = Directories {
@@ -16,201 +27,1066 @@ pub const DIRECTORIES: Directories = Directories {
     project: "/project",
     job: "/job",
     artifacts: "/artifacts",
+    // Same place GitLab Runner puts them: next to the project directory.
+    file_variables: "/job.tmp",
+    // Where variables and the working directory are kept between lines when stepping through a
+    // script. Outside of `/job`, so it never ends up in artifacts.
+    step_state: "/tmp/fake-ci-step",
 };
 
-pub fn image_needs_to_be_built(tag: &str) -> Result<bool, Error> {
-    let tag_id = cmd!(
-        "docker",
-        "image",
-        "ls",
-        "--quiet",
-        "--filter",
-        format!("reference={}", tag)
-    )
-    .read()?;
+// Progress and errors of pulls and builds are reported as a stream of JSON objects.
+#[derive(Deserialize, Debug, Default)]
+struct Progress {
+    #[serde(default)]
+    stream: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
 
-    Ok(tag_id.is_empty())
+#[derive(Deserialize)]
+struct Identifier {
+    #[serde(rename = "Id")]
+    id: String,
 }
 
-pub fn build_image(tag: &str) -> Result<(), Error> {
-    cmd!("echo", DOCKERFILE_CONTENT)
-        .pipe(cmd!("docker", "build", "-t", tag, "-"))
-        .run()?;
+#[cfg_attr(test, allow(dead_code))]
+pub struct Docker {
+    endpoint: Endpoint,
+    // For credentials of registries.
+    config: DockerConfig,
+    rootless: bool,
+    // The CLI for interactive shells, which the API can't hand a terminal to.
+    cli: &'static str,
+}
 
-    Ok(())
+// A client of the Docker Engine API, talking to it over a Unix socket or TCP.
+// Podman provides the same API, so it's used for both.
+#[cfg_attr(test, allow(dead_code))]
+impl Docker {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Docker::at(Endpoint::Unix(socket.into()))
+    }
+
+    pub fn at(endpoint: Endpoint) -> Self {
+        Docker {
+            endpoint,
+            config: DockerConfig::default(),
+            rootless: false,
+            cli: "docker",
+        }
+    }
+
+    pub fn with_config(self, config: DockerConfig) -> Self {
+        Docker { config, ..self }
+    }
+
+    pub fn podman(socket: impl Into<PathBuf>) -> Self {
+        Docker {
+            cli: "podman",
+            ..Docker::new(socket)
+        }
+    }
+
+    pub fn rootless(socket: impl Into<PathBuf>) -> Self {
+        Docker {
+            rootless: true,
+            ..Docker::podman(socket)
+        }
+    }
+
+    pub fn pull_image(&self, image: &str, output: &mut dyn Write) -> Result<(), RuntimeError> {
+        let (name, tag) = split_reference(image);
+        let auth = self.config.registry_auth(image).map(|auth| auth.header());
+        let headers = auth
+            .iter()
+            .map(|auth| ("X-Registry-Auth", auth.as_str()))
+            .collect::<Vec<_>>();
+        let response = self.send(
+            "POST",
+            &format!(
+                "/images/create?fromImage={}&tag={}",
+                encode(name),
+                encode(tag)
+            ),
+            &headers,
+            None,
+        )?;
+
+        let response = match response.status {
+            404 => return Err(RuntimeError::ImageNotFound(image.into())),
+            401 | 403 => {
+                return Err(RuntimeError::PullDenied(
+                    image.into(),
+                    message_of(response)?,
+                ))
+            }
+            _ => check(response)?,
+        };
+
+        for progress in progress_of(response) {
+            let progress = progress?;
+
+            if let Some(error) = progress.error {
+                return Err(pull_error(image, error));
+            }
+            // Only overall progress, not the one of every layer.
+            if let (Some(status), None) = (progress.status, progress.id) {
+                writeln!(output, "{}", status)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The user an image runs as by default, root if it doesn't say.
+    fn user_of(&self, image: &str, output: &mut dyn Write) -> Result<String, RuntimeError> {
+        let path = format!("/images/{}/json", image);
+        let mut response = self.call("GET", &path, None)?;
+
+        if response.status == 404 {
+            self.pull_image(image, output)?;
+            response = self.call("GET", &path, None)?;
+        }
+
+        let image: Value = parse(check(response)?)?;
+
+        Ok(image["Config"]["User"]
+            .as_str()
+            .filter(|user| !user.is_empty())
+            .unwrap_or("0")
+            .to_string())
+    }
+
+    fn was_out_of_memory(&self, container_id: &str) -> Result<bool, RuntimeError> {
+        let response = self.call("GET", &format!("/containers/{}/json", container_id), None)?;
+        let container: Value = parse(check(response)?)?;
+
+        Ok(container["State"]["OOMKilled"].as_bool().unwrap_or(false))
+    }
+
+    fn call(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Response, RuntimeError> {
+        let body = body.map(|body| ("application/json", body.to_string().into_bytes()));
+
+        self.send(method, path, &[], body)
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<(&str, Vec<u8>)>,
+    ) -> Result<Response, RuntimeError> {
+        request(&self.endpoint, method, path, headers, body)
+            .map_err(|e| RuntimeError::Unavailable(self.endpoint.to_string(), e))
+    }
 }
 
-#[cfg(not(test))]
-pub fn prune_containers() -> Result<usize, Error> {
-    let container_output = cmd!(
-        "docker",
-        "container",
-        "ls",
-        "--filter",
-        "name=fake-ci",
-        "--quiet"
-    )
-    .pipe(cmd!("xargs", "docker", "container", "rm", "-f"))
-    .read()?;
-
-    let container_lines = container_output
-        .split('\n')
-        .filter(|s| !s.is_empty())
-        .count();
-
-    Ok(container_lines)
-}
-
-#[cfg(not(test))]
-pub fn prune_volumes() -> Result<usize, Error> {
-    let volume_output = cmd!("docker", "volume", "ls", "--filter", "name=fake", "--quiet")
-        .pipe(cmd!("xargs", "docker", "volume", "rm", "-f"))
-        .read()?;
-
-    let volume_lines = volume_output.split('\n').filter(|s| !s.is_empty()).count();
-
-    Ok(volume_lines)
-}
-
-#[cfg(not(test))]
-pub fn prune_images() -> Result<usize, Error> {
-    let image_output = cmd!(
-        "docker",
-        "image",
-        "ls",
-        "--filter",
-        "reference=fake-ci",
-        "--quiet"
-    )
-    .pipe(cmd!("xargs", "docker", "image", "rm", "-f"))
-    .read()?;
-
-    let image_lines = image_output.split('\n').filter(|s| !s.is_empty()).count();
-
-    Ok(image_lines)
-}
-
-#[cfg(not(test))]
-pub fn prune_container(container_name: &str) -> Result<(), Error> {
-    cmd!(
-        "docker",
-        "ps",
-        "--all",
-        "--quiet",
-        "--filter",
-        format!("name={}", container_name),
-    )
-    .pipe(cmd!("xargs", "docker", "rm", "--force"))
-    .read()?;
+impl ContainerRuntime for Docker {
+    fn image_needs_to_be_built(&self, tag: &str) -> Result<bool, RuntimeError> {
+        let response = self.call("GET", &format!("/images/{}/json", tag), None)?;
 
-    Ok(())
+        if response.status == 404 {
+            return Ok(true);
+        }
+        check(response)?;
+
+        Ok(false)
+    }
+
+    fn build_image(&self, tag: &str, output: &mut dyn Write) -> Result<(), RuntimeError> {
+        let context = archive(&[("Dockerfile", DOCKERFILE_CONTENT.as_bytes())])?;
+        let response = self.send(
+            "POST",
+            &format!("/build?rm=true&t={}", encode(tag)),
+            &[],
+            Some(("application/x-tar", context)),
+        )?;
+
+        for progress in progress_of(check(response)?) {
+            let progress = progress?;
+
+            if let Some(error) = progress.error {
+                return Err(RuntimeError::BuildFailed(tag.into(), error));
+            }
+            if let Some(stream) = progress.stream {
+                write!(output, "{}", stream)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_containers(&self, name: &str) -> Result<usize, RuntimeError> {
+        let filters = encode(&json!({ "name": [name] }).to_string());
+        let response = self.call(
+            "GET",
+            &format!("/containers/json?all=true&filters={}", filters),
+            None,
+        )?;
+        let containers: Vec<Identifier> = parse(check(response)?)?;
+
+        for container in &containers {
+            let path = format!("/containers/{}?force=true", container.id);
+            check(self.call("DELETE", &path, None)?)?;
+        }
+
+        Ok(containers.len())
+    }
+
+    fn remove_volumes(&self, name: &str) -> Result<usize, RuntimeError> {
+        #[derive(Deserialize)]
+        struct Volumes {
+            #[serde(rename = "Volumes", default)]
+            volumes: Option<Vec<Volume>>,
+        }
+        #[derive(Deserialize)]
+        struct Volume {
+            #[serde(rename = "Name")]
+            name: String,
+        }
+
+        let filters = encode(&json!({ "name": [name] }).to_string());
+        let response = self.call("GET", &format!("/volumes?filters={}", filters), None)?;
+        let volumes = parse::<Volumes>(check(response)?)?
+            .volumes
+            .unwrap_or_default();
+
+        for volume in &volumes {
+            let path = format!("/volumes/{}?force=true", volume.name);
+            check(self.call("DELETE", &path, None)?)?;
+        }
+
+        Ok(volumes.len())
+    }
+
+    fn remove_images(&self, reference: &str) -> Result<usize, RuntimeError> {
+        let filters = encode(&json!({ "reference": [reference] }).to_string());
+        let response = self.call("GET", &format!("/images/json?filters={}", filters), None)?;
+        let images: Vec<Identifier> = parse(check(response)?)?;
+
+        for image in &images {
+            let path = format!("/images/{}?force=true", image.id);
+            check(self.call("DELETE", &path, None)?)?;
+        }
+
+        Ok(images.len())
+    }
+
+    fn run_container(
+        &self,
+        name: &str,
+        spec: &ContainerSpec,
+        output: &mut dyn Write,
+    ) -> Result<String, RuntimeError> {
+        let mut configuration = configuration_of(spec);
+        // Rootless, the host's user is root in containers. Files of the project would belong to
+        // root then, instead of to the same user ID as with Docker, which `keep-id` keeps them at.
+        // Podman would run containers as that user, too, unless the image's own one is given.
+        if self.rootless {
+            configuration["HostConfig"]["UsernsMode"] = json!("keep-id");
+            configuration["User"] = json!(self.user_of(&spec.image, output)?);
+        }
+        let path = format!("/containers/create?name={}", encode(name));
+        let mut response = self.call("POST", &path, Some(&configuration))?;
+
+        if response.status == 404 {
+            self.pull_image(&spec.image, output)?;
+            response = self.call("POST", &path, Some(&configuration))?;
+        }
+
+        let container: Identifier = parse(check(response)?)?;
+        check(self.call("POST", &format!("/containers/{}/start", container.id), None)?)?;
+
+        Ok(container.id)
+    }
+
+    fn execute(
+        &self,
+        container_id: &str,
+        commands: &str,
+        variables: &[(String, String)],
+        masked_values: &[String],
+        output: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        let environment = variables
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>();
+        let configuration = json!({
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "Env": environment,
+            "Cmd": ["sh", "-c", commands],
+        });
+        let response = self.call(
+            "POST",
+            &format!("/containers/{}/exec", container_id),
+            Some(&configuration),
+        )?;
+        let exec: Identifier = parse(check(response)?)?;
+        let response = self.call(
+            "POST",
+            &format!("/exec/{}/start", exec.id),
+            Some(&json!({ "Detach": false, "Tty": false })),
+        )?;
+
+        demultiplex(check(response)?.body, masked_values, output)?;
+
+        #[derive(Deserialize)]
+        struct Exec {
+            #[serde(rename = "ExitCode")]
+            exit_code: Option<i64>,
+            #[serde(rename = "Running", default)]
+            running: bool,
+        }
+
+        // The output can end before the command does, its exit code is only known once it's done.
+        let exit_code = loop {
+            let response = self.call("GET", &format!("/exec/{}/json", exec.id), None)?;
+            let state = parse::<Exec>(check(response)?)?;
+
+            if !state.running {
+                break state.exit_code;
+            }
+            sleep(Duration::from_millis(50));
+        };
+        let exit_code = exit_code.ok_or_else(|| {
+            RuntimeError::Api(200, format!("no exit code for command in {}", container_id))
+        })?;
+
+        if exit_code == 0 {
+            return Ok(());
+        }
+        if self.was_out_of_memory(container_id)? {
+            return Err(RuntimeError::OutOfMemory(container_id.into()));
+        }
+
+        Err(RuntimeError::CommandFailed(exit_code))
+    }
+
+    fn write_file(
+        &self,
+        container_id: &str,
+        path: &str,
+        content: &str,
+    ) -> Result<(), RuntimeError> {
+        let content = archive(&[(path.trim_start_matches('/'), content.as_bytes())])?;
+        let response = self.send(
+            "PUT",
+            &format!("/containers/{}/archive?path=%2F", container_id),
+            &[],
+            Some(("application/x-tar", content)),
+        )?;
+
+        check(response)?;
+
+        Ok(())
+    }
+
+    fn kill_container(&self, name: &str) -> Result<(), RuntimeError> {
+        let response = self.call("POST", &format!("/containers/{}/kill", name), None)?;
+
+        // Not found, or not running.
+        if matches!(response.status, 404 | 409) {
+            return Ok(());
+        }
+        check(response)?;
+
+        Ok(())
+    }
+
+    fn open_shell(
+        &self,
+        container_id: &str,
+        directory: &str,
+        variables: &[(String, String)],
+    ) -> Result<(), RuntimeError> {
+        // Both CLIs talk to the same endpoint the API is used on.
+        let host_option = if self.cli == "podman" {
+            "--url"
+        } else {
+            "--host"
+        };
+        let mut arguments = vec![host_option.to_string(), self.endpoint.to_string()];
+        let env_file = EnvFile::write(variables)?;
+        write_multiline_variables(self, container_id, variables)?;
+        arguments.extend(shell_arguments(
+            container_id,
+            directory,
+            &env_file,
+            variables,
+        ));
+
+        open_shell(cmd(self.cli, arguments))
+    }
+}
+
+fn configuration_of(spec: &ContainerSpec) -> Value {
+    let volumes = spec
+        .volumes
+        .iter()
+        .map(|volume| (volume.clone(), json!({})))
+        .collect::<serde_json::Map<_, _>>();
+    let binds = spec
+        .binds
+        .iter()
+        .map(|(source, target)| format!("{}:{}", source, target))
+        .collect::<Vec<_>>();
+    let environment = spec
+        .environment
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>();
+
+    json!({
+        "Image": spec.image,
+        "Tty": true,
+        "Env": environment,
+        "Volumes": volumes,
+        "HostConfig": {
+            "Binds": binds,
+            "VolumesFrom": spec.volumes_from.iter().collect::<Vec<_>>(),
+        },
+    })
 }
 
-#[cfg(not(test))]
-pub fn start_checkout_container(
-    container_name: &str,
-    image_tag: &str,
-    project_directory: &str,
-) -> Result<String, Error> {
-    let container_id = cmd!(
-        "docker",
-        "run",
-        "--tty",
-        "--detach",
-        "--volume",
-        format!("{}:{}", project_directory, DIRECTORIES.project),
-        "--volume",
-        DIRECTORIES.checkout,
-        "--volume",
-        format!("fake-ci-artifacts:{}", DIRECTORIES.artifacts),
-        "--volume",
-        DIRECTORIES.job,
-        "--name",
-        container_name,
-        image_tag
-    )
-    .read()?;
-
-    Ok(container_id)
-}
-
-#[cfg(not(test))]
-pub fn execute_commands(container_id: &str, commands: &str) -> Result<(), Error> {
-    cmd!("docker", "exec", container_id, "sh", "-c", commands).run()?;
+fn check(response: Response) -> Result<Response, RuntimeError> {
+    if response.is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status;
+
+    Err(RuntimeError::Api(status, message_of(response)?))
+}
+
+// Errors come as `{"message": "..."}`.
+fn message_of(response: Response) -> Result<String, RuntimeError> {
+    let text = response.text()?;
+
+    Ok(serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|value| value["message"].as_str().map(str::to_string))
+        .unwrap_or(text))
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(response: Response) -> Result<T, RuntimeError> {
+    let text = response.text()?;
+
+    serde_json::from_str(&text).map_err(|e| RuntimeError::IO(e.into()))
+}
+
+fn progress_of(response: Response) -> impl Iterator<Item = Result<Progress, RuntimeError>> {
+    BufReader::new(response.body)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?).unwrap_or_default()))
+}
+
+fn pull_error(image: &str, error: String) -> RuntimeError {
+    if error.contains("not found") || error.contains("manifest unknown") {
+        RuntimeError::ImageNotFound(image.into())
+    } else if error.contains("denied") || error.contains("unauthorized") {
+        RuntimeError::PullDenied(image.into(), error)
+    } else {
+        RuntimeError::Api(200, error)
+    }
+}
+
+// `registry:5000/group/image:tag` into the image and its tag, which is `latest` when there is none.
+fn split_reference(image: &str) -> (&str, &str) {
+    if let Some((name, digest)) = image.split_once('@') {
+        return (name, digest);
+    }
+
+    match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (image, "latest"),
+    }
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+// Without a TTY, output of `exec` is sent in frames: one byte for the stream (stdout or stderr),
+// three unused ones, four for the size of the payload that follows.
+fn demultiplex(
+    stream: Box<dyn Read>,
+    masked_values: &[String],
+    output: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let mut stream = BufReader::new(stream);
+    let mut header = [0; 8];
+    // Masking needs whole lines, a value could be split across frames.
+    let mut line = vec![];
+
+    loop {
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut payload = vec![0; size];
+        stream.read_exact(&mut payload)?;
+
+        if masked_values.is_empty() {
+            output.write_all(&payload)?;
+            continue;
+        }
+
+        for byte in payload {
+            line.push(byte);
+
+            if byte == b'\n' {
+                output
+                    .write_all(mask(&String::from_utf8_lossy(&line), masked_values).as_bytes())?;
+                line.clear();
+            }
+        }
+    }
+
+    if !line.is_empty() {
+        output.write_all(mask(&String::from_utf8_lossy(&line), masked_values).as_bytes())?;
+    }
+    output.flush()?;
 
     Ok(())
 }
 
-#[cfg(not(test))]
-pub fn start_job_container(
-    container_name: &str,
-    image_tag: &str,
-    source_container_id: &str,
-) -> Result<String, Error> {
-    let container_id = cmd!(
-        "docker",
-        "run",
-        "--tty",
-        "--detach",
-        "--volumes-from",
-        source_container_id,
-        "--env",
-        format!("CI_PROJECT_DIR={}", DIRECTORIES.job),
-        "--name",
-        container_name,
-        image_tag
-    )
-    .read()?;
-
-    Ok(container_id)
+// A tar archive of regular files, which is what the API expects for build contexts and copies.
+fn archive(files: &[(&str, &[u8])]) -> Result<Vec<u8>, RuntimeError> {
+    let mut archive = vec![];
+
+    for (path, content) in files {
+        match split_path(path) {
+            Some((prefix, name)) => {
+                archive.extend_from_slice(&tar_header(prefix, name, content.len(), b'0')?);
+            }
+            // Too long even for the prefix field, the path goes into an extended header instead.
+            None => {
+                let record = pax_record("path", path);
+                let name = &path.as_bytes()[..NAME_FIELD_SIZE];
+
+                archive.extend_from_slice(&tar_header(b"", b"PaxHeader", record.len(), b'x')?);
+                append_padded(&mut archive, &record);
+                archive.extend_from_slice(&tar_header(b"", name, content.len(), b'0')?);
+            }
+        }
+        append_padded(&mut archive, content);
+    }
+
+    // Two empty blocks mark the end.
+    archive.resize(archive.len() + 1024, 0);
+    Ok(archive)
+}
+
+const NAME_FIELD_SIZE: usize = 100;
+const PREFIX_FIELD_SIZE: usize = 155;
+// Sizes are written as eleven octal digits.
+const MAXIMUM_FILE_SIZE: usize = 0o77777777777;
+
+// Paths longer than the name field are split into a prefix and a name at one of their slashes.
+fn split_path(path: &str) -> Option<(&[u8], &[u8])> {
+    let path = path.as_bytes();
+
+    if path.len() <= NAME_FIELD_SIZE {
+        return Some((b"", path));
+    }
+
+    path.iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'/')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(prefix, name)| {
+            !prefix.is_empty() && prefix.len() <= PREFIX_FIELD_SIZE && name.len() <= NAME_FIELD_SIZE
+        })
+}
+
+fn tar_header(
+    prefix: &[u8],
+    name: &[u8],
+    size: usize,
+    kind: u8,
+) -> Result<[u8; 512], RuntimeError> {
+    if size > MAXIMUM_FILE_SIZE {
+        return Err(RuntimeError::IO(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} bytes are too many for a file in an archive", size),
+        )));
+    }
+
+    let mut header = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name);
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0");
+    field(148, b"        ");
+    field(156, &[kind]);
+    field(257, b"ustar\0");
+    field(263, b"00");
+    field(345, prefix);
+
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    Ok(header)
+}
+
+// `<length> <key>=<value>\n`, where the length includes the digits of the length itself.
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = format!(" {}={}\n", key, value);
+    let mut length = rest.len();
+
+    while length != length.to_string().len() + rest.len() {
+        length = length.to_string().len() + rest.len();
+    }
+
+    format!("{}{}", length, rest).into_bytes()
+}
+
+// Content fills up whole blocks.
+fn append_padded(archive: &mut Vec<u8>, content: &[u8]) {
+    archive.extend_from_slice(content);
+    archive.resize(archive.len().div_ceil(512) * 512, 0);
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::io::docker_config::tests::ConfigDirectory;
+    use crate::io::http::tests::{json_response, FakeServer};
+    use crate::io::runtime::select_runtime;
+    use crate::settings::structure::RuntimeKind;
+    use base64::engine::general_purpose::URL_SAFE;
+    use base64::Engine;
+
+    fn frame(stream: u8, payload: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload.as_bytes());
+
+        frame
+    }
 
-    const TEST_IMAGE_TAG: &str = "fake-ci:test";
+    fn exec_responses(output: &[u8], exit_code: i64) -> Vec<Vec<u8>> {
+        let mut stream =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/vnd.docker.raw-stream\r\n\r\n".to_vec();
+        stream.extend_from_slice(output);
 
-    fn clean_up_test_artifacts() {
-        cmd!(
-            "docker",
-            "image",
-            "ls",
-            "--filter",
-            format!("reference={}", TEST_IMAGE_TAG),
-            "--quiet"
-        )
-        .pipe(cmd!("xargs", "docker", "image", "rm", "-f"))
-        .run()
-        .unwrap();
+        vec![
+            json_response(201, r#"{"Id": "exec-id"}"#).into_bytes(),
+            stream,
+            json_response(
+                200,
+                &format!(r#"{{"ExitCode": {}, "Running": false}}"#, exit_code),
+            )
+            .into_bytes(),
+        ]
     }
 
     #[test]
-    #[cfg_attr(not(feature = "docker_tests"), ignore)]
     fn identifies_image_tags_that_need_to_be_built() {
-        assert!(image_needs_to_be_built("tag-that-does-not-exist").unwrap());
+        let server = FakeServer::start(vec![json_response(404, r#"{"message": "No such image"}"#)]);
+        let docker = Docker::new(&server.socket);
+
+        assert!(docker.image_needs_to_be_built("fake-ci:1.0").unwrap());
+        assert_eq!(server.request_lines(), vec!["GET /images/fake-ci:1.0/json"]);
+    }
+
+    #[test]
+    fn executes_commands_with_their_variables() {
+        let output = [frame(1, "out\n"), frame(2, "err\n")].concat();
+        let server = FakeServer::start(exec_responses(&output, 0));
+        let docker = Docker::new(&server.socket);
+        let variables = vec![("KEY".to_string(), "line one\n\"$(id)\"".to_string())];
+        let mut written = vec![];
+
+        docker
+            .execute("container-id", "echo", &variables, &[], &mut written)
+            .unwrap();
+
+        let requests = server.requests.lock().unwrap();
+        let exec: Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(exec["Env"], json!(["KEY=line one\n\"$(id)\""]));
+        assert_eq!(exec["Cmd"], json!(["sh", "-c", "echo"]));
+        assert_eq!(requests[1].0, "POST /exec/exec-id/start");
+        assert_eq!(String::from_utf8(written).unwrap(), "out\nerr\n");
+    }
+
+    #[test]
+    fn masks_values_split_across_frames() {
+        let output = [frame(1, "token: sec"), frame(1, "ret\n")].concat();
+        let server = FakeServer::start(exec_responses(&output, 0));
+        let docker = Docker::new(&server.socket);
+        let mut written = vec![];
+
+        docker
+            .execute(
+                "container-id",
+                "echo",
+                &[],
+                &["secret".into()],
+                &mut written,
+            )
+            .unwrap();
+
+        assert_eq!(String::from_utf8(written).unwrap(), "token: [MASKED]\n");
+    }
+
+    #[test]
+    fn reports_exit_codes_of_failed_commands() {
+        let mut responses = exec_responses(b"", 2);
+        responses.push(json_response(200, r#"{"State": {"OOMKilled": false}}"#).into_bytes());
+        let server = FakeServer::start(responses);
+        let docker = Docker::new(&server.socket);
+
+        let error = docker
+            .execute("container-id", "exit 2", &[], &[], &mut vec![])
+            .unwrap_err();
+
+        assert!(matches!(error, RuntimeError::CommandFailed(2)));
+    }
+
+    #[test]
+    fn waits_for_commands_to_finish_before_taking_their_exit_code() {
+        let mut responses = exec_responses(b"", 2);
+        responses.insert(
+            2,
+            json_response(200, r#"{"ExitCode": null, "Running": true}"#).into_bytes(),
+        );
+        responses.push(json_response(200, r#"{"State": {"OOMKilled": false}}"#).into_bytes());
+        let server = FakeServer::start(responses);
+        let docker = Docker::new(&server.socket);
+
+        let error = docker
+            .execute("container-id", "exit 2", &[], &[], &mut vec![])
+            .unwrap_err();
+
+        assert!(matches!(error, RuntimeError::CommandFailed(2)));
+        assert_eq!(
+            server.request_lines()[2..4],
+            ["GET /exec/exec-id/json", "GET /exec/exec-id/json"]
+        );
+    }
+
+    #[test]
+    fn does_not_take_commands_without_exit_code_as_successful() {
+        let mut responses = exec_responses(b"", 0);
+        responses[2] = json_response(200, r#"{"ExitCode": null, "Running": false}"#).into_bytes();
+        let server = FakeServer::start(responses);
+        let docker = Docker::new(&server.socket);
+
+        let error = docker
+            .execute("container-id", "echo", &[], &[], &mut vec![])
+            .unwrap_err();
+
+        assert!(matches!(error, RuntimeError::Api(_, _)));
+    }
+
+    #[test]
+    fn reports_containers_running_out_of_memory() {
+        let mut responses = exec_responses(b"", 137);
+        responses.push(json_response(200, r#"{"State": {"OOMKilled": true}}"#).into_bytes());
+        let server = FakeServer::start(responses);
+        let docker = Docker::new(&server.socket);
+
+        let error = docker
+            .execute("container-id", "allocate", &[], &[], &mut vec![])
+            .unwrap_err();
+
+        assert!(matches!(error, RuntimeError::OutOfMemory(_)));
+    }
+
+    #[test]
+    fn kills_containers_unless_they_are_not_running() {
+        let server = FakeServer::start(vec![
+            json_response(204, ""),
+            json_response(409, r#"{"message": "container is not running"}"#),
+            json_response(404, r#"{"message": "No such container: fake-ci-job"}"#),
+            json_response(500, r#"{"message": "cannot kill"}"#),
+        ]);
+        let docker = Docker::new(&server.socket);
+
+        docker.kill_container("fake-ci-job").unwrap();
+        docker.kill_container("fake-ci-job").unwrap();
+        docker.kill_container("fake-ci-job").unwrap();
+        let error = docker.kill_container("fake-ci-job").unwrap_err();
+
+        assert!(matches!(error, RuntimeError::Api(500, _)));
+        assert_eq!(
+            server.request_lines()[0],
+            "POST /containers/fake-ci-job/kill"
+        );
+    }
+
+    #[test]
+    fn pulls_missing_images_when_running_containers() {
+        let server = FakeServer::start(vec![
+            json_response(404, r#"{"message": "No such image: alpine:3.18"}"#),
+            json_response(200, r#"{"status": "Pulling from library/alpine"}"#),
+            json_response(201, r#"{"Id": "container-id"}"#),
+            json_response(204, ""),
+        ]);
+        let docker = Docker::new(&server.socket);
+        let mut progress = vec![];
+
+        let id = docker
+            .run_container(
+                "fake-ci-job",
+                &ContainerSpec {
+                    image: "alpine:3.18".into(),
+                    ..Default::default()
+                },
+                &mut progress,
+            )
+            .unwrap();
+
+        assert_eq!(id, "container-id");
+        assert_eq!(
+            String::from_utf8(progress).unwrap(),
+            "Pulling from library/alpine\n"
+        );
+        assert_eq!(
+            server.request_lines(),
+            vec![
+                "POST /containers/create?name=fake%2Dci%2Djob",
+                "POST /images/create?fromImage=alpine&tag=3%2E18",
+                "POST /containers/create?name=fake%2Dci%2Djob",
+                "POST /containers/container-id/start",
+            ]
+        );
+    }
+
+    #[test]
+    fn sends_credentials_of_the_registry_when_pulling() {
+        let directory = ConfigDirectory::with_config(
+            r#"{"auths": {"registry.example.com": {"auth": "dXNlcjpzZWNyZXQ="}}}"#,
+        );
+        let server = FakeServer::start(vec![
+            json_response(200, r#"{"status": "Downloaded"}"#),
+            json_response(200, r#"{"status": "Downloaded"}"#),
+        ]);
+        let docker =
+            Docker::new(&server.socket).with_config(DockerConfig::in_directory(&directory.path));
+
+        docker
+            .pull_image("registry.example.com/private", &mut vec![])
+            .unwrap();
+        docker.pull_image("alpine", &mut vec![]).unwrap();
+
+        let headers = server.headers.lock().unwrap();
+        let auth = headers[0]
+            .iter()
+            .find_map(|header| header.strip_prefix("X-Registry-Auth: "))
+            .unwrap();
+        let auth: Value = serde_json::from_slice(&URL_SAFE.decode(auth).unwrap()).unwrap();
+        assert_eq!(
+            auth,
+            json!({
+                "username": "user",
+                "password": "secret",
+                "serveraddress": "registry.example.com",
+            })
+        );
+        assert!(!headers[1]
+            .iter()
+            .any(|header| header.starts_with("X-Registry-Auth")));
+    }
+
+    #[test]
+    fn keeps_user_ids_and_the_user_of_images_when_rootless() {
+        let server = FakeServer::start(vec![
+            json_response(200, r#"{"Config": {"User": "node"}}"#),
+            json_response(201, r#"{"Id": "container-id"}"#),
+            json_response(204, ""),
+        ]);
+        let podman = Docker::rootless(&server.socket);
+
+        podman
+            .run_container(
+                "fake-ci-job",
+                &ContainerSpec {
+                    image: "node:20".into(),
+                    ..Default::default()
+                },
+                &mut vec![],
+            )
+            .unwrap();
+
+        let requests = server.requests.lock().unwrap();
+        let configuration: Value = serde_json::from_str(&requests[1].1).unwrap();
+        assert_eq!(requests[0].0, "GET /images/node:20/json");
+        assert_eq!(configuration["HostConfig"]["UsernsMode"], "keep-id");
+        assert_eq!(configuration["User"], "node");
+    }
+
+    #[test]
+    fn reports_images_that_cannot_be_found() {
+        let server = FakeServer::start(vec![json_response(
+            404,
+            r#"{"message": "pull access denied for unknown, repository does not exist"}"#,
+        )]);
+        let docker = Docker::new(&server.socket);
+
+        let error = docker.pull_image("unknown", &mut vec![]).unwrap_err();
+
+        assert!(matches!(error, RuntimeError::ImageNotFound(image) if image == "unknown"));
+    }
+
+    #[test]
+    fn reports_denied_pulls() {
+        let server = FakeServer::start(vec![json_response(
+            200,
+            r#"{"error": "unauthorized: authentication required"}"#,
+        )]);
+        let docker = Docker::new(&server.socket);
+
+        let error = docker
+            .pull_image("registry.example.com/private", &mut vec![])
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "pulling image registry.example.com/private was denied: unauthorized: authentication required"
+        );
+    }
+
+    #[test]
+    fn removes_all_matching_containers() {
+        let server = FakeServer::start(vec![
+            json_response(200, r#"[{"Id": "first"}, {"Id": "second"}]"#),
+            json_response(204, ""),
+            json_response(204, ""),
+        ]);
+        let docker = Docker::new(&server.socket);
+
+        assert_eq!(docker.remove_containers("fake-ci").unwrap(), 2);
+        assert_eq!(
+            server.request_lines()[1..],
+            [
+                "DELETE /containers/first?force=true",
+                "DELETE /containers/second?force=true"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_when_docker_is_not_available() {
+        let docker = Docker::new("/does/not/exist.sock");
+
+        let error = docker.image_needs_to_be_built("fake-ci").unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("cannot talk to the container runtime at unix:///does/not/exist.sock"));
+    }
+
+    #[test]
+    fn configures_containers_from_their_spec() {
+        let spec = ContainerSpec {
+            image: "alpine".into(),
+            volumes: vec!["/job".into()],
+            binds: vec![("fake-ci-artifacts".into(), "/artifacts".into())],
+            volumes_from: Some("checkout-id".into()),
+            environment: vec![("CI_PROJECT_DIR".into(), "/job".into())],
+        };
+
+        assert_eq!(
+            configuration_of(&spec),
+            json!({
+                "Image": "alpine",
+                "Tty": true,
+                "Env": ["CI_PROJECT_DIR=/job"],
+                "Volumes": { "/job": {} },
+                "HostConfig": {
+                    "Binds": ["fake-ci-artifacts:/artifacts"],
+                    "VolumesFrom": ["checkout-id"],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn splits_image_references_into_name_and_tag() {
+        assert_eq!(split_reference("alpine"), ("alpine", "latest"));
+        assert_eq!(split_reference("rust:1.65"), ("rust", "1.65"));
+        assert_eq!(
+            split_reference("registry:5000/group/image"),
+            ("registry:5000/group/image", "latest")
+        );
+        assert_eq!(
+            split_reference("alpine@sha256:abc"),
+            ("alpine", "sha256:abc")
+        );
+    }
+
+    #[test]
+    fn archives_files_as_tar() {
+        let archive = archive(&[("job.tmp/KEY", b"value")]).unwrap();
+
+        assert_eq!(archive.len(), 512 + 512 + 1024);
+        assert_eq!(&archive[0..11], b"job.tmp/KEY");
+        assert_eq!(&archive[124..136], b"00000000005\0");
+        assert_eq!(&archive[512..517], b"value");
+    }
+
+    #[test]
+    fn splits_long_paths_into_prefix_and_name() {
+        let directory = "d".repeat(150);
+        let name = "n".repeat(90);
+        let path = format!("{}/{}", directory, name);
+
+        let archive = archive(&[(&path, b"value")]).unwrap();
+
+        assert_eq!(archive.len(), 512 + 512 + 1024);
+        assert_eq!(&archive[0..90], name.as_bytes());
+        assert_eq!(&archive[345..495], directory.as_bytes());
+    }
+
+    #[test]
+    fn puts_paths_too_long_for_the_prefix_into_extended_headers() {
+        let path = format!("{}KEY", "d/".repeat(150));
+
+        let archive = archive(&[(&path, b"value")]).unwrap();
+        let record = format!("313 path={}\n", path);
+
+        assert_eq!(archive[156], b'x');
+        assert_eq!(&archive[512..512 + record.len()], record.as_bytes());
+        assert_eq!(archive[1024 + 156], b'0');
+        assert_eq!(&archive[1536..1541], b"value");
     }
 
     #[test]
     #[cfg_attr(not(feature = "docker_tests"), ignore)]
     fn builds_new_images_successfully() {
-        clean_up_test_artifacts();
-
-        build_image(TEST_IMAGE_TAG).unwrap();
+        const TEST_IMAGE_TAG: &str = "fake-ci:test";
+        let docker = select_runtime(Some(RuntimeKind::Docker));
+        docker.remove_images(TEST_IMAGE_TAG).unwrap();
 
-        let new_image_id = cmd!(
-            "docker",
-            "image",
-            "ls",
-            "--quiet",
-            "--filter",
-            format!("reference={}", TEST_IMAGE_TAG),
-        )
-        .read()
-        .unwrap();
+        docker
+            .build_image(TEST_IMAGE_TAG, &mut std::io::sink())
+            .unwrap();
 
-        assert!(!new_image_id.is_empty());
+        assert!(!docker.image_needs_to_be_built(TEST_IMAGE_TAG).unwrap());
 
-        clean_up_test_artifacts();
+        docker.remove_images(TEST_IMAGE_TAG).unwrap();
     }
 }
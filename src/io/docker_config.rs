@@ -0,0 +1,413 @@
+use crate::io::http::Endpoint;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use base64::Engine;
+use duct::cmd;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Docker Hub is known by this key, for historical reasons.
+const DOCKER_HUB: &str = "https://index.docker.io/v1/";
+// Credential helpers return this as the user name of identity tokens.
+const IDENTITY_TOKEN_USERNAME: &str = "<token>";
+
+// What the Docker CLI is configured with in `~/.docker` (or `$DOCKER_CONFIG`), so that Fake CI
+// talks to the same daemon the CLI does:
+// https://docs.docker.com/engine/reference/commandline/cli/#configuration-files
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerConfig {
+    #[serde(skip)]
+    directory: PathBuf,
+    #[serde(default)]
+    current_context: Option<String>,
+    #[serde(default)]
+    auths: HashMap<String, StoredAuth>,
+    // Helpers of single registries, which win over the one for all of them.
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+    #[serde(default)]
+    creds_store: Option<String>,
+}
+
+// Credentials as `docker login` stores them without a helper.
+#[derive(Deserialize, Debug, Default)]
+struct StoredAuth {
+    // Base64 of `<username>:<password>`.
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    identitytoken: Option<String>,
+}
+
+// What the Engine API expects in `X-Registry-Auth` for pulls.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct RegistryAuth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identitytoken: Option<String>,
+    serveraddress: String,
+}
+
+impl RegistryAuth {
+    pub fn header(&self) -> String {
+        URL_SAFE.encode(serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+// `contexts/meta/<hash of the name>/meta.json`, as written by `docker context create`.
+#[derive(Deserialize)]
+struct ContextMeta {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Endpoints", default)]
+    endpoints: ContextEndpoints,
+}
+
+#[derive(Deserialize, Default)]
+struct ContextEndpoints {
+    docker: Option<ContextEndpoint>,
+}
+
+#[derive(Deserialize)]
+struct ContextEndpoint {
+    #[serde(rename = "Host")]
+    host: String,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl DockerConfig {
+    pub fn load() -> Self {
+        let directory = std::env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".docker")))
+            .unwrap_or_default();
+
+        DockerConfig::in_directory(directory)
+    }
+
+    // A missing or invalid configuration is treated like an empty one, as the CLI does.
+    pub fn in_directory(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        let config = std::fs::read_to_string(directory.join("config.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str::<DockerConfig>(&content).ok())
+            .unwrap_or_default();
+
+        DockerConfig {
+            directory,
+            ..config
+        }
+    }
+
+    pub fn current_context(&self) -> Option<&str> {
+        self.current_context.as_deref()
+    }
+
+    // Endpoint of a context, e.g. one that Colima or Rancher Desktop created.
+    pub fn context_endpoint(&self, name: &str) -> Endpoint {
+        let unsupported = |reason: &str| Endpoint::Unsupported {
+            host: format!("Docker context {}", name),
+            reason: reason.into(),
+        };
+        let contexts = self.directory.join("contexts");
+        let context = std::fs::read_dir(contexts.join("meta"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .find_map(|entry| {
+                let content = std::fs::read_to_string(entry.path().join("meta.json")).ok()?;
+                let meta = serde_json::from_str::<ContextMeta>(&content).ok()?;
+
+                (meta.name == name).then(|| (entry.file_name(), meta))
+            });
+
+        let Some((id, meta)) = context else {
+            return unsupported("the context does not exist");
+        };
+        let Some(endpoint) = meta.endpoints.docker else {
+            return unsupported("the context has no Docker endpoint");
+        };
+        let uses_tls = contexts.join("tls").join(id).join("docker").exists();
+
+        match Endpoint::parse(&endpoint.host) {
+            Endpoint::Tcp(_) if uses_tls => unsupported("TLS connections are not supported"),
+            endpoint => endpoint,
+        }
+    }
+
+    // Credentials for the registry of an image, looked up like the CLI does: the registry's own
+    // helper, then the one for all registries, then what has been stored in the configuration.
+    pub fn registry_auth(&self, image: &str) -> Option<RegistryAuth> {
+        let registry = registry_of(image);
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|(server, _)| host_of(server) == host_of(registry))
+            .map(|(_, helper)| helper)
+            .or(self.creds_store.as_ref());
+
+        helper
+            .and_then(|helper| ask_helper(&format!("docker-credential-{}", helper), registry))
+            .or_else(|| self.stored_auth(registry))
+    }
+
+    fn stored_auth(&self, registry: &str) -> Option<RegistryAuth> {
+        let (_, stored) = self
+            .auths
+            .iter()
+            .find(|(server, _)| host_of(server) == host_of(registry))?;
+
+        if let Some(token) = &stored.identitytoken {
+            return Some(RegistryAuth {
+                identitytoken: Some(token.clone()),
+                serveraddress: registry.into(),
+                ..Default::default()
+            });
+        }
+
+        let decoded = STANDARD.decode(stored.auth.as_ref()?).ok()?;
+        let (username, password) = String::from_utf8(decoded)
+            .ok()?
+            .split_once(':')
+            .map(|(username, password)| (username.to_string(), password.to_string()))?;
+
+        Some(RegistryAuth {
+            username: Some(username),
+            password: Some(password),
+            serveraddress: registry.into(),
+            ..Default::default()
+        })
+    }
+}
+
+// `<program> get` reads the server from stdin and answers with its credentials, if it has some:
+// https://github.com/docker/docker-credential-helpers
+fn ask_helper(program: &str, registry: &str) -> Option<RegistryAuth> {
+    #[derive(Deserialize)]
+    struct Credentials {
+        #[serde(rename = "Username")]
+        username: String,
+        #[serde(rename = "Secret")]
+        secret: String,
+    }
+
+    let output = cmd!(program, "get")
+        .stdin_bytes(registry)
+        .stdout_capture()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let credentials = serde_json::from_slice::<Credentials>(&output.stdout).ok()?;
+
+    Some(match credentials.username.as_str() {
+        IDENTITY_TOKEN_USERNAME => RegistryAuth {
+            identitytoken: Some(credentials.secret),
+            serveraddress: registry.into(),
+            ..Default::default()
+        },
+        _ => RegistryAuth {
+            username: Some(credentials.username),
+            password: Some(credentials.secret),
+            serveraddress: registry.into(),
+            ..Default::default()
+        },
+    })
+}
+
+// The registry of an image reference, Docker Hub for images without one. Like Docker, the first
+// part of a name is only taken for a registry when it looks like a host.
+fn registry_of(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains(['.', ':']) || first == "localhost" => match first {
+            "docker.io" | "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB,
+            _ => first,
+        },
+        _ => DOCKER_HUB,
+    }
+}
+
+// Servers are stored with and without scheme, e.g. `https://registry.example.com/v1/`.
+fn host_of(server: &str) -> &str {
+    let server = server
+        .strip_prefix("https://")
+        .or_else(|| server.strip_prefix("http://"))
+        .unwrap_or(server);
+
+    server.split('/').next().unwrap_or(server)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::test_support::{Script, TemporaryDirectory};
+    use std::ops::Deref;
+
+    // A configuration directory, removed again once dropped.
+    pub struct ConfigDirectory(TemporaryDirectory);
+
+    impl ConfigDirectory {
+        pub fn with_config(config: &str) -> Self {
+            let directory = TemporaryDirectory::new("docker-config");
+            std::fs::write(directory.path.join("config.json"), config).unwrap();
+
+            ConfigDirectory(directory)
+        }
+
+        pub fn add_context(&self, id: &str, name: &str, host: &str) {
+            let directory = self.path.join("contexts/meta").join(id);
+            std::fs::create_dir_all(&directory).unwrap();
+            std::fs::write(
+                directory.join("meta.json"),
+                format!(
+                    r#"{{"Name": "{}", "Metadata": {{}}, "Endpoints": {{"docker": {{"Host": "{}", "SkipTLSVerify": false}}}}}}"#,
+                    name, host
+                ),
+            )
+            .unwrap();
+        }
+    }
+
+    impl Deref for ConfigDirectory {
+        type Target = TemporaryDirectory;
+
+        fn deref(&self) -> &TemporaryDirectory {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn reads_the_current_context() {
+        let directory = ConfigDirectory::with_config(r#"{"currentContext": "colima"}"#);
+
+        let config = DockerConfig::in_directory(&directory.path);
+
+        assert_eq!(config.current_context(), Some("colima"));
+    }
+
+    #[test]
+    fn treats_missing_configurations_as_empty() {
+        let config = DockerConfig::in_directory("/does/not/exist");
+
+        assert_eq!(config.current_context(), None);
+    }
+
+    #[test]
+    fn finds_endpoints_of_contexts_by_their_name() {
+        let directory = ConfigDirectory::with_config("{}");
+        directory.add_context("a1", "remote", "tcp://192.168.1.5:2375");
+        directory.add_context("b2", "colima", "unix:///home/me/.colima/docker.sock");
+
+        let config = DockerConfig::in_directory(&directory.path);
+
+        assert_eq!(
+            config.context_endpoint("colima"),
+            Endpoint::Unix("/home/me/.colima/docker.sock".into())
+        );
+        assert_eq!(
+            config.context_endpoint("remote"),
+            Endpoint::Tcp("192.168.1.5:2375".into())
+        );
+    }
+
+    #[test]
+    fn finds_registries_of_images() {
+        assert_eq!(registry_of("alpine"), DOCKER_HUB);
+        assert_eq!(registry_of("library/alpine:3.18"), DOCKER_HUB);
+        assert_eq!(registry_of("docker.io/library/alpine"), DOCKER_HUB);
+        assert_eq!(
+            registry_of("registry.example.com/group/image:1.0"),
+            "registry.example.com"
+        );
+        assert_eq!(registry_of("localhost:5000/image"), "localhost:5000");
+    }
+
+    #[test]
+    fn takes_stored_credentials_of_the_registry() {
+        let directory = ConfigDirectory::with_config(
+            r#"{"auths": {
+                "https://index.docker.io/v1/": {"auth": "aHViOnNlY3JldA=="},
+                "registry.example.com": {"identitytoken": "token"}
+            }}"#,
+        );
+        let config = DockerConfig::in_directory(&directory.path);
+
+        assert_eq!(
+            config.registry_auth("alpine"),
+            Some(RegistryAuth {
+                username: Some("hub".into()),
+                password: Some("secret".into()),
+                serveraddress: DOCKER_HUB.into(),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            config.registry_auth("registry.example.com/image"),
+            Some(RegistryAuth {
+                identitytoken: Some("token".into()),
+                serveraddress: "registry.example.com".into(),
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.registry_auth("other.example.com/image"), None);
+    }
+
+    #[test]
+    fn asks_credential_helpers() {
+        let helper = Script::new(
+            "helper",
+            "#!/bin/sh\nread server\necho \"{\\\"Username\\\": \\\"user\\\", \\\"Secret\\\": \\\"for $server\\\"}\"\n",
+        );
+
+        let auth = ask_helper(helper.path.to_str().unwrap(), "registry.example.com");
+        let missing = ask_helper("/does/not/exist", "registry.example.com");
+
+        assert_eq!(
+            auth,
+            Some(RegistryAuth {
+                username: Some("user".into()),
+                password: Some("for registry.example.com".into()),
+                serveraddress: "registry.example.com".into(),
+                ..Default::default()
+            })
+        );
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn encodes_headers_as_url_safe_base64_json() {
+        let auth = RegistryAuth {
+            identitytoken: Some("token".into()),
+            serveraddress: "registry.example.com".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            URL_SAFE.decode(auth.header()).unwrap(),
+            br#"{"identitytoken":"token","serveraddress":"registry.example.com"}"#
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_contexts_and_ones_using_tls() {
+        let directory = ConfigDirectory::with_config("{}");
+        directory.add_context("a1", "secure", "tcp://192.168.1.5:2376");
+        std::fs::create_dir_all(directory.path.join("contexts/tls/a1/docker")).unwrap();
+
+        let config = DockerConfig::in_directory(&directory.path);
+
+        assert!(matches!(
+            config.context_endpoint("secure"),
+            Endpoint::Unsupported { reason, .. } if reason == "TLS connections are not supported"
+        ));
+        assert!(matches!(
+            config.context_endpoint("unknown"),
+            Endpoint::Unsupported { host, .. } if host == "Docker context unknown"
+        ));
+    }
+}
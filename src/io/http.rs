@@ -0,0 +1,414 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+// The Engine API listens on this port when it's exposed unencrypted.
+const DEFAULT_TCP_PORT: u16 = 2375;
+
+// Where the Engine API is served, e.g. by `DOCKER_HOST` or a Docker context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    // `host:port`, unencrypted.
+    Tcp(String),
+    // Fails every request with the reason, instead of failing commands that don't need the API.
+    Unsupported { host: String, reason: String },
+}
+
+impl Endpoint {
+    pub fn parse(host: &str) -> Self {
+        if let Some(path) = host.strip_prefix("unix://") {
+            return Endpoint::Unix(path.into());
+        }
+        if let Some(address) = host.strip_prefix("tcp://") {
+            let address = address.trim_end_matches('/');
+            let has_port = address
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+
+            return match has_port {
+                true => Endpoint::Tcp(address.into()),
+                false => Endpoint::Tcp(format!("{}:{}", address, DEFAULT_TCP_PORT)),
+            };
+        }
+
+        Endpoint::Unsupported {
+            host: host.into(),
+            reason: "only unix:// and tcp:// hosts are supported".into(),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            Endpoint::Unsupported { host, .. } => write!(f, "{}", host),
+        }
+    }
+}
+
+// Just enough HTTP/1.1 to talk to the Docker Engine API over its Unix socket.
+// Every request uses its own connection, which also makes hijacked streams (like `exec`) simple:
+// they are read until the Engine closes the connection.
+pub struct Response {
+    pub status: u16,
+    pub body: Box<dyn Read>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn text(mut self) -> Result<String, Error> {
+        let mut text = String::new();
+        self.body.read_to_string(&mut text)?;
+
+        Ok(text)
+    }
+}
+
+pub fn request(
+    endpoint: &Endpoint,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<(&str, Vec<u8>)>,
+) -> Result<Response, Error> {
+    match endpoint {
+        Endpoint::Unix(socket) => send(UnixStream::connect(socket)?, method, path, headers, body),
+        Endpoint::Tcp(address) => send(TcpStream::connect(address)?, method, path, headers, body),
+        Endpoint::Unsupported { reason, .. } => {
+            Err(Error::new(ErrorKind::Unsupported, reason.clone()))
+        }
+    }
+}
+
+fn send<S: Read + Write + 'static>(
+    mut stream: S,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<(&str, Vec<u8>)>,
+) -> Result<Response, Error> {
+    let mut head = format!("{method} {path} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n");
+
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    if let Some((content_type, content)) = &body {
+        head.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n",
+            content_type,
+            content.len()
+        ));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    if let Some((_, content)) = body {
+        stream.write_all(&content)?;
+    }
+
+    read_response(BufReader::new(stream))
+}
+
+fn read_response<R: BufRead + 'static>(mut reader: R) -> Result<Response, Error> {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+
+    // e.g. `HTTP/1.1 200 OK`
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid(format!("invalid status line `{}`", status_line.trim())))?;
+    let mut content_length = None;
+    let mut chunked = false;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse::<u64>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => {}
+        }
+    }
+
+    let body: Box<dyn Read> = if chunked {
+        Box::new(Chunked {
+            reader,
+            remaining: 0,
+            done: false,
+        })
+    } else if let Some(length) = content_length {
+        Box::new(reader.take(length))
+    } else {
+        Box::new(reader)
+    };
+
+    Ok(Response { status, body })
+}
+
+struct Chunked<R> {
+    reader: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if self.done || buffer.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            // Sizes can be followed by extensions, e.g. `1a;name=value`.
+            let size = line.trim().split(';').next().unwrap_or_default();
+
+            self.remaining = usize::from_str_radix(size, 16)
+                .map_err(|_| invalid(format!("invalid chunk size `{}`", line.trim())))?;
+
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let length = buffer.len().min(self.remaining);
+        let read = self.reader.read(&mut buffer[..length])?;
+        if read == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        self.remaining -= read;
+
+        if self.remaining == 0 {
+            let mut line_ending = String::new();
+            self.reader.read_line(&mut line_ending)?;
+        }
+
+        Ok(read)
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::test_support::temporary_path;
+    use std::io::Cursor;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // Answers requests on a temporary Unix socket with the given raw responses, in order.
+    // Keeps the request lines (e.g. `GET /images/json`), bodies and headers for assertions.
+    pub struct FakeServer {
+        pub socket: PathBuf,
+        pub requests: Arc<Mutex<Vec<(String, String)>>>,
+        pub headers: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    impl FakeServer {
+        pub fn start(responses: Vec<impl Into<Vec<u8>> + Send + 'static>) -> Self {
+            let socket = temporary_path("server").with_extension("sock");
+            let _ = std::fs::remove_file(&socket);
+            let listener = UnixListener::bind(&socket).unwrap();
+            let requests = Arc::new(Mutex::new(vec![]));
+            let recorded = requests.clone();
+            let headers = Arc::new(Mutex::new(vec![]));
+            let recorded_headers = headers.clone();
+
+            thread::spawn(move || {
+                for response in responses {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    let mut length = 0;
+                    let mut header_lines = vec![];
+                    reader.read_line(&mut request_line).unwrap();
+
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = line.strip_prefix("Content-Length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                        header_lines.push(line.trim().to_string());
+                    }
+                    recorded_headers.lock().unwrap().push(header_lines);
+
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    recorded.lock().unwrap().push((
+                        request_line.trim().replace(" HTTP/1.1", ""),
+                        String::from_utf8_lossy(&body).to_string(),
+                    ));
+
+                    (&stream).write_all(&response.into()).unwrap();
+                }
+            });
+
+            FakeServer {
+                socket,
+                requests,
+                headers,
+            }
+        }
+
+        pub fn request_lines(&self) -> Vec<String> {
+            let requests = self.requests.lock().unwrap();
+
+            requests.iter().map(|(line, _)| line.clone()).collect()
+        }
+    }
+
+    impl Drop for FakeServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.socket);
+        }
+    }
+
+    pub fn json_response(status: u16, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    fn response(raw: &str) -> Response {
+        read_response(Cursor::new(raw.as_bytes().to_vec())).unwrap()
+    }
+
+    #[test]
+    fn reads_bodies_of_given_length() {
+        let response = response("HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\r\nvalueignored");
+
+        assert_eq!(response.status, 404);
+        assert!(!response.is_success());
+        assert_eq!(response.text().unwrap(), "value");
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let response = response(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nfirst\r\na;ext=1\r\n second ½\r\n0\r\n\r\n",
+        );
+
+        assert!(response.is_success());
+        assert_eq!(response.text().unwrap(), "first second ½");
+    }
+
+    #[test]
+    fn reads_streams_until_the_connection_is_closed() {
+        let response = response("HTTP/1.1 200 OK\r\n\r\nall of the output");
+
+        assert_eq!(response.text().unwrap(), "all of the output");
+    }
+
+    #[test]
+    fn parses_hosts_of_endpoints() {
+        assert_eq!(
+            Endpoint::parse("unix:///var/run/docker.sock"),
+            Endpoint::Unix("/var/run/docker.sock".into())
+        );
+        assert_eq!(
+            Endpoint::parse("tcp://192.168.1.5:2375"),
+            Endpoint::Tcp("192.168.1.5:2375".into())
+        );
+        assert_eq!(
+            Endpoint::parse("tcp://docker.local/"),
+            Endpoint::Tcp("docker.local:2375".into())
+        );
+        assert!(matches!(
+            Endpoint::parse("ssh://me@docker.local"),
+            Endpoint::Unsupported { .. }
+        ));
+    }
+
+    #[test]
+    fn fails_requests_to_unsupported_endpoints_with_the_reason() {
+        let endpoint = Endpoint::parse("ssh://me@docker.local");
+
+        let error = request(&endpoint, "GET", "/version", &[], None)
+            .err()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            error.to_string(),
+            "only unix:// and tcp:// hosts are supported"
+        );
+    }
+
+    #[test]
+    fn sends_requests_over_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .unwrap();
+            stream
+                .write_all(json_response(200, "{}").as_bytes())
+                .unwrap();
+
+            request_line
+        });
+
+        let response = request(&Endpoint::Tcp(address), "GET", "/version", &[], None).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(server.join().unwrap(), "GET /version HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn sends_requests_over_the_socket() {
+        let server = FakeServer::start(vec![json_response(201, "{}")]);
+
+        let response = request(
+            &Endpoint::Unix(server.socket.clone()),
+            "POST",
+            "/containers/create",
+            &[],
+            Some(("application/json", b"{\"Image\":\"alpine\"}".to_vec())),
+        )
+        .unwrap();
+
+        assert_eq!(response.status, 201);
+        assert_eq!(
+            *server.requests.lock().unwrap(),
+            vec![(
+                "POST /containers/create".to_string(),
+                "{\"Image\":\"alpine\"}".to_string()
+            )]
+        );
+    }
+}
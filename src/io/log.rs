@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::io::{Error, Write};
+use std::time::Instant;
+
+const CLEAR_LINE: &str = "\x1b[0K";
+const HEADER_STYLE: &str = "\x1b[1;36m";
+const DURATION_STYLE: &str = "\x1b[2m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogOptions {
+    // Prefix every line with `[job-name]`, e.g. to tell apart jobs running one after another.
+    pub prefix: bool,
+    // Prefix every line with the time passed since the job started.
+    pub timestamps: bool,
+    // Colour headers of sections, only for terminals. Log files are kept plain.
+    pub colours: bool,
+}
+
+// Output of a job as it's shown on the terminal and kept in its log file.
+// Collapsible sections (`section_start`/`section_end` markers) are rendered as headers.
+// The log file always has timestamps, but no prefixes since it belongs to a single job.
+pub struct JobLog<'a> {
+    name: String,
+    options: LogOptions,
+    terminal: &'a mut dyn Write,
+    file: Option<Box<dyn Write>>,
+    started: Instant,
+    line: Vec<u8>,
+    // Sections that have been started, with the time they have been started at.
+    sections: HashMap<String, u64>,
+}
+
+enum Line<'a> {
+    SectionStart {
+        name: &'a str,
+        timestamp: u64,
+        header: &'a str,
+    },
+    SectionEnd {
+        name: &'a str,
+        timestamp: u64,
+    },
+    Output(&'a str),
+}
+
+impl<'a> JobLog<'a> {
+    pub fn new(
+        name: &str,
+        options: LogOptions,
+        terminal: &'a mut dyn Write,
+        file: Option<Box<dyn Write>>,
+    ) -> Self {
+        JobLog {
+            name: name.into(),
+            options,
+            terminal,
+            file,
+            started: Instant::now(),
+            line: vec![],
+            sections: HashMap::new(),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        let (text, style) = match parse(line) {
+            Line::SectionStart {
+                name,
+                timestamp,
+                header,
+            } => {
+                self.sections.insert(name.into(), timestamp);
+                let header = if header.is_empty() { name } else { header };
+
+                (format!("» {}", header), Some(HEADER_STYLE))
+            }
+            Line::SectionEnd { name, timestamp } => match self.sections.remove(name) {
+                Some(started) => (
+                    format!("  {} took {}s", name, timestamp.saturating_sub(started)),
+                    Some(DURATION_STYLE),
+                ),
+                None => return Ok(()),
+            },
+            Line::Output(text) => (text.to_string(), None),
+        };
+        let timestamp = format!("[{}] ", elapsed(self.started));
+
+        let mut terminal_line = String::new();
+        if self.options.timestamps {
+            terminal_line.push_str(&timestamp);
+        }
+        if self.options.prefix {
+            terminal_line.push_str(&format!("[{}] ", self.name));
+        }
+        match style {
+            Some(style) if self.options.colours => {
+                terminal_line.push_str(&format!("{}{}{}", style, text, RESET_STYLE))
+            }
+            _ => terminal_line.push_str(&text),
+        }
+
+        writeln!(self.terminal, "{}", terminal_line)?;
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}{}", timestamp, text)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for JobLog<'_> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        for byte in buffer {
+            if *byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.line).to_string();
+                self.line.clear();
+                self.write_line(line.trim_end_matches('\r'))?;
+            } else {
+                self.line.push(*byte);
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    // Output that doesn't end with a newline yet is kept until the job has finished.
+    fn flush(&mut self) -> Result<(), Error> {
+        self.terminal.flush()?;
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for JobLog<'_> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let line = String::from_utf8_lossy(&self.line).to_string();
+            self.line.clear();
+            let _ = self.write_line(&line);
+        }
+        let _ = self.flush();
+    }
+}
+
+// GitLab's markers look like `\e[0Ksection_start:1560896352:name[collapsed=true]\r\e[0KHeader`
+// and `\e[0Ksection_end:1560896353:name\r\e[0K`.
+fn parse(line: &str) -> Line<'_> {
+    let marker = line.trim_start_matches(CLEAR_LINE);
+    let (marker, header) = marker.split_once('\r').unwrap_or((marker, ""));
+    let header = header.trim_start_matches(CLEAR_LINE).trim();
+
+    let mut parts = marker.splitn(3, ':');
+    let (Some(kind), Some(timestamp), Some(name)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Line::Output(line);
+    };
+    let Ok(timestamp) = timestamp.parse() else {
+        return Line::Output(line);
+    };
+    // Options like `[collapsed=true]` only matter to GitLab's UI.
+    let name = name.split('[').next().unwrap_or(name);
+
+    match kind {
+        "section_start" => Line::SectionStart {
+            name,
+            timestamp,
+            header,
+        },
+        "section_end" => Line::SectionEnd { name, timestamp },
+        _ => Line::Output(line),
+    }
+}
+
+fn elapsed(started: Instant) -> String {
+    let seconds = started.elapsed().as_secs();
+
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // A log file that can still be read after the log has been dropped.
+    #[derive(Clone, Default)]
+    struct SharedFile(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedFile {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+            self.0.lock().unwrap().write(buffer)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl SharedFile {
+        fn content(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn render(options: LogOptions, output: &str) -> (String, String) {
+        let mut terminal = vec![];
+        let file = SharedFile::default();
+        {
+            let mut log = JobLog::new(
+                "build",
+                options,
+                &mut terminal,
+                Some(Box::new(file.clone())),
+            );
+            log.write_all(output.as_bytes()).unwrap();
+        }
+
+        (String::from_utf8(terminal).unwrap(), file.content())
+    }
+
+    #[test]
+    fn passes_output_through_line_by_line() {
+        let (terminal, _) = render(LogOptions::default(), "first\nsecond");
+
+        assert_eq!(terminal, "first\nsecond\n");
+    }
+
+    #[test]
+    fn prefixes_lines_with_job_name_and_elapsed_time() {
+        let options = LogOptions {
+            prefix: true,
+            timestamps: true,
+            ..Default::default()
+        };
+        let (terminal, _) = render(options, "output\n");
+
+        assert_eq!(terminal, "[00:00] [build] output\n");
+    }
+
+    #[test]
+    fn keeps_timestamps_but_no_prefixes_in_the_log_file() {
+        let options = LogOptions {
+            prefix: true,
+            timestamps: false,
+            ..Default::default()
+        };
+        let (terminal, file) = render(options, "output\n");
+
+        assert_eq!(terminal, "[build] output\n");
+        assert_eq!(file, "[00:00] output\n");
+    }
+
+    fn coloured() -> LogOptions {
+        LogOptions {
+            colours: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_sections_as_headers() {
+        let output = "\x1b[0Ksection_start:1560896352:install[collapsed=true]\r\x1b[0KInstalling dependencies\n\
+                      npm ci\n\
+                      \x1b[0Ksection_end:1560896355:install\r\x1b[0K\n";
+        let (terminal, file) = render(coloured(), output);
+
+        assert_eq!(
+            terminal,
+            "\x1b[1;36m» Installing dependencies\x1b[0m\nnpm ci\n\x1b[2m  install took 3s\x1b[0m\n"
+        );
+        assert_eq!(
+            file,
+            "[00:00] » Installing dependencies\n[00:00] npm ci\n[00:00]   install took 3s\n"
+        );
+    }
+
+    #[test]
+    fn leaves_headers_plain_without_colours() {
+        let (terminal, _) = render(LogOptions::default(), "section_start:1:tests\r\n");
+
+        assert_eq!(terminal, "» tests\n");
+    }
+
+    #[test]
+    fn uses_section_names_for_sections_without_header() {
+        let (terminal, _) = render(coloured(), "section_start:1:tests\r\n");
+
+        assert_eq!(terminal, "\x1b[1;36m» tests\x1b[0m\n");
+    }
+
+    #[test]
+    fn leaves_lines_that_only_look_like_markers_alone() {
+        let (terminal, _) = render(
+            LogOptions::default(),
+            "section_start:soon:tests\nkey:value:other\n",
+        );
+
+        assert_eq!(terminal, "section_start:soon:tests\nkey:value:other\n");
+    }
+}
@@ -1,5 +1,14 @@
+pub mod checkout;
+pub mod cli;
 pub mod docker;
+pub mod docker_config;
+pub mod history;
+pub mod http;
+pub mod log;
 pub mod processes;
 pub mod prompt;
+pub mod runtime;
 pub mod shell;
+pub mod shell_executor;
+pub mod tui;
 pub mod variables;
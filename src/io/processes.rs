@@ -1,20 +1,33 @@
 use crate::core::Job;
 #[cfg(not(test))]
-use crate::io::docker;
+use crate::git::SourceMode;
+use crate::io::checkout::Checkout;
+#[cfg(not(test))]
+use crate::io::checkout::{lfs_commands, submodule_commands};
 #[cfg(not(test))]
 use crate::io::docker::DIRECTORIES;
 #[cfg(not(test))]
-use crate::io::shell::combine_lines;
+use crate::io::history::RUNS_DIRECTORY;
+#[cfg(not(test))]
+use crate::io::runtime::{select_runtime, ContainerRuntime, ContainerSpec};
 #[cfg(not(test))]
-use crate::io::variables::{concatenate_variables, interpolate};
+use crate::io::shell::{combine_lines, step_line};
+use crate::settings::structure::RuntimeKind;
 use crate::Context;
 use std::collections::HashMap;
 #[cfg(not(test))]
 use std::io::Error;
+use std::io::Write;
+#[cfg(not(test))]
+use std::sync::Arc;
+
+// Stops the job that's running, called from another thread than the one running it.
+pub type JobStopper = Box<dyn Fn() -> Result<(), std::io::Error>>;
 
 pub trait ProcessesToExecute {
     fn image_needs_to_be_built(&mut self, tag: &str) -> Result<bool, std::io::Error>;
-    fn build_image(&mut self, tag: &str) -> Result<(), std::io::Error>;
+    // Progress of building, or of pulling the job's image below, is written to the output.
+    fn build_image(&mut self, tag: &str, output: &mut dyn Write) -> Result<(), std::io::Error>;
 
     fn prune_containers(&mut self) -> Result<usize, std::io::Error>;
     fn prune_volumes(&mut self) -> Result<usize, std::io::Error>;
@@ -26,6 +39,7 @@ pub trait ProcessesToExecute {
         &mut self,
         container_id: &str,
         context: &Context,
+        checkout: &Checkout,
     ) -> Result<(), std::io::Error>;
 
     fn prepare_artifacts(
@@ -39,86 +53,158 @@ pub trait ProcessesToExecute {
         &mut self,
         job: &Job,
         source_container_id: &str,
+        output: &mut dyn Write,
     ) -> Result<String, std::io::Error>;
-    fn run_job(&mut self, container_id: &str, job: &Job) -> Result<(), std::io::Error>;
+    fn write_files(
+        &mut self,
+        container_id: &str,
+        files: &[(String, String)],
+    ) -> Result<(), std::io::Error>;
+    // Writes the job's output to the given log.
+    fn run_job(
+        &mut self,
+        container_id: &str,
+        job: &Job,
+        output: &mut dyn Write,
+    ) -> Result<(), std::io::Error>;
 
+    // Runs a single line of the job's script, keeping the variables and working directory that
+    // earlier lines left behind.
+    fn run_line(
+        &mut self,
+        container_id: &str,
+        job: &Job,
+        line: &str,
+        output: &mut dyn Write,
+    ) -> Result<(), std::io::Error>;
+
+    // Returns the extracted files, relative to the job's artifacts.
     fn extract_artifacts(
         &mut self,
         container_id: &str,
         job_name: &str,
         job: &Job,
-    ) -> Result<(), std::io::Error>;
+    ) -> Result<Vec<String>, std::io::Error>;
+
+    // An interactive shell where the job that ran last has run, with the job's variables.
+    fn open_shell(&mut self, job: &Job) -> Result<(), std::io::Error>;
+
+    // Works while the job is running, unlike everything else, which waits for it.
+    fn job_stopper(&self) -> JobStopper;
 }
 
 #[cfg(not(test))]
-pub struct Processes;
+pub struct Processes {
+    // Shared with stoppers of jobs.
+    runtime: Arc<dyn ContainerRuntime>,
+}
 #[cfg(test)]
 pub use tests::ProcessesSpy as Processes;
 
 #[cfg(not(test))]
 impl Processes {
-    pub fn new() -> Self {
-        Self {}
+    pub fn for_runtime(kind: Option<RuntimeKind>) -> Self {
+        Self {
+            runtime: select_runtime(kind).into(),
+        }
+    }
+
+    fn execute_commands(&self, container_id: &str, commands: &str) -> Result<(), Error> {
+        Ok(self
+            .runtime
+            .execute(container_id, commands, &[], &[], &mut std::io::stdout())?)
     }
 }
 
 #[cfg(not(test))]
 impl ProcessesToExecute for Processes {
     fn image_needs_to_be_built(&mut self, tag: &str) -> Result<bool, Error> {
-        docker::image_needs_to_be_built(tag)
+        Ok(self.runtime.image_needs_to_be_built(tag)?)
     }
 
-    fn build_image(&mut self, tag: &str) -> Result<(), Error> {
-        docker::build_image(tag)
+    fn build_image(&mut self, tag: &str, output: &mut dyn Write) -> Result<(), Error> {
+        Ok(self.runtime.build_image(tag, output)?)
     }
 
     fn prune_containers(&mut self) -> Result<usize, Error> {
-        docker::prune_containers()
+        Ok(self.runtime.remove_containers("fake-ci")?)
     }
 
     fn prune_volumes(&mut self) -> Result<usize, Error> {
-        docker::prune_volumes()
+        Ok(self.runtime.remove_volumes("fake")?)
     }
 
     fn prune_images(&mut self) -> Result<usize, Error> {
-        docker::prune_images()
+        Ok(self.runtime.remove_images("fake-ci")?)
     }
 
     fn prune_checkout_container(&mut self) -> Result<(), Error> {
-        docker::prune_container("fake-ci-checkout")
+        self.runtime.remove_containers("fake-ci-checkout")?;
+
+        Ok(())
     }
 
     fn start_checkout_container(&mut self, context: &Context) -> Result<String, Error> {
-        docker::start_checkout_container(
-            "fake-ci-checkout",
-            &context.image_tag,
-            &context.current_directory,
-        )
+        let spec = ContainerSpec {
+            image: context.image_tag.clone(),
+            volumes: vec![DIRECTORIES.checkout.into(), DIRECTORIES.job.into()],
+            binds: vec![
+                (
+                    context.current_directory.clone(),
+                    DIRECTORIES.project.into(),
+                ),
+                ("fake-ci-artifacts".into(), DIRECTORIES.artifacts.into()),
+            ],
+            ..Default::default()
+        };
+
+        // Fake CI's own image, which has just been built and is never pulled.
+        Ok(self
+            .runtime
+            .run_container("fake-ci-checkout", &spec, &mut std::io::sink())?)
     }
 
     fn checkout_code(
         &mut self,
         container_id: &str,
         context: &Context,
+        checkout: &Checkout,
     ) -> Result<(), std::io::Error> {
         let checkout_directory = DIRECTORIES.checkout;
         let project_directory = DIRECTORIES.project;
         let job_directory = DIRECTORIES.job;
         let artifacts_directory = DIRECTORIES.artifacts;
         let git_sha = &context.git_sha;
-
-        let checkout_commands = format!(
-            "
-              cd {checkout_directory};
-              git init;
-              git remote add origin {project_directory};
-              git fetch origin --quiet;
-              git checkout --quiet {git_sha};
-              (cd {project_directory}; git add --intent-to-add .; git diff) | git apply --allow-empty --quiet;
-              (cd {project_directory}; git reset --mixed)
-            "
-        );
-        docker::execute_commands(container_id, &checkout_commands)?;
+        let changes = changes_of(context.source_mode);
+        let mut checkout_commands = vec![format!("cd {checkout_directory}")];
+
+        if checkout.has_code() {
+            checkout_commands.push("git init".into());
+            checkout_commands.push(format!("git remote add origin {project_directory}"));
+
+            match checkout.depth {
+                Some(depth) => checkout_commands.push(format!(
+                    "git fetch origin --quiet --depth {depth} {git_sha}"
+                )),
+                // The commit is fetched on its own as well, in case no branch of the project has it.
+                None => checkout_commands.extend([
+                    "git fetch origin --quiet".into(),
+                    format!("git fetch origin --quiet {git_sha}"),
+                ]),
+            }
+        }
+        if checkout.has_code() && checkout.checkout {
+            // LFS files are taken from the project afterwards.
+            checkout_commands.push(format!(
+                "GIT_LFS_SKIP_SMUDGE=1 git checkout --quiet {git_sha}"
+            ));
+            checkout_commands.push(format!(
+                "(cd {project_directory}; {changes}) | git apply --allow-empty --quiet"
+            ));
+            checkout_commands.extend(submodule_commands(checkout, project_directory));
+            checkout_commands.push(lfs_commands(project_directory));
+        }
+        self.execute_commands(container_id, &checkout_commands.join(";\n"))?;
 
         let other_preparation_commands = format!(
             "
@@ -127,7 +213,7 @@ impl ProcessesToExecute for Processes {
               chmod 0777 {artifacts_directory};
             "
         );
-        docker::execute_commands(container_id, &other_preparation_commands)?;
+        self.execute_commands(container_id, &other_preparation_commands)?;
 
         Ok(())
     }
@@ -149,32 +235,84 @@ impl ProcessesToExecute for Processes {
             }
         }
 
-        docker::execute_commands(container_id, &artifact_commands.join(";"))?;
+        self.execute_commands(container_id, &artifact_commands.join(";"))?;
 
         Ok(())
     }
 
     fn prune_job_container(&mut self) -> Result<(), std::io::Error> {
-        docker::prune_container("fake-ci-job")
+        self.runtime.remove_containers("fake-ci-job")?;
+
+        Ok(())
     }
 
     fn start_job_container(
         &mut self,
         job: &Job,
         source_container_id: &str,
+        output: &mut dyn Write,
     ) -> Result<String, std::io::Error> {
-        let interpolated_image_name = interpolate(&job.image, &job.variables)?;
+        let spec = ContainerSpec {
+            image: job.image.clone(),
+            volumes_from: Some(source_container_id.into()),
+            environment: vec![("CI_PROJECT_DIR".into(), DIRECTORIES.job.into())],
+            ..Default::default()
+        };
+
+        Ok(self.runtime.run_container("fake-ci-job", &spec, output)?)
+    }
 
-        docker::start_job_container("fake-ci-job", &interpolated_image_name, source_container_id)
+    fn write_files(
+        &mut self,
+        container_id: &str,
+        files: &[(String, String)],
+    ) -> Result<(), std::io::Error> {
+        for (path, content) in files {
+            self.runtime.write_file(container_id, path, content)?;
+        }
+
+        Ok(())
     }
 
-    fn run_job(&mut self, container_id: &str, job: &Job) -> Result<(), std::io::Error> {
-        let variables = concatenate_variables(&job.variables);
+    fn run_job(
+        &mut self,
+        container_id: &str,
+        job: &Job,
+        output: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
         let script_commands = combine_lines(&job.script);
         let job_directory = DIRECTORIES.job;
-        let full_script = format!("cd {job_directory}; {variables} {script_commands}");
+        let full_script = format!("cd {job_directory}; {script_commands}");
+
+        Ok(self.runtime.execute(
+            container_id,
+            &full_script,
+            &job.variables,
+            &job.masked_values,
+            output,
+        )?)
+    }
+
+    fn run_line(
+        &mut self,
+        container_id: &str,
+        job: &Job,
+        line: &str,
+        output: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
+        let job_directory = DIRECTORIES.job;
+        let full_script = format!(
+            "cd {job_directory}\n{}",
+            step_line(line, DIRECTORIES.step_state)
+        );
 
-        docker::execute_commands(container_id, &full_script)
+        Ok(self.runtime.execute(
+            container_id,
+            &full_script,
+            &job.variables,
+            &job.masked_values,
+            output,
+        )?)
     }
 
     fn extract_artifacts(
@@ -182,7 +320,7 @@ impl ProcessesToExecute for Processes {
         job_container_id: &str,
         job_name: &str,
         job: &Job,
-    ) -> Result<(), std::io::Error> {
+    ) -> Result<Vec<String>, std::io::Error> {
         let job_directory = DIRECTORIES.job;
         let artifacts_directory = DIRECTORIES.artifacts;
         let mut artifact_commands = vec![format!("mkdir -p \"{artifacts_directory}/{job_name}\"")];
@@ -193,13 +331,76 @@ impl ProcessesToExecute for Processes {
             ));
         }
 
-        docker::execute_commands(job_container_id, &artifact_commands.join(";"))
+        self.execute_commands(job_container_id, &artifact_commands.join(";"))?;
+
+        let mut files = vec![];
+        self.runtime.execute(
+            job_container_id,
+            &format!("cd \"{artifacts_directory}/{job_name}\" && find . -type f"),
+            &[],
+            &[],
+            &mut files,
+        )?;
+
+        Ok(artifact_files(&String::from_utf8_lossy(&files)))
+    }
+
+    fn open_shell(&mut self, job: &Job) -> Result<(), std::io::Error> {
+        Ok(self
+            .runtime
+            .open_shell("fake-ci-job", DIRECTORIES.job, &job.variables)?)
+    }
+
+    fn job_stopper(&self) -> JobStopper {
+        let runtime = self.runtime.clone();
+
+        Box::new(move || Ok(runtime.kill_container("fake-ci-job")?))
+    }
+}
+
+// Commands to get a patch of the project's changes on top of `HEAD`. Untracked files are added to
+// a temporary index, which leaves what has been staged in the project as it is.
+#[cfg(not(test))]
+fn changes_of(source_mode: SourceMode) -> String {
+    let exclude_runs = format!(":!{}", RUNS_DIRECTORY);
+
+    match source_mode {
+        SourceMode::Committed => "true".into(),
+        SourceMode::Staged => "git diff --cached --binary HEAD".into(),
+        SourceMode::Working => format!(
+            "export GIT_INDEX_FILE=/tmp/fake-ci-index; git read-tree HEAD; git add --all -- . '{exclude_runs}'; git diff --cached --binary HEAD"
+        ),
     }
 }
 
+// Turns the output of `find . -type f` into relative paths, sorted.
+pub fn artifact_files(output: &str) -> Vec<String> {
+    let mut files = output
+        .lines()
+        .map(|line| line.trim().trim_start_matches("./").to_string())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    files
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn lists_artifact_files_relative_to_the_job() {
+        let output = "./out.txt\r\n./coverage/index.html\n\n";
+
+        assert_eq!(
+            artifact_files(output),
+            vec!["coverage/index.html".to_string(), "out.txt".to_string()]
+        );
+    }
 
     #[derive(Default)]
     pub struct ProcessesSpy {
@@ -214,8 +415,17 @@ pub mod tests {
         pub prepare_artifacts_call_count: usize,
         pub prune_job_container_call_count: usize,
         pub start_job_container_call_count: usize,
+        pub write_files_call_count: usize,
         pub run_job_call_count: usize,
         pub extract_artifacts_call_count: usize,
+        pub open_shell_call_count: usize,
+        // Lines run one by one, when stepping through the script.
+        pub lines_run: Vec<String>,
+        // Makes the job's script fail.
+        pub job_fails: bool,
+        // Makes the job's script run without output until it's stopped.
+        pub job_hangs: bool,
+        pub stopped: Arc<AtomicBool>,
     }
 
     impl ProcessesSpy {
@@ -223,6 +433,10 @@ pub mod tests {
             ProcessesSpy::default()
         }
 
+        pub fn for_runtime(_kind: Option<RuntimeKind>) -> Self {
+            ProcessesSpy::default()
+        }
+
         pub fn with_image_to_be_built() -> Self {
             Self {
                 image_needs_to_be_built: true,
@@ -236,7 +450,11 @@ pub mod tests {
             Ok(self.image_needs_to_be_built)
         }
 
-        fn build_image(&mut self, _tag: &str) -> Result<(), std::io::Error> {
+        fn build_image(
+            &mut self,
+            _tag: &str,
+            _output: &mut dyn Write,
+        ) -> Result<(), std::io::Error> {
             self.build_image_call_count += 1;
 
             Ok(())
@@ -279,6 +497,7 @@ pub mod tests {
             &mut self,
             _container_id: &str,
             _context: &Context,
+            _checkout: &Checkout,
         ) -> Result<(), std::io::Error> {
             self.checkout_code_call_count += 1;
 
@@ -305,27 +524,85 @@ pub mod tests {
             &mut self,
             _job: &Job,
             _source_container_id: &str,
+            _output: &mut dyn Write,
         ) -> Result<String, std::io::Error> {
             self.start_job_container_call_count += 1;
 
             Ok("container-id".into())
         }
 
-        fn run_job(&mut self, _container_id: &str, _job: &Job) -> Result<(), std::io::Error> {
+        fn write_files(
+            &mut self,
+            _container_id: &str,
+            _files: &[(String, String)],
+        ) -> Result<(), std::io::Error> {
+            self.write_files_call_count += 1;
+
+            Ok(())
+        }
+
+        fn run_job(
+            &mut self,
+            _container_id: &str,
+            _job: &Job,
+            _output: &mut dyn Write,
+        ) -> Result<(), std::io::Error> {
             self.run_job_call_count += 1;
 
+            while self.job_hangs && !self.stopped.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            if self.stopped.load(Ordering::SeqCst) {
+                return Err(std::io::Error::other("command was killed by signal 9"));
+            }
+            if self.job_fails {
+                return Err(std::io::Error::other("command exited with code 1"));
+            }
+
             Ok(())
         }
 
-        fn extract_artifacts(
+        fn run_line(
             &mut self,
             _container_id: &str,
-            _job_name: &str,
             _job: &Job,
+            line: &str,
+            _output: &mut dyn Write,
         ) -> Result<(), std::io::Error> {
+            self.lines_run.push(line.into());
+
+            if self.job_fails {
+                return Err(std::io::Error::other("command exited with code 1"));
+            }
+
+            Ok(())
+        }
+
+        fn extract_artifacts(
+            &mut self,
+            _container_id: &str,
+            _job_name: &str,
+            job: &Job,
+        ) -> Result<Vec<String>, std::io::Error> {
             self.extract_artifacts_call_count += 1;
 
+            Ok(job.artifacts.clone())
+        }
+
+        fn open_shell(&mut self, _job: &Job) -> Result<(), std::io::Error> {
+            self.open_shell_call_count += 1;
+
             Ok(())
         }
+
+        fn job_stopper(&self) -> JobStopper {
+            let stopped = self.stopped.clone();
+
+            Box::new(move || {
+                stopped.store(true, Ordering::SeqCst);
+
+                Ok(())
+            })
+        }
     }
 }
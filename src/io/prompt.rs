@@ -3,11 +3,18 @@ use crossterm::style::Stylize;
 #[cfg(not(test))]
 use dialoguer::theme::SimpleTheme;
 #[cfg(not(test))]
-use dialoguer::Confirm;
+use dialoguer::{Confirm, FuzzySelect, Input, MultiSelect};
+use std::io::Write;
 
 pub trait Prompts {
     fn question(&mut self, question: &str) -> PromptResponse;
     fn info(&mut self, message: &str);
+    // Index of the chosen item, `None` if the selection has been cancelled.
+    fn select(&mut self, question: &str, items: &[String]) -> Option<usize>;
+    // Indices of all chosen items, empty if the selection has been cancelled.
+    fn select_multiple(&mut self, question: &str, items: &[String]) -> Vec<usize>;
+    // Text entered starting from `initial`, `None` if the input has been cancelled.
+    fn input(&mut self, question: &str, initial: &str) -> Option<String>;
 }
 
 #[cfg(not(test))]
@@ -39,6 +46,80 @@ impl Prompts for Prompt {
     fn info(&mut self, message: &str) {
         println!("{}", message.blue());
     }
+
+    fn select(&mut self, question: &str, items: &[String]) -> Option<usize> {
+        FuzzySelect::with_theme(&SimpleTheme {})
+            .with_prompt(format!("{}", question.blue()))
+            .items(items)
+            .default(0)
+            .interact_opt()
+            .ok()
+            .flatten()
+    }
+
+    fn select_multiple(&mut self, question: &str, items: &[String]) -> Vec<usize> {
+        MultiSelect::with_theme(&SimpleTheme {})
+            .with_prompt(format!("{}", question.blue()))
+            .items(items)
+            .interact_opt()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    fn input(&mut self, question: &str, initial: &str) -> Option<String> {
+        Input::with_theme(&SimpleTheme {})
+            .with_prompt(format!("{}", question.blue()))
+            .with_initial_text(initial)
+            .interact_text()
+            .ok()
+    }
+}
+
+// Passes what's written on to the prompt line by line, like the progress of pulling or building
+// images, which would otherwise end up on stdout.
+pub struct InfoLines<'a, PROMPTS: Prompts> {
+    prompt: &'a mut PROMPTS,
+    line: Vec<u8>,
+}
+
+impl<'a, PROMPTS: Prompts> InfoLines<'a, PROMPTS> {
+    pub fn new(prompt: &'a mut PROMPTS) -> Self {
+        Self {
+            prompt,
+            line: vec![],
+        }
+    }
+}
+
+impl<PROMPTS: Prompts> Write for InfoLines<'_, PROMPTS> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        for byte in buffer {
+            if *byte == b'\n' {
+                self.flush()?;
+            } else {
+                self.line.push(*byte);
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line).trim_end().to_string();
+        if !line.is_empty() {
+            self.prompt.info(&line);
+        }
+        self.line.clear();
+
+        Ok(())
+    }
+}
+
+impl<PROMPTS: Prompts> Drop for InfoLines<'_, PROMPTS> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
 }
 
 #[derive(Clone)]
@@ -54,6 +135,12 @@ pub mod tests {
     pub struct FakePrompt {
         pub has_been_asked_to_confirm: bool,
         pub response: PromptResponse,
+        pub has_been_asked_to_select: bool,
+        // Indices of the items to pick when asked to select any. Single selections take them one
+        // after another, the last one for every further question.
+        pub selection: Vec<usize>,
+        // What's entered when asked for input, `None` cancels it.
+        pub input: Option<String>,
     }
 
     pub struct SpyPrompt {
@@ -65,6 +152,9 @@ pub mod tests {
             Self {
                 has_been_asked_to_confirm: false,
                 response: PromptResponse::No,
+                has_been_asked_to_select: false,
+                selection: vec![],
+                input: None,
             }
         }
 
@@ -72,6 +162,16 @@ pub mod tests {
             Self {
                 has_been_asked_to_confirm: false,
                 response: PromptResponse::Yes,
+                has_been_asked_to_select: false,
+                selection: vec![],
+                input: None,
+            }
+        }
+
+        pub fn selecting(selection: Vec<usize>) -> Self {
+            Self {
+                selection,
+                ..Self::always_confirming()
             }
         }
 
@@ -79,6 +179,9 @@ pub mod tests {
             Self {
                 has_been_asked_to_confirm: false,
                 response: PromptResponse::No,
+                has_been_asked_to_select: false,
+                selection: vec![],
+                input: None,
             }
         }
     }
@@ -90,6 +193,25 @@ pub mod tests {
         }
 
         fn info(&mut self, _message: &str) {}
+
+        fn select(&mut self, _question: &str, _items: &[String]) -> Option<usize> {
+            self.has_been_asked_to_select = true;
+
+            if self.selection.len() > 1 {
+                Some(self.selection.remove(0))
+            } else {
+                self.selection.first().copied()
+            }
+        }
+
+        fn select_multiple(&mut self, _question: &str, _items: &[String]) -> Vec<usize> {
+            self.has_been_asked_to_select = true;
+            self.selection.clone()
+        }
+
+        fn input(&mut self, _question: &str, _initial: &str) -> Option<String> {
+            self.input.clone()
+        }
     }
 
     impl SpyPrompt {
@@ -106,5 +228,30 @@ pub mod tests {
         fn info(&mut self, _message: &str) {
             self.info_call_count += 1;
         }
+
+        fn select(&mut self, _question: &str, _items: &[String]) -> Option<usize> {
+            None
+        }
+
+        fn select_multiple(&mut self, _question: &str, _items: &[String]) -> Vec<usize> {
+            vec![]
+        }
+
+        fn input(&mut self, _question: &str, _initial: &str) -> Option<String> {
+            None
+        }
+    }
+
+    #[test]
+    fn passes_written_lines_on_as_info() {
+        let mut prompt = SpyPrompt::new();
+
+        let mut lines = InfoLines::new(&mut prompt);
+        write!(lines, "Step 1/2\nStep").unwrap();
+        write!(lines, " 2/2\n\n").unwrap();
+        write!(lines, "Successfully built").unwrap();
+        drop(lines);
+
+        assert_eq!(prompt.info_call_count, 3);
     }
 }
@@ -0,0 +1,404 @@
+use crate::io::cli::Cli;
+use crate::io::docker::Docker;
+use crate::io::docker_config::DockerConfig;
+use crate::io::http::Endpoint;
+use crate::settings::structure::RuntimeKind;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use thiserror::Error;
+
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+const PODMAN_SOCKET: &str = "/run/podman/podman.sock";
+// Fake CI's own image, which every runtime builds from the same Dockerfile.
+pub const DOCKERFILE_CONTENT: &str = include_str!("../../Dockerfile");
+
+#[derive(Error, Debug)]
+pub enum RuntimeError {
+    #[error("cannot talk to the container runtime at {0}: {1}")]
+    Unavailable(String, std::io::Error),
+    #[error("image {0} not found")]
+    ImageNotFound(String),
+    #[error("pulling image {0} was denied: {1}")]
+    PullDenied(String, String),
+    #[error("building image {0} failed: {1}")]
+    BuildFailed(String, String),
+    #[error("container {0} ran out of memory")]
+    OutOfMemory(String),
+    #[error("command exited with code {0}")]
+    CommandFailed(i64),
+    #[error("command was killed by signal {0}")]
+    Killed(i32),
+    #[error("container runtime responded with {0}: {1}")]
+    Api(u16, String),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+// Commands only succeed when they exit with code 0, not when they end because of a signal.
+pub fn exit_status(status: ExitStatus) -> Result<(), RuntimeError> {
+    match (status.code(), status.signal()) {
+        (Some(0), _) => Ok(()),
+        (Some(code), _) => Err(RuntimeError::CommandFailed(code.into())),
+        (None, signal) => Err(RuntimeError::Killed(signal.unwrap_or_default())),
+    }
+}
+
+// Commands still report plain IO errors, the typed error stays available as its source.
+impl From<RuntimeError> for std::io::Error {
+    fn from(error: RuntimeError) -> Self {
+        match error {
+            RuntimeError::IO(error) => error,
+            error => std::io::Error::other(error),
+        }
+    }
+}
+
+// What a container is started with, independent of how a runtime expects it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ContainerSpec {
+    pub image: String,
+    // Anonymous volumes, only mount points.
+    pub volumes: Vec<String>,
+    // Host directories or named volumes, and where to mount them.
+    pub binds: Vec<(String, String)>,
+    pub volumes_from: Option<String>,
+    pub environment: Vec<(String, String)>,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub trait ContainerRuntime: Send + Sync {
+    fn image_needs_to_be_built(&self, tag: &str) -> Result<bool, RuntimeError>;
+    // Progress of building, or pulling below, is written to the output.
+    fn build_image(&self, tag: &str, output: &mut dyn Write) -> Result<(), RuntimeError>;
+
+    // All of these return the number of removed objects.
+    fn remove_containers(&self, name: &str) -> Result<usize, RuntimeError>;
+    fn remove_volumes(&self, name: &str) -> Result<usize, RuntimeError>;
+    fn remove_images(&self, reference: &str) -> Result<usize, RuntimeError>;
+
+    // Like `docker run --tty --detach`, including pulling the image if it's not there yet.
+    fn run_container(
+        &self,
+        name: &str,
+        spec: &ContainerSpec,
+        output: &mut dyn Write,
+    ) -> Result<String, RuntimeError>;
+    // Runs `sh -c <commands>` in the container and writes its output (stdout and stderr) with all
+    // masked values redacted, like GitLab does in job logs.
+    fn execute(
+        &self,
+        container_id: &str,
+        commands: &str,
+        variables: &[(String, String)],
+        masked_values: &[String],
+        output: &mut dyn Write,
+    ) -> Result<(), RuntimeError>;
+    // An interactive shell in the container, Bash if the image has it, using the current terminal
+    // until it's exited. Goes through the runtime's CLI, which knows best how to hand over a terminal.
+    fn open_shell(
+        &self,
+        container_id: &str,
+        directory: &str,
+        variables: &[(String, String)],
+    ) -> Result<(), RuntimeError>;
+    // Written as root, so that it works regardless of the user the image runs as.
+    fn write_file(&self, container_id: &str, path: &str, content: &str)
+        -> Result<(), RuntimeError>;
+    // Ends commands running in the container at once. Nothing to do if it isn't running.
+    fn kill_container(&self, name: &str) -> Result<(), RuntimeError>;
+}
+
+// The variables the Docker CLI takes its endpoint from.
+#[derive(Debug, Default)]
+struct DockerEnvironment {
+    host: Option<String>,
+    context: Option<String>,
+    tls_verify: bool,
+}
+
+impl DockerEnvironment {
+    fn current() -> Self {
+        let variable = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        DockerEnvironment {
+            host: variable("DOCKER_HOST"),
+            context: variable("DOCKER_CONTEXT"),
+            tls_verify: variable("DOCKER_TLS_VERIFY").is_some(),
+        }
+    }
+}
+
+// Like the Docker CLI: `DOCKER_HOST` first, then the selected context, then the default socket.
+fn docker_endpoint(environment: &DockerEnvironment, config: &DockerConfig) -> Endpoint {
+    if let Some(host) = &environment.host {
+        return match Endpoint::parse(host) {
+            Endpoint::Tcp(_) if environment.tls_verify => Endpoint::Unsupported {
+                host: host.clone(),
+                reason: "TLS connections are not supported".into(),
+            },
+            endpoint => endpoint,
+        };
+    }
+
+    match environment.context.as_deref().or(config.current_context()) {
+        Some(name) if name != "default" => config.context_endpoint(name),
+        _ => Endpoint::Unix(DOCKER_SOCKET.into()),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Choice {
+    Docker,
+    Podman { socket: PathBuf, rootless: bool },
+    Nerdctl,
+}
+
+// Uses the configured runtime, or the first one that is available: Docker, then Podman, then nerdctl.
+#[cfg_attr(test, allow(dead_code))]
+pub fn select_runtime(kind: Option<RuntimeKind>) -> Box<dyn ContainerRuntime> {
+    let config = DockerConfig::load();
+    let docker_endpoint = docker_endpoint(&DockerEnvironment::current(), &config);
+    let rootless_podman_socket = std::env::var("XDG_RUNTIME_DIR")
+        .ok()
+        .map(|directory| Path::new(&directory).join("podman/podman.sock"));
+    let choice = choose_runtime(
+        kind,
+        &docker_endpoint,
+        rootless_podman_socket.as_deref(),
+        Path::new(PODMAN_SOCKET),
+        || Cli::new("nerdctl").is_available(),
+    );
+
+    // Podman takes credentials of registries in the same way.
+    match choice {
+        Choice::Docker => Box::new(Docker::at(docker_endpoint).with_config(config)),
+        Choice::Podman {
+            socket,
+            rootless: true,
+        } => Box::new(Docker::rootless(socket).with_config(config)),
+        Choice::Podman { socket, .. } => Box::new(Docker::podman(socket).with_config(config)),
+        Choice::Nerdctl => Box::new(Cli::new("nerdctl")),
+    }
+}
+
+fn choose_runtime(
+    kind: Option<RuntimeKind>,
+    docker_endpoint: &Endpoint,
+    rootless_podman_socket: Option<&Path>,
+    podman_socket: &Path,
+    has_nerdctl: impl FnOnce() -> bool,
+) -> Choice {
+    // Endpoints other than the default socket have been configured explicitly, so Docker is meant
+    // to be used even when they can't be reached.
+    let has_docker = match docker_endpoint {
+        Endpoint::Unix(socket) => socket.exists(),
+        _ => true,
+    };
+    // The user's own Podman is preferred over the system-wide one.
+    let podman = match rootless_podman_socket {
+        Some(socket) if socket.exists() => Some(Choice::Podman {
+            socket: socket.into(),
+            rootless: true,
+        }),
+        _ if podman_socket.exists() => Some(Choice::Podman {
+            socket: podman_socket.into(),
+            rootless: false,
+        }),
+        _ => None,
+    };
+
+    match kind {
+        Some(RuntimeKind::Docker) => Choice::Docker,
+        Some(RuntimeKind::Podman) => podman.unwrap_or(Choice::Podman {
+            socket: podman_socket.into(),
+            rootless: false,
+        }),
+        Some(RuntimeKind::Nerdctl) => Choice::Nerdctl,
+        None if has_docker => Choice::Docker,
+        None => match podman {
+            Some(podman) => podman,
+            None if has_nerdctl() => Choice::Nerdctl,
+            // Nothing found, the error when using it tells where Docker was expected.
+            None => Choice::Docker,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::docker_config::tests::ConfigDirectory;
+
+    mod test_choosing_runtimes {
+        use super::*;
+        use crate::test_support::TemporaryDirectory;
+
+        // Only whether they exist matters, so plain files stand in for sockets.
+        struct Sockets {
+            directory: TemporaryDirectory,
+        }
+
+        impl Sockets {
+            fn existing(names: &[&str]) -> Self {
+                let directory = TemporaryDirectory::new("sockets");
+                for name in names {
+                    std::fs::write(directory.path.join(name), "").unwrap();
+                }
+
+                Sockets { directory }
+            }
+
+            fn choose(&self, kind: Option<RuntimeKind>, has_nerdctl: bool) -> Choice {
+                choose_runtime(
+                    kind,
+                    &Endpoint::Unix(self.directory.path.join("docker.sock")),
+                    Some(&self.directory.path.join("rootless-podman.sock")),
+                    &self.directory.path.join("podman.sock"),
+                    || has_nerdctl,
+                )
+            }
+
+            fn podman(&self, name: &str, rootless: bool) -> Choice {
+                Choice::Podman {
+                    socket: self.directory.path.join(name),
+                    rootless,
+                }
+            }
+        }
+
+        #[test]
+        fn prefers_docker() {
+            let sockets = Sockets::existing(&["docker.sock", "podman.sock"]);
+
+            assert_eq!(sockets.choose(None, true), Choice::Docker);
+        }
+
+        #[test]
+        fn prefers_rootless_podman_over_the_system_wide_one() {
+            let sockets = Sockets::existing(&["rootless-podman.sock", "podman.sock"]);
+
+            assert_eq!(
+                sockets.choose(None, true),
+                sockets.podman("rootless-podman.sock", true)
+            );
+        }
+
+        #[test]
+        fn uses_system_wide_podman_without_a_rootless_one() {
+            let sockets = Sockets::existing(&["podman.sock"]);
+
+            assert_eq!(
+                sockets.choose(None, true),
+                sockets.podman("podman.sock", false)
+            );
+        }
+
+        #[test]
+        fn uses_nerdctl_without_any_sockets() {
+            let sockets = Sockets::existing(&[]);
+
+            assert_eq!(sockets.choose(None, true), Choice::Nerdctl);
+        }
+
+        #[test]
+        fn falls_back_to_docker_without_anything_available() {
+            let sockets = Sockets::existing(&[]);
+
+            assert_eq!(sockets.choose(None, false), Choice::Docker);
+        }
+
+        #[test]
+        fn uses_configured_endpoints_of_docker_even_if_they_cannot_be_checked() {
+            let choice = choose_runtime(
+                None,
+                &Endpoint::Tcp("docker.local:2375".into()),
+                None,
+                Path::new("/does/not/exist.sock"),
+                || true,
+            );
+
+            assert_eq!(choice, Choice::Docker);
+        }
+
+        #[test]
+        fn uses_the_configured_runtime_regardless_of_what_is_available() {
+            let sockets = Sockets::existing(&["docker.sock"]);
+
+            assert_eq!(
+                sockets.choose(Some(RuntimeKind::Podman), false),
+                sockets.podman("podman.sock", false)
+            );
+            assert_eq!(
+                sockets.choose(Some(RuntimeKind::Nerdctl), false),
+                Choice::Nerdctl
+            );
+            assert_eq!(
+                Sockets::existing(&["podman.sock"]).choose(Some(RuntimeKind::Docker), true),
+                Choice::Docker
+            );
+        }
+    }
+
+    #[test]
+    fn uses_the_default_socket_without_anything_configured() {
+        let config = DockerConfig::in_directory("/does/not/exist");
+
+        assert_eq!(
+            docker_endpoint(&DockerEnvironment::default(), &config),
+            Endpoint::Unix(DOCKER_SOCKET.into())
+        );
+    }
+
+    #[test]
+    fn prefers_docker_host_over_contexts() {
+        let directory = ConfigDirectory::with_config(r#"{"currentContext": "colima"}"#);
+        directory.add_context("a1", "colima", "unix:///home/me/.colima/docker.sock");
+        let config = DockerConfig::in_directory(&directory.path);
+        let environment = DockerEnvironment {
+            host: Some("tcp://localhost".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            docker_endpoint(&environment, &config),
+            Endpoint::Tcp("localhost:2375".into())
+        );
+        assert_eq!(
+            docker_endpoint(&DockerEnvironment::default(), &config),
+            Endpoint::Unix("/home/me/.colima/docker.sock".into())
+        );
+    }
+
+    #[test]
+    fn prefers_the_context_of_the_environment_over_the_current_one() {
+        let directory = ConfigDirectory::with_config(r#"{"currentContext": "colima"}"#);
+        directory.add_context("a1", "colima", "unix:///home/me/.colima/docker.sock");
+        let config = DockerConfig::in_directory(&directory.path);
+        let environment = DockerEnvironment {
+            context: Some("default".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            docker_endpoint(&environment, &config),
+            Endpoint::Unix(DOCKER_SOCKET.into())
+        );
+    }
+
+    #[test]
+    fn rejects_hosts_that_need_tls() {
+        let config = DockerConfig::in_directory("/does/not/exist");
+        let environment = DockerEnvironment {
+            host: Some("tcp://docker.example.com:2376".into()),
+            tls_verify: true,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            docker_endpoint(&environment, &config),
+            Endpoint::Unsupported { .. }
+        ));
+    }
+}
@@ -9,6 +9,33 @@ pub fn combine_lines(lines: &Vec<String>) -> String {
     all_lines.join(";")
 }
 
+// Runs a single line of a script on its own, in the state earlier lines left behind. Their variables
+// and working directory are kept in `state_directory` between lines. Assignments are exported while
+// the line runs (`set -a`), so that `export -p` has all of them.
+pub fn step_line(line: &str, state_directory: &str) -> String {
+    [
+        restore_state(state_directory),
+        "set -a".into(),
+        wrap_itself_with_echo(line),
+        line.into(),
+        // The positional parameters keep the line's status without adding a variable.
+        "set -- $?".into(),
+        "set +a".into(),
+        format!("mkdir -p \"{state_directory}\""),
+        format!("export -p > \"{state_directory}/variables\""),
+        format!("pwd > \"{state_directory}/directory\""),
+        "exit $1".into(),
+    ]
+    .join("\n")
+}
+
+// Picks up where the last line run by `step_line()` left off, if there has been one.
+pub fn restore_state(state_directory: &str) -> String {
+    format!(
+        "if [ -f \"{state_directory}/variables\" ]; then . \"{state_directory}/variables\" 2> /dev/null; cd \"$(cat \"{state_directory}/directory\")\"; fi"
+    )
+}
+
 pub fn wrap_itself_with_echo(command: &str) -> String {
     format!("echo -e \"\\e[1;32m{}\\e[0m\"", command)
 }
@@ -16,6 +43,7 @@ pub fn wrap_itself_with_echo(command: &str) -> String {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::test_support::temporary_path;
 
     #[test]
     fn wraps_command_with_shell_escape_sequence() {
@@ -36,4 +64,28 @@ pub mod tests {
         // And each wrapped and actual command is separated by a semicolon.
         assert_eq!(combined.matches(";cat file.txt").count(), 1);
     }
+
+    #[test]
+    fn keeps_variables_and_directory_between_lines() {
+        let state = temporary_path("step");
+        let state = state.to_str().unwrap();
+        let run = |line: &str| {
+            duct::cmd!("sh", "-c", step_line(line, state))
+                .stderr_to_stdout()
+                .stdout_capture()
+                .unchecked()
+                .run()
+                .unwrap()
+        };
+
+        run("NAME=\"two words\"");
+        run("cd /tmp");
+        let output = run("echo \"$NAME in $(pwd)\"");
+        let failed = run("false");
+        std::fs::remove_dir_all(state).unwrap();
+
+        assert!(String::from_utf8_lossy(&output.stdout).ends_with("two words in /tmp\n"));
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(failed.status.code(), Some(1));
+    }
 }
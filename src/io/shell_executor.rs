@@ -0,0 +1,772 @@
+use crate::core::Job;
+use crate::git::SourceMode;
+use crate::io::checkout::{submodule_commands, Checkout};
+use crate::io::docker::DIRECTORIES;
+use crate::io::history::RUNS_DIRECTORY;
+use crate::io::processes::{artifact_files, JobStopper, ProcessesToExecute};
+use crate::io::runtime::exit_status;
+use crate::io::shell::{combine_lines, restore_state, step_line};
+use crate::io::variables::mask;
+use crate::Context;
+use duct::{cmd, ReaderHandle};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Where artifacts are kept between runs, relative to the project.
+const ARTIFACTS_DIRECTORY: &str = ".fake-ci/artifacts";
+
+// Runs jobs straight on the host, like GitLab's shell executor.
+// Instead of a container, a job gets a temporary git worktree of the project with all uncommitted
+// changes applied. The directories of containers, like `/job`, are mapped into that.
+pub struct ShellExecutor {
+    root: PathBuf,
+    project_directory: PathBuf,
+    // The process of the commands that are running, shared with stoppers of jobs.
+    running: Arc<Mutex<Option<u32>>>,
+}
+
+impl ShellExecutor {
+    pub fn new() -> Self {
+        ShellExecutor {
+            root: std::env::temp_dir().join(format!("fake-ci-shell-{}", std::process::id())),
+            project_directory: PathBuf::new(),
+            running: Arc::default(),
+        }
+    }
+
+    fn job_directory(&self) -> PathBuf {
+        self.on_host(DIRECTORIES.job)
+    }
+
+    fn artifacts_directory(&self) -> PathBuf {
+        self.project_directory.join(ARTIFACTS_DIRECTORY)
+    }
+
+    // Removed together with the worktree, so every job starts without state.
+    fn step_state_directory(&self) -> String {
+        self.root.join("step").display().to_string()
+    }
+
+    // `/job/out` becomes `<root>/job/out`, other values are kept as they are.
+    fn on_host(&self, value: &str) -> PathBuf {
+        PathBuf::from(self.translate(value))
+    }
+
+    fn translate(&self, value: &str) -> String {
+        for directory in [DIRECTORIES.job, DIRECTORIES.file_variables] {
+            if value == directory || value.starts_with(&format!("{}/", directory)) {
+                return format!("{}{}", self.root.display(), value);
+            }
+        }
+
+        value.to_string()
+    }
+
+    // A separate index, so that what's staged in the project stays untouched.
+    fn working_tree_changes(&self) -> Result<String, Error> {
+        let index = self.root.join("index");
+        let exclude_artifacts = format!(":!{}", ARTIFACTS_DIRECTORY);
+        let exclude_runs = format!(":!{}", RUNS_DIRECTORY);
+
+        cmd!("git", "read-tree", "HEAD")
+            .dir(&self.project_directory)
+            .env("GIT_INDEX_FILE", &index)
+            .run()?;
+        cmd!(
+            "git",
+            "add",
+            "--all",
+            "--",
+            ".",
+            &exclude_artifacts,
+            &exclude_runs
+        )
+        .dir(&self.project_directory)
+        .env("GIT_INDEX_FILE", &index)
+        .run()?;
+
+        cmd!("git", "diff", "--cached", "--binary", "HEAD")
+            .dir(&self.project_directory)
+            .env("GIT_INDEX_FILE", &index)
+            .read()
+    }
+
+    // Git refuses to remove worktrees with submodules, so it's deleted and pruned instead.
+    fn remove_worktree(&self) -> Result<(), Error> {
+        let is_worktree = self.job_directory().join(".git").exists();
+
+        if self.root.exists() {
+            std::fs::remove_dir_all(&self.root)?;
+        }
+        if is_worktree {
+            cmd!("git", "worktree", "prune")
+                .dir(&self.project_directory)
+                .run()?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_changes(&self, source_mode: SourceMode) -> Result<(), Error> {
+        let changes = match source_mode {
+            SourceMode::Committed => return Ok(()),
+            SourceMode::Staged => cmd!("git", "diff", "--cached", "--binary", "HEAD")
+                .dir(&self.project_directory)
+                .read()?,
+            SourceMode::Working => self.working_tree_changes()?,
+        };
+
+        cmd!("git", "apply", "--allow-empty", "--whitespace=nowarn")
+            .dir(self.job_directory())
+            .stdin_bytes(format!("{}\n", changes))
+            .run()?;
+
+        Ok(())
+    }
+
+    fn execute_in_job_directory(&self, commands: &str) -> Result<(), Error> {
+        let commands = format!("cd \"{}\"; {}", self.job_directory().display(), commands);
+
+        self.execute_commands(&commands, &[], &[], &mut std::io::stdout())
+    }
+
+    fn execute_commands(
+        &self,
+        commands: &str,
+        variables: &[(String, String)],
+        masked_values: &[String],
+        output: &mut dyn Write,
+    ) -> Result<(), Error> {
+        // Bash, like GitLab's shell executor uses by default.
+        let mut command = cmd!("bash", "-c", commands).dir(&self.project_directory);
+        for (name, value) in variables {
+            command = command.env(name, self.translate(value));
+        }
+
+        let reader = command.stderr_to_stdout().unchecked().reader()?;
+        *self.running.lock().unwrap() = reader.pids().first().copied();
+        let copied = copy_output(&reader, masked_values, output);
+        *self.running.lock().unwrap() = None;
+        copied?;
+
+        // Reading up to the end of the output waits for the process, so it has exited by now.
+        match reader.try_wait()? {
+            Some(output) => Ok(exit_status(output.status)?),
+            None => Err(Error::other("job kept running after its output ended")),
+        }
+    }
+}
+
+fn copy_output(
+    reader: &ReaderHandle,
+    masked_values: &[String],
+    output: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut lines = BufReader::new(reader);
+    let mut line = vec![];
+
+    while lines.read_until(b'\n', &mut line)? > 0 {
+        output.write_all(mask(&String::from_utf8_lossy(&line), masked_values).as_bytes())?;
+        line.clear();
+    }
+
+    Ok(())
+}
+
+// Paths in the directory that match the pattern of an artifact, `**` included. Only Bash knows
+// how to expand them, it gets both as arguments so that neither is read as a command. Patterns
+// without a match are kept as they are, like Bash does, and fail to be copied.
+fn matching_paths(directory: &Path, pattern: &str) -> Result<Vec<String>, Error> {
+    let paths = cmd!(
+        "bash",
+        "-c",
+        "cd \"$1\" && shopt -s globstar && IFS= && printf '%s\\0' $2",
+        "fake-ci",
+        directory,
+        pattern
+    )
+    .read()?;
+
+    Ok(paths
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+// Like `cp -Rp <source> <directory>`, without a shell that would read names as commands.
+fn copy_into(source: &Path, directory: &Path) -> Result<(), Error> {
+    let name = source
+        .file_name()
+        .ok_or_else(|| Error::other(format!("cannot copy {}", source.display())))?;
+
+    copy_recursively(source, &directory.join(name))
+}
+
+fn copy_recursively(source: &Path, target: &Path) -> Result<(), Error> {
+    let metadata = std::fs::metadata(source)
+        .map_err(|e| Error::new(e.kind(), format!("cannot copy {}: {}", source.display(), e)))?;
+
+    if metadata.is_dir() {
+        std::fs::create_dir_all(target)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(target, metadata.permissions())
+    } else {
+        std::fs::copy(source, target).map(|_| ())
+    }
+}
+
+// Children have to go as well, they'd keep the output open otherwise. They are looked up first,
+// since they no longer belong to the process once it's gone.
+fn kill_process_tree(pid: u32) -> Result<(), Error> {
+    let children = cmd!("pgrep", "-P", pid.to_string()).unchecked().read()?;
+
+    cmd!("kill", "-KILL", pid.to_string())
+        .unchecked()
+        .stderr_null()
+        .run()?;
+    for child in children.lines().filter_map(|line| line.parse().ok()) {
+        kill_process_tree(child)?;
+    }
+
+    Ok(())
+}
+
+impl Drop for ShellExecutor {
+    fn drop(&mut self) {
+        let _ = self.remove_worktree();
+    }
+}
+
+impl ProcessesToExecute for ShellExecutor {
+    fn image_needs_to_be_built(&mut self, _tag: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn build_image(&mut self, _tag: &str, _output: &mut dyn Write) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn prune_containers(&mut self) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn prune_volumes(&mut self) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn prune_images(&mut self) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn prune_checkout_container(&mut self) -> Result<(), Error> {
+        self.remove_worktree()
+    }
+
+    fn start_checkout_container(&mut self, context: &Context) -> Result<String, Error> {
+        self.project_directory = PathBuf::from(&context.current_directory);
+        std::fs::create_dir_all(&self.root)?;
+
+        Ok(self.root.display().to_string())
+    }
+
+    // The worktree shares its history and LFS objects with the project, so there's nothing to
+    // fetch, whatever `GIT_DEPTH` is.
+    fn checkout_code(
+        &mut self,
+        _container_id: &str,
+        context: &Context,
+        checkout: &Checkout,
+    ) -> Result<(), Error> {
+        if !checkout.has_code() {
+            return std::fs::create_dir_all(self.job_directory());
+        }
+
+        let mut arguments = vec!["worktree", "add", "--detach", "--quiet"];
+        if !checkout.checkout {
+            arguments.push("--no-checkout");
+        }
+        let job_directory = self.job_directory().display().to_string();
+        arguments.extend([job_directory.as_str(), &context.git_sha]);
+        duct::cmd("git", arguments)
+            .dir(&self.project_directory)
+            .run()?;
+
+        if !checkout.checkout {
+            return Ok(());
+        }
+        self.apply_changes(context.source_mode)?;
+        if let Some(submodules) =
+            submodule_commands(checkout, &self.project_directory.display().to_string())
+        {
+            self.execute_in_job_directory(&submodules)?;
+        }
+
+        Ok(())
+    }
+
+    fn prepare_artifacts(
+        &mut self,
+        _container_id: &str,
+        artifacts: &HashMap<String, Vec<String>>,
+    ) -> Result<(), Error> {
+        let job_directory = self.job_directory();
+        let artifacts_directory = self.artifacts_directory();
+
+        for (job_name, files) in artifacts {
+            for file in files {
+                let source = artifacts_directory.join(job_name).join(file);
+                copy_into(&source, &job_directory)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn prune_job_container(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // The job runs in the worktree that has been checked out.
+    fn start_job_container(
+        &mut self,
+        _job: &Job,
+        source_container_id: &str,
+        _output: &mut dyn Write,
+    ) -> Result<String, Error> {
+        Ok(source_container_id.into())
+    }
+
+    fn write_files(
+        &mut self,
+        _container_id: &str,
+        files: &[(String, String)],
+    ) -> Result<(), Error> {
+        for (path, content) in files {
+            let path = self.on_host(path);
+
+            if let Some(directory) = path.parent() {
+                std::fs::create_dir_all(directory)?;
+            }
+            std::fs::write(path, content)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_job(
+        &mut self,
+        _container_id: &str,
+        job: &Job,
+        output: &mut dyn Write,
+    ) -> Result<(), Error> {
+        let script_commands = combine_lines(&job.script);
+        let full_script = format!(
+            "cd \"{}\"; {}",
+            self.job_directory().display(),
+            script_commands
+        );
+
+        self.execute_commands(&full_script, &job.variables, &job.masked_values, output)
+    }
+
+    fn run_line(
+        &mut self,
+        _container_id: &str,
+        job: &Job,
+        line: &str,
+        output: &mut dyn Write,
+    ) -> Result<(), Error> {
+        let full_script = format!(
+            "cd \"{}\"\n{}",
+            self.job_directory().display(),
+            step_line(line, &self.step_state_directory())
+        );
+
+        self.execute_commands(&full_script, &job.variables, &job.masked_values, output)
+    }
+
+    fn extract_artifacts(
+        &mut self,
+        _container_id: &str,
+        job_name: &str,
+        job: &Job,
+    ) -> Result<Vec<String>, Error> {
+        let job_directory = self.job_directory();
+        let target = self.artifacts_directory().join(job_name);
+        std::fs::create_dir_all(&target)?;
+
+        for artifact in &job.artifacts {
+            for path in matching_paths(&job_directory, artifact)? {
+                copy_into(&job_directory.join(path), &target)?;
+            }
+        }
+
+        let files = cmd!("find", ".", "-type", "f").dir(target).read()?;
+
+        Ok(artifact_files(&files))
+    }
+
+    fn open_shell(&mut self, job: &Job) -> Result<(), Error> {
+        let restore = restore_state(&self.step_state_directory());
+        let mut command =
+            cmd!("bash", "-c", format!("{}; exec bash", restore)).dir(self.job_directory());
+        for (name, value) in &job.variables {
+            command = command.env(name, self.translate(value));
+        }
+        command.unchecked().run()?;
+
+        Ok(())
+    }
+
+    fn job_stopper(&self) -> JobStopper {
+        let running = self.running.clone();
+
+        Box::new(move || match *running.lock().unwrap() {
+            Some(pid) => kill_process_tree(pid),
+            None => Ok(()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, temporary_path, TemporaryDirectory};
+
+    // A root of its own, so that tests running in parallel don't share worktrees.
+    fn executor() -> ShellExecutor {
+        ShellExecutor {
+            root: temporary_path("shell"),
+            project_directory: PathBuf::new(),
+            running: Arc::default(),
+        }
+    }
+
+    // A repository with a committed and an uncommitted change.
+    fn project() -> (TemporaryDirectory, Context) {
+        let project = TemporaryDirectory::new("shell-project");
+        let directory = &project.path;
+
+        git(directory, &["init", "--quiet"]);
+        std::fs::write(directory.join("committed.txt"), "committed").unwrap();
+        git(directory, &["add", "."]);
+        git(directory, &["commit", "--quiet", "-m", "initial"]);
+        std::fs::write(directory.join("untracked.txt"), "untracked").unwrap();
+
+        let sha = cmd!("git", "rev-parse", "HEAD")
+            .dir(directory)
+            .read()
+            .unwrap();
+        let context = Context {
+            current_directory: directory.display().to_string(),
+            git_sha: sha,
+            image_tag: String::new(),
+            run_directory: None,
+            source_mode: SourceMode::Working,
+        };
+
+        (project, context)
+    }
+
+    #[test]
+    fn runs_jobs_in_a_worktree_with_uncommitted_changes() {
+        let (project, context) = project();
+        let directory = &project.path;
+        let mut executor = executor();
+        let job = Job {
+            script: vec!["cat committed.txt untracked.txt > out.txt".into()],
+            variables: vec![("CI_PROJECT_DIR".into(), DIRECTORIES.job.into())],
+            artifacts: vec!["out.txt".into()],
+            ..Default::default()
+        };
+
+        let id = executor.start_checkout_container(&context).unwrap();
+        executor
+            .checkout_code(&id, &context, &Checkout::default())
+            .unwrap();
+        executor.run_job(&id, &job, &mut vec![]).unwrap();
+        let artifacts = executor.extract_artifacts(&id, "build", &job).unwrap();
+
+        assert!(executor.job_directory().join(".git").is_file());
+        assert_eq!(
+            std::fs::read_to_string(directory.join(".fake-ci/artifacts/build/out.txt")).unwrap(),
+            "committeduntracked"
+        );
+        assert_eq!(artifacts, vec!["out.txt"]);
+
+        drop(executor);
+    }
+
+    #[test]
+    fn copies_artifacts_whose_names_would_be_commands_to_a_shell() {
+        let (project, context) = project();
+        let directory = &project.path;
+        let mut executor = executor();
+        let name = "out \"$(touch injected)\"; touch injected;.txt";
+        let job = Job {
+            script: vec![format!(
+                "mkdir -p dist/a/b && echo nested > dist/a/b/deep.txt && echo content > '{}'",
+                name
+            )],
+            artifacts: vec![name.into(), "dist/**/deep.txt".into()],
+            ..Default::default()
+        };
+
+        let id = executor.start_checkout_container(&context).unwrap();
+        executor
+            .checkout_code(&id, &context, &Checkout::default())
+            .unwrap();
+        executor.run_job(&id, &job, &mut vec![]).unwrap();
+        let artifacts = executor
+            .extract_artifacts(&id, "build $(touch injected)", &job)
+            .unwrap();
+        executor.prune_checkout_container().unwrap();
+        let id = executor.start_checkout_container(&context).unwrap();
+        executor
+            .checkout_code(&id, &context, &Checkout::default())
+            .unwrap();
+        executor
+            .prepare_artifacts(
+                &id,
+                &HashMap::from([("build $(touch injected)".into(), vec![name.into()])]),
+            )
+            .unwrap();
+
+        assert_eq!(artifacts, vec!["deep.txt", name]);
+        assert_eq!(
+            std::fs::read_to_string(executor.job_directory().join(name)).unwrap(),
+            "content\n"
+        );
+        assert!(!directory.join("injected").exists());
+        assert!(!executor.job_directory().join("injected").exists());
+
+        drop(executor);
+    }
+
+    #[test]
+    fn fails_to_extract_artifacts_that_do_not_exist() {
+        let (_project, context) = project();
+        let mut executor = executor();
+        let job = Job {
+            artifacts: vec!["missing/*.txt".into()],
+            ..Default::default()
+        };
+
+        let id = executor.start_checkout_container(&context).unwrap();
+        executor
+            .checkout_code(&id, &context, &Checkout::default())
+            .unwrap();
+        let error = executor.extract_artifacts(&id, "build", &job).unwrap_err();
+
+        assert!(error.to_string().contains("missing/*.txt"), "{}", error);
+
+        drop(executor);
+    }
+
+    #[test]
+    fn checks_out_only_what_the_source_mode_asks_for() {
+        let (project, context) = project();
+        let directory = &project.path;
+        std::fs::write(directory.join("staged.txt"), "staged").unwrap();
+        git(directory, &["add", "staged.txt"]);
+        let files = |source_mode| {
+            let mut executor = executor();
+            let context = Context {
+                source_mode,
+                git_sha: context.git_sha.clone(),
+                current_directory: context.current_directory.clone(),
+                ..Default::default()
+            };
+            let id = executor.start_checkout_container(&context).unwrap();
+            executor
+                .checkout_code(&id, &context, &Checkout::default())
+                .unwrap();
+
+            let mut files = std::fs::read_dir(executor.job_directory())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .filter(|name| name != ".git")
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        assert_eq!(files(SourceMode::Committed), vec!["committed.txt"]);
+        assert_eq!(
+            files(SourceMode::Staged),
+            vec!["committed.txt", "staged.txt"]
+        );
+        assert_eq!(
+            files(SourceMode::Working),
+            vec!["committed.txt", "staged.txt", "untracked.txt"]
+        );
+        // Staged changes stay staged.
+        assert_eq!(
+            cmd!("git", "diff", "--cached", "--name-only")
+                .dir(directory)
+                .read()
+                .unwrap(),
+            "staged.txt"
+        );
+    }
+
+    #[test]
+    fn takes_submodules_from_the_checkouts_of_the_project() {
+        let (project, context) = project();
+        let directory = &project.path;
+        let repository = |name: &str| {
+            let repository = directory.with_extension(name);
+            let _ = std::fs::remove_dir_all(&repository);
+            std::fs::create_dir_all(&repository).unwrap();
+            git(&repository, &["init", "--quiet"]);
+            std::fs::write(repository.join(format!("{}.txt", name)), name).unwrap();
+            git(&repository, &["add", "."]);
+            git(&repository, &["commit", "--quiet", "-m", name]);
+
+            repository
+        };
+        let add_submodule = |repository: &Path, submodule: &Path, path: &str| {
+            let url = submodule.display().to_string();
+            git(
+                repository,
+                &[
+                    "-c",
+                    "protocol.file.allow=always",
+                    "submodule",
+                    "add",
+                    &url,
+                    path,
+                ],
+            );
+            git(repository, &["commit", "--quiet", "-m", path]);
+        };
+        let inner = repository("inner");
+        let library = repository("library");
+        add_submodule(&library, &inner, "inner");
+        add_submodule(directory, &library, "library");
+        git(
+            directory,
+            &[
+                "-c",
+                "protocol.file.allow=always",
+                "submodule",
+                "update",
+                "--init",
+                "--recursive",
+            ],
+        );
+        // Only the checkouts in the project are left.
+        std::fs::remove_dir_all(&inner).unwrap();
+        std::fs::remove_dir_all(&library).unwrap();
+        let context = Context {
+            git_sha: cmd!("git", "rev-parse", "HEAD")
+                .dir(directory)
+                .read()
+                .unwrap(),
+            ..context
+        };
+        let checkout = Checkout::of(&[("GIT_SUBMODULE_STRATEGY".into(), "recursive".into())]);
+        let mut executor = executor();
+
+        let id = executor.start_checkout_container(&context).unwrap();
+        executor.checkout_code(&id, &context, &checkout).unwrap();
+        let job_directory = executor.job_directory();
+
+        assert_eq!(
+            std::fs::read_to_string(job_directory.join("library/inner/inner.txt")).unwrap(),
+            "inner"
+        );
+
+        drop(executor);
+    }
+
+    #[test]
+    fn starts_without_code_without_git_strategy() {
+        let (_project, context) = project();
+        let checkout = Checkout::of(&[("GIT_STRATEGY".into(), "none".into())]);
+        let mut executor = executor();
+
+        let id = executor.start_checkout_container(&context).unwrap();
+        executor.checkout_code(&id, &context, &checkout).unwrap();
+
+        assert_eq!(
+            std::fs::read_dir(executor.job_directory()).unwrap().count(),
+            0
+        );
+
+        drop(executor);
+    }
+
+    #[test]
+    fn maps_directories_of_containers_onto_the_host() {
+        let executor = executor();
+        let root = executor.root.display().to_string();
+
+        assert_eq!(executor.translate("/job"), format!("{}/job", root));
+        assert_eq!(
+            executor.translate("/job.tmp/KEY"),
+            format!("{}/job.tmp/KEY", root)
+        );
+        assert_eq!(executor.translate("/jobs"), "/jobs");
+        assert_eq!(executor.translate("value"), "value");
+    }
+
+    #[test]
+    fn reports_failing_scripts() {
+        let mut executor = executor();
+        executor.project_directory = std::env::temp_dir();
+        let mut output = vec![];
+
+        let error = executor
+            .execute_commands(
+                "echo $SECRET; exit 4",
+                &[("SECRET".into(), "secret".into())],
+                &["secret".into()],
+                &mut output,
+            )
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "command exited with code 4");
+        assert_eq!(String::from_utf8(output).unwrap(), "[MASKED]\n");
+    }
+
+    #[test]
+    fn reports_scripts_killed_by_signals() {
+        let mut executor = executor();
+        executor.project_directory = std::env::temp_dir();
+        let mut output = vec![];
+
+        let error = executor
+            .execute_commands("echo started; kill -9 $$", &[], &[], &mut output)
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "command was killed by signal 9");
+        assert_eq!(String::from_utf8(output).unwrap(), "started\n");
+    }
+
+    #[test]
+    fn stops_jobs_without_output() {
+        let mut executor = executor();
+        executor.project_directory = std::env::temp_dir();
+        let stop_job = executor.job_stopper();
+
+        let error = std::thread::scope(|scope| {
+            let running = scope
+                .spawn(|| executor.execute_commands("sleep 600; echo done", &[], &[], &mut vec![]));
+            while !running.is_finished() {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                stop_job().unwrap();
+            }
+
+            running.join().unwrap().unwrap_err()
+        });
+
+        assert_eq!(error.to_string(), "command was killed by signal 9");
+    }
+}
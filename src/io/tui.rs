@@ -0,0 +1,638 @@
+use crate::io::prompt::{PromptResponse, Prompts};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Print, Stylize};
+use crossterm::terminal::{ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, event, execute, queue, terminal};
+use std::io::{Error, Stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const HELP: &str = "←↑↓→ select  PgUp/PgDn scroll  c cancel  r retry  s shell  q quit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Passed,
+    Failed,
+    Cancelled,
+    // Not run because an earlier job failed.
+    Skipped,
+}
+
+impl JobStatus {
+    fn icon(self) -> char {
+        match self {
+            JobStatus::Pending => '○',
+            JobStatus::Running => '●',
+            JobStatus::Passed => '✔',
+            JobStatus::Failed => '✘',
+            JobStatus::Cancelled => '⊘',
+            JobStatus::Skipped => '»',
+        }
+    }
+
+    fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Pending | JobStatus::Running)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    ScrollUp,
+    ScrollDown,
+    // Back to following the end of the log.
+    Follow,
+    Cancel,
+    Retry,
+    Shell,
+    Quit,
+}
+
+struct JobState {
+    name: String,
+    status: JobStatus,
+    log: Vec<String>,
+    // Output that doesn't end with a newline yet.
+    partial: String,
+}
+
+// Jobs of a run by stage, with their logs, and what's selected of them.
+pub struct Pipeline {
+    // Stages in the order they run in, with the indices of their jobs.
+    columns: Vec<(String, Vec<usize>)>,
+    jobs: Vec<JobState>,
+    selected: usize,
+    // Lines scrolled up from the end of the log, 0 follows the log.
+    scroll: usize,
+    pub message: Option<String>,
+}
+
+impl Pipeline {
+    // Jobs are given by name and stage, in the order they are supposed to run in. Stages that
+    // aren't known are shown after all others.
+    pub fn new(stages: &[String], jobs: &[(String, String)]) -> Self {
+        let mut columns = stages
+            .iter()
+            .map(|stage| (stage.clone(), vec![]))
+            .collect::<Vec<_>>();
+
+        for (index, (_, stage)) in jobs.iter().enumerate() {
+            match columns.iter_mut().find(|(name, _)| name == stage) {
+                Some((_, indices)) => indices.push(index),
+                None => columns.push((stage.clone(), vec![index])),
+            }
+        }
+        columns.retain(|(_, indices)| !indices.is_empty());
+
+        Pipeline {
+            selected: columns
+                .first()
+                .and_then(|(_, indices)| indices.first().copied())
+                .unwrap_or(0),
+            columns,
+            jobs: jobs
+                .iter()
+                .map(|(name, _)| JobState {
+                    name: name.clone(),
+                    status: JobStatus::Pending,
+                    log: vec![],
+                    partial: String::new(),
+                })
+                .collect(),
+            scroll: 0,
+            message: None,
+        }
+    }
+
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.job(name).map(|job| job.status)
+    }
+
+    pub fn set_status(&mut self, name: &str, status: JobStatus) {
+        if let Some(job) = self.job_mut(name) {
+            job.status = status;
+        }
+    }
+
+    // The next job to run, in the order jobs have been given.
+    pub fn next_pending(&self) -> Option<String> {
+        self.jobs
+            .iter()
+            .find(|job| job.status == JobStatus::Pending)
+            .map(|job| job.name.clone())
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| matches!(job.status, JobStatus::Pending | JobStatus::Running))
+    }
+
+    // Marks all jobs that haven't run yet, e.g. after a job failed.
+    pub fn finish_pending(&mut self, status: JobStatus) {
+        for job in &mut self.jobs {
+            if job.status == JobStatus::Pending {
+                job.status = status;
+            }
+        }
+    }
+
+    pub fn unsuccessful_count(&self) -> usize {
+        self.jobs
+            .iter()
+            .filter(|job| job.status != JobStatus::Passed)
+            .count()
+    }
+
+    // Adds output of a job, which doesn't need to be complete lines.
+    pub fn append(&mut self, name: &str, output: &str) {
+        if let Some(job) = self.job_mut(name) {
+            job.partial.push_str(&strip_escape_sequences(output));
+
+            while let Some(end) = job.partial.find('\n') {
+                let line = job.partial[..end].trim_end_matches('\r').to_string();
+                job.partial.drain(..=end);
+                job.log.push(line);
+            }
+        }
+    }
+
+    pub fn selected_job(&self) -> &str {
+        &self.jobs[self.selected].name
+    }
+
+    pub fn select(&mut self, action: Action) {
+        let Some((column, row)) = self.position_of_selected() else {
+            return;
+        };
+        let (column, row) = match action {
+            Action::Up => (column, row.saturating_sub(1)),
+            Action::Down => (column, row + 1),
+            Action::Left => (column.saturating_sub(1), row),
+            Action::Right => (column + 1, row),
+            _ => (column, row),
+        };
+
+        if let Some((_, indices)) = self.columns.get(column) {
+            let index = indices[row.min(indices.len() - 1)];
+            if index != self.selected {
+                self.selected = index;
+                self.scroll = 0;
+            }
+        }
+    }
+
+    pub fn scroll(&mut self, action: Action, lines: usize) {
+        let maximum = self.jobs[self.selected].log.len();
+
+        self.scroll = match action {
+            Action::ScrollUp => (self.scroll + lines).min(maximum),
+            Action::ScrollDown => self.scroll.saturating_sub(lines),
+            _ => 0,
+        };
+    }
+
+    // Queues the selected job again, unless it's still waiting or running.
+    pub fn retry_selected(&mut self) -> bool {
+        let job = &mut self.jobs[self.selected];
+        if !job.status.is_finished() {
+            return false;
+        }
+
+        job.status = JobStatus::Pending;
+        job.log.clear();
+        job.partial.clear();
+        self.scroll = 0;
+
+        true
+    }
+
+    // Rows needed to show the jobs of the stage with the most jobs.
+    fn rows(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|(_, indices)| indices.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn job(&self, name: &str) -> Option<&JobState> {
+        self.jobs.iter().find(|job| job.name == name)
+    }
+
+    fn job_mut(&mut self, name: &str) -> Option<&mut JobState> {
+        self.jobs.iter_mut().find(|job| job.name == name)
+    }
+
+    fn position_of_selected(&self) -> Option<(usize, usize)> {
+        self.columns
+            .iter()
+            .enumerate()
+            .find_map(|(column, (_, indices))| {
+                indices
+                    .iter()
+                    .position(|index| *index == self.selected)
+                    .map(|row| (column, row))
+            })
+    }
+}
+
+// Stages as columns with their jobs, the log of the selected job below, and help at the bottom.
+pub fn render(pipeline: &Pipeline, width: usize, height: usize) -> Vec<String> {
+    let column_width = width / pipeline.columns.len().max(1);
+    let rows = pipeline.rows();
+    let mut lines = vec![pipeline
+        .columns
+        .iter()
+        .map(|(stage, _)| fit(&format!(" {}", stage), column_width))
+        .collect::<String>()];
+
+    for row in 0..rows {
+        lines.push(
+            pipeline
+                .columns
+                .iter()
+                .map(|(_, indices)| match indices.get(row) {
+                    Some(index) => {
+                        let job = &pipeline.jobs[*index];
+                        let pointer = if *index == pipeline.selected {
+                            '›'
+                        } else {
+                            ' '
+                        };
+
+                        fit(
+                            &format!("{} {} {}", pointer, job.status.icon(), job.name),
+                            column_width,
+                        )
+                    }
+                    None => fit("", column_width),
+                })
+                .collect(),
+        );
+    }
+
+    let job = &pipeline.jobs[pipeline.selected];
+    lines.push(fit(
+        &format!("── {} {}", job.name, "─".repeat(width)),
+        width,
+    ));
+
+    let log_height = height.saturating_sub(lines.len() + 1);
+    let end = job.log.len().saturating_sub(pipeline.scroll);
+    let start = end.saturating_sub(log_height);
+    for line in &job.log[start..end] {
+        lines.push(fit(line, width));
+    }
+    while lines.len() < height.saturating_sub(1) {
+        lines.push(fit("", width));
+    }
+
+    let footer = match &pipeline.message {
+        Some(message) => format!("{}  {}", message, HELP),
+        None => HELP.to_string(),
+    };
+    lines.push(fit(&footer, width));
+    lines.truncate(height);
+
+    lines
+}
+
+pub fn action_of(key: &KeyEvent) -> Option<Action> {
+    match (key.code, key.modifiers) {
+        (KeyCode::Char('c'), KeyModifiers::CONTROL) => Some(Action::Quit),
+        (KeyCode::Up | KeyCode::Char('k'), _) => Some(Action::Up),
+        (KeyCode::Down | KeyCode::Char('j'), _) => Some(Action::Down),
+        (KeyCode::Left | KeyCode::Char('h'), _) => Some(Action::Left),
+        (KeyCode::Right | KeyCode::Char('l'), _) => Some(Action::Right),
+        (KeyCode::PageUp, _) => Some(Action::ScrollUp),
+        (KeyCode::PageDown, _) => Some(Action::ScrollDown),
+        (KeyCode::End, _) => Some(Action::Follow),
+        (KeyCode::Char('c'), _) => Some(Action::Cancel),
+        (KeyCode::Char('r'), _) => Some(Action::Retry),
+        (KeyCode::Char('s'), _) => Some(Action::Shell),
+        (KeyCode::Char('q') | KeyCode::Esc, _) => Some(Action::Quit),
+        _ => None,
+    }
+}
+
+// The terminal while the TUI is shown. It's restored when dropped.
+pub struct Screen {
+    stdout: Stdout,
+}
+
+impl Screen {
+    pub fn enter() -> Result<Self, Error> {
+        let mut screen = Screen {
+            stdout: std::io::stdout(),
+        };
+        screen.resume()?;
+
+        Ok(screen)
+    }
+
+    // Hands the terminal back, e.g. for an interactive shell.
+    pub fn suspend(&mut self) -> Result<(), Error> {
+        execute!(self.stdout, cursor::Show, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()
+    }
+
+    pub fn resume(&mut self) -> Result<(), Error> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            self.stdout,
+            EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(ClearType::All)
+        )
+    }
+
+    pub fn draw(&mut self, pipeline: &Pipeline) -> Result<(), Error> {
+        let (width, height) = terminal::size()?;
+        let lines = render(pipeline, width.into(), height.into());
+
+        // Only the rows of jobs have status icons, the log below them is shown as it is.
+        let job_rows = 1..=pipeline.rows();
+
+        for (row, line) in lines.iter().enumerate() {
+            queue!(self.stdout, cursor::MoveTo(0, row as u16))?;
+            if row == 0 {
+                queue!(self.stdout, Print(line.as_str().bold()))?;
+            } else if job_rows.contains(&row) {
+                for character in line.chars() {
+                    queue!(self.stdout, Print(colored(character)))?;
+                }
+            } else {
+                queue!(self.stdout, Print(line))?;
+            }
+        }
+
+        self.stdout.flush()
+    }
+
+    pub fn next_action(&mut self, timeout: Duration) -> Result<Option<Action>, Error> {
+        if !event::poll(timeout)? {
+            return Ok(None);
+        }
+
+        match event::read()? {
+            Event::Key(key) => Ok(action_of(&key)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
+
+// Where a job writes its output to while the TUI is shown.
+pub struct PaneWriter {
+    pub pipeline: Arc<Mutex<Pipeline>>,
+    pub job: String,
+    // Set to stop the job with its next output.
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl Write for PaneWriter {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            // Not `ErrorKind::Interrupted`, writes would be retried.
+            return Err(Error::other("job has been cancelled"));
+        }
+
+        lock(&self.pipeline).append(&self.job, &String::from_utf8_lossy(buffer));
+
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Shows the progress of a job in its log instead of the terminal.
+pub struct PanePrompt {
+    pub pipeline: Arc<Mutex<Pipeline>>,
+    pub job: String,
+}
+
+impl Prompts for PanePrompt {
+    fn question(&mut self, _question: &str) -> PromptResponse {
+        PromptResponse::No
+    }
+
+    fn info(&mut self, message: &str) {
+        lock(&self.pipeline).append(&self.job, &format!("{}\n", message));
+    }
+
+    fn select(&mut self, _question: &str, _items: &[String]) -> Option<usize> {
+        None
+    }
+
+    fn select_multiple(&mut self, _question: &str, _items: &[String]) -> Vec<usize> {
+        vec![]
+    }
+
+    fn input(&mut self, _question: &str, _initial: &str) -> Option<String> {
+        None
+    }
+}
+
+// A job that panicked while holding the lock leaves a pipeline that can still be shown.
+pub fn lock(pipeline: &Mutex<Pipeline>) -> std::sync::MutexGuard<'_, Pipeline> {
+    pipeline.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+fn colored(character: char) -> crossterm::style::StyledContent<char> {
+    match character {
+        '✔' => character.green(),
+        '✘' => character.red(),
+        '●' => character.yellow(),
+        '⊘' | '»' => character.dark_grey(),
+        _ => character.stylize(),
+    }
+}
+
+// Pads or cuts the text to exactly the given number of characters.
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = text.chars().take(width).collect::<String>();
+    let length = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(' ', width - length));
+
+    fitted
+}
+
+// Colors and other control sequences of job output would break the layout.
+fn strip_escape_sequences(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut characters = text.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        if character != '\x1b' {
+            stripped.push(character);
+            continue;
+        }
+
+        // e.g. `\e[1;36m`, ending with the first letter.
+        if characters.next_if_eq(&'[').is_some() {
+            for next in characters.by_ref() {
+                if next.is_ascii_alphabetic() || next == '~' {
+                    break;
+                }
+            }
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline() -> Pipeline {
+        let stages = ["build", "test", "deploy"].map(String::from);
+        let jobs = [
+            ("compile", "build"),
+            ("unit", "test"),
+            ("lint", "test"),
+            ("package", "custom"),
+        ]
+        .map(|(name, stage)| (name.to_string(), stage.to_string()));
+
+        Pipeline::new(&stages, &jobs)
+    }
+
+    #[test]
+    fn shows_stages_with_jobs_as_columns_and_the_selected_log() {
+        let mut pipeline = pipeline();
+        pipeline.set_status("compile", JobStatus::Passed);
+        pipeline.set_status("unit", JobStatus::Running);
+        pipeline.append("compile", "first\nsecond\nthird\n");
+
+        assert_eq!(
+            render(&pipeline, 36, 7),
+            vec![
+                " build       test        custom     ",
+                "› ✔ compile   ● unit      ○ package ",
+                "              ○ lint                ",
+                "── compile ─────────────────────────",
+                "second                              ",
+                "third                               ",
+                &HELP.chars().take(36).collect::<String>(),
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_the_selection_between_stages_and_jobs() {
+        let mut pipeline = pipeline();
+
+        pipeline.select(Action::Right);
+        assert_eq!(pipeline.selected_job(), "unit");
+        pipeline.select(Action::Down);
+        assert_eq!(pipeline.selected_job(), "lint");
+        pipeline.select(Action::Right);
+        assert_eq!(pipeline.selected_job(), "package");
+        pipeline.select(Action::Right);
+        assert_eq!(pipeline.selected_job(), "package");
+        pipeline.select(Action::Left);
+        pipeline.select(Action::Left);
+        pipeline.select(Action::Up);
+        assert_eq!(pipeline.selected_job(), "compile");
+    }
+
+    #[test]
+    fn scrolls_the_log_back_from_its_end() {
+        let mut pipeline = pipeline();
+        pipeline.append("compile", "1\n2\n3\n4\n");
+
+        pipeline.scroll(Action::ScrollUp, 2);
+        let lines = render(&pipeline, 12, 7);
+
+        assert_eq!(lines[4].trim(), "1");
+        assert_eq!(lines[5].trim(), "2");
+
+        pipeline.scroll(Action::ScrollUp, 10);
+        assert_eq!(pipeline.scroll, 4);
+        pipeline.scroll(Action::Follow, 0);
+        assert_eq!(pipeline.scroll, 0);
+    }
+
+    #[test]
+    fn keeps_partial_lines_until_they_are_complete_and_strips_colors() {
+        let mut pipeline = pipeline();
+
+        pipeline.append("compile", "\x1b[1;36m» Build\x1b[0m\r\nhalf");
+        pipeline.append("compile", " a line\n");
+
+        assert_eq!(pipeline.jobs[0].log, vec!["» Build", "half a line"]);
+    }
+
+    #[test]
+    fn runs_pending_jobs_in_order_and_retries_finished_ones() {
+        let mut pipeline = pipeline();
+        assert_eq!(pipeline.next_pending(), Some("compile".into()));
+        assert!(!pipeline.retry_selected());
+
+        pipeline.set_status("compile", JobStatus::Failed);
+        pipeline.finish_pending(JobStatus::Skipped);
+        assert_eq!(pipeline.next_pending(), None);
+        assert!(!pipeline.is_busy());
+        assert_eq!(pipeline.unsuccessful_count(), 4);
+
+        assert!(pipeline.retry_selected());
+        assert_eq!(pipeline.next_pending(), Some("compile".into()));
+        assert!(pipeline.is_busy());
+    }
+
+    #[test]
+    fn stops_jobs_with_their_next_output_once_cancelled() {
+        let pipeline = Arc::new(Mutex::new(pipeline()));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut writer = PaneWriter {
+            pipeline: pipeline.clone(),
+            job: "unit".into(),
+            cancelled: cancelled.clone(),
+        };
+
+        writer.write_all(b"output\n").unwrap();
+        cancelled.store(true, Ordering::SeqCst);
+        let error = writer.write_all(b"more\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "job has been cancelled");
+        assert_eq!(lock(&pipeline).jobs[1].log, vec!["output"]);
+    }
+
+    #[test]
+    fn maps_keys_to_actions() {
+        let key = |code, modifiers| action_of(&KeyEvent::new(code, modifiers));
+
+        assert_eq!(
+            key(KeyCode::Char('c'), KeyModifiers::NONE),
+            Some(Action::Cancel)
+        );
+        assert_eq!(
+            key(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            key(KeyCode::Char('j'), KeyModifiers::NONE),
+            Some(Action::Down)
+        );
+        assert_eq!(
+            key(KeyCode::PageUp, KeyModifiers::NONE),
+            Some(Action::ScrollUp)
+        );
+        assert_eq!(key(KeyCode::Char('x'), KeyModifiers::NONE), None);
+    }
+}
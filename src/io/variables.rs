@@ -1,23 +1,16 @@
-use duct::cmd;
-
-pub fn interpolate(
-    value: &str,
-    variables: &Vec<(String, String)>,
-) -> Result<String, std::io::Error> {
-    let variables = concatenate_variables(variables);
-    let command = format!("{} echo \"{}\"", variables, value);
-
-    cmd!("sh", "-c", command).read()
-}
-
-pub fn concatenate_variables(variables: &Vec<(String, String)>) -> String {
-    let mut lines = vec![];
-
-    for (name, value) in variables {
-        lines.push(format!("export {}=\"{}\";", name, value));
-    }
-
-    lines.join("")
+const MASK: &str = "[MASKED]";
+
+pub fn mask(text: &str, masked_values: &[String]) -> String {
+    let mut values = masked_values.iter().collect::<Vec<_>>();
+    // Longer values first, in case one contains another.
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+
+    values
+        .into_iter()
+        .filter(|value| !value.is_empty())
+        .fold(text.to_string(), |text, value| {
+            text.replace(value.as_str(), MASK)
+        })
 }
 
 #[cfg(test)]
@@ -25,19 +18,12 @@ pub mod tests {
     use super::*;
 
     #[test]
-    fn returns_same_value_when_nothing_is_interpolated() {
-        assert_eq!("the-value", interpolate("the-value", &vec![]).unwrap());
-    }
+    fn masks_values_in_output() {
+        let masked_values = vec!["secret".to_string(), "secret-token".to_string()];
 
-    #[test]
-    fn interpolates_values() {
         assert_eq!(
-            "some-interpolated-value",
-            interpolate(
-                "some-${VARIABLE}-value",
-                &vec![("VARIABLE".to_string(), "interpolated".to_string())]
-            )
-            .unwrap()
+            mask("token: secret-token, other: secret\n", &masked_values),
+            "token: [MASKED], other: [MASKED]\n"
         );
     }
 }
@@ -1,50 +1,62 @@
 mod commands;
 mod core;
+mod diagnostic;
 mod error;
 pub mod file;
 mod git;
 mod gitlab;
 mod io;
 mod settings;
+#[cfg(test)]
+mod test_support;
 
-use crate::commands::{image, print, prune, run};
-use crate::core::read_ci_definition;
+use crate::commands::run::Executor;
+use crate::commands::{history, image, lint, list, logs, print, prune, run, shell};
+use crate::core::{read_ci_definition, CiDefinition};
+use crate::diagnostic::{Diagnostic, ErrorFormat};
 use crate::error::FakeCiError;
 use crate::file::FileAccess;
-use crate::git::read_details;
+use crate::git::{read_details, GitDetails, SourceMode};
+use crate::gitlab::GitLabAccess;
+use crate::io::history::new_run_directory;
 use crate::io::processes::Processes;
-use crate::io::prompt::{Prompt, Prompts};
+use crate::io::prompt::Prompt;
+use crate::io::shell_executor::ShellExecutor;
+use crate::settings::credentials::resolve_token;
 use crate::settings::structure::Settings;
-use crate::settings::{load_settings, LoadedSettings};
-use anyhow::anyhow;
+use crate::settings::variables::{pipeline_variables, project_variables};
+use crate::settings::{load_secrets, load_settings, LoadedSettings};
 use clap::{Parser, Subcommand};
-use file::RealFileSystem;
+use file::{ProjectFiles, RealFileSystem};
 use std::env::current_dir;
+use std::path::Path;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn main() -> ExitCode {
     let arguments = Arguments::parse();
+    let error_format = arguments.error_format;
 
     match run(arguments).await {
-        Ok(_) => Ok(()),
-        Err(e) => match e {
-            FakeCiError::File(e) => Err(anyhow!("{}", e)),
-            FakeCiError::Git(e) => Err(anyhow!("Couldn't gather git details: {}", e)),
-            FakeCiError::GitLab(e) => Err(anyhow!(
-                "Ran into an issue while parsing configuration file: {}",
-                e
-            )),
-            FakeCiError::Other(e) => Err(anyhow!("Unexpected error: {}", e)),
-            FakeCiError::IO(e) => Err(anyhow!("Unexpected IO error: {}", e)),
-            FakeCiError::Command(e) => Err(anyhow!("Error running command: {}", e)),
-            FakeCiError::Settings(e) => Err(anyhow!("Error reading settings: {}", e)),
-        },
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("{}", Diagnostic::from(&e).format(error_format));
+
+            ExitCode::FAILURE
+        }
     }
 }
 
-async fn run(arguments: Arguments) -> Result<(), FakeCiError> {
-    let git_details = read_details()?;
-    let file_access = RealFileSystem;
+// Commands can fail without an error to report, e.g. `lint` after it has printed its findings.
+async fn run(arguments: Arguments) -> Result<ExitCode, FakeCiError> {
+    let error_format = arguments.error_format;
+    let (revision, source_mode) = match &arguments.command {
+        Command::Run(run) => (run.revision.clone(), run.source_mode()),
+        Command::Shell(shell) => (shell.revision.clone(), shell.source_mode()),
+        _ => (None, SourceMode::default()),
+    };
+    let git_details = read_details(revision.as_deref())?;
+    let file_access = RealFileSystem::default();
     let mut prompt = Prompt::new();
     let mut path_to_settings_file = current_dir().map_err(FakeCiError::other)?;
     let path_to_configuration_file = match arguments.configuration_file {
@@ -56,24 +68,53 @@ async fn run(arguments: Arguments) -> Result<(), FakeCiError> {
         Some(path) => path,
     };
 
+    let mut path_to_secrets_file = path_to_settings_file.clone();
+
     path_to_settings_file.push(".fake-ci.yml");
+    // Meant to be git-ignored, for variables that must not be committed.
+    path_to_secrets_file.push(".fake-ci.secrets.yml");
 
     let settings = match load_settings(path_to_settings_file, &file_access).await? {
         LoadedSettings::FromFile(s) => {
-            prompt.info("Using settings from .fake-ci.yml");
+            // On stderr, to keep it out of what `print` and `list` write, and only for people.
+            if error_format == ErrorFormat::Human {
+                eprintln!("Using settings from .fake-ci.yml");
+            }
             s
         }
         LoadedSettings::Default(s) => s,
     };
-    let gitlab_host = settings.gitlab.host.clone();
-    let context = Context {
+    let gitlab = GitLabAccess {
+        host: settings.gitlab.host.clone(),
+        authenticated: settings.gitlab.token.is_some(),
+        local_projects: settings.gitlab.projects.clone(),
+    };
+    let file_access = match settings.gitlab.token {
+        Some(_) => {
+            let gitlab_settings = settings.gitlab.clone();
+
+            RealFileSystem::with_token(&settings.gitlab.host, move || {
+                resolve_token(&gitlab_settings, &RealFileSystem::default())
+            })?
+        }
+        None => file_access,
+    };
+    let mut context = Context {
         current_directory: file_access.read_current_directory()?,
         git_sha: git_details.sha.clone(),
         image_tag: format!("fake-ci:{}", env!("CARGO_PKG_VERSION")),
+        run_directory: None,
+        source_mode,
     };
-    let mut processes = Processes::new();
+    // The CI configuration is read from what jobs get of the project.
+    let project_files = ProjectFiles::new(
+        &file_access,
+        &git_details.root,
+        source_mode.revision_of_files(&git_details.sha, revision.is_some()),
+    );
+    let mut processes = Processes::for_runtime(settings.runtime);
 
-    match arguments.command {
+    let result = match arguments.command {
         Command::Image(image) => Ok(image::command(
             &mut prompt,
             &mut processes,
@@ -82,30 +123,132 @@ async fn run(arguments: Arguments) -> Result<(), FakeCiError> {
         )?),
         Command::Prune(_) => Ok(prune::command(&mut prompt, &mut processes)?),
         Command::Run(run) => {
-            let definition = read_ci_definition(
+            let variables = pipeline_variables(&run.env_file, &run.variables, &file_access)?;
+            let definition = read_definition_with_variables(
+                path_to_configuration_file,
+                &path_to_secrets_file,
+                &settings,
+                &project_files,
+                &git_details,
+                &gitlab,
+                &variables,
+            )
+            .await?;
+            context.run_directory = Some(new_run_directory(&context.current_directory));
+
+            match run.executor {
+                Executor::Docker => Ok(run::command(
+                    &mut prompt,
+                    &mut processes,
+                    &context,
+                    &definition,
+                    &run,
+                )?),
+                Executor::Shell => Ok(run::command(
+                    &mut prompt,
+                    &mut ShellExecutor::new(),
+                    &context,
+                    &definition,
+                    &run,
+                )?),
+            }
+        }
+        Command::Shell(shell) => {
+            let variables = pipeline_variables(&shell.env_file, &shell.variables, &file_access)?;
+            let definition = read_definition_with_variables(
+                path_to_configuration_file,
+                &path_to_secrets_file,
+                &settings,
+                &project_files,
+                &git_details,
+                &gitlab,
+                &variables,
+            )
+            .await?;
+
+            match shell.executor {
+                Executor::Docker => Ok(shell::command(
+                    &mut prompt,
+                    &mut processes,
+                    &context,
+                    &definition,
+                    &shell,
+                )?),
+                Executor::Shell => Ok(shell::command(
+                    &mut prompt,
+                    &mut ShellExecutor::new(),
+                    &context,
+                    &definition,
+                    &shell,
+                )?),
+            }
+        }
+        Command::History(history) => Ok(history::command(&context.current_directory, &history)?),
+        Command::Logs(logs) => Ok(logs::command(&context.current_directory, &logs)?),
+        Command::Lint(lint) => {
+            let valid = lint::command(
                 path_to_configuration_file,
                 &file_access,
                 &git_details,
-                &gitlab_host,
+                &gitlab,
+                &lint,
+                arguments.error_format,
             )
             .await?;
 
-            Ok(run::command(
-                &mut prompt,
-                &mut processes,
-                &context,
-                &definition,
-                run.job,
-            )?)
+            return Ok(if valid {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
         }
-        Command::Print(_) => Ok(print::command(
+        Command::List(list) => Ok(list::command(
             path_to_configuration_file,
             &file_access,
             &git_details,
-            &gitlab_host,
+            &gitlab,
+            &list,
         )
         .await?),
-    }
+        Command::Print(print) => Ok(print::command(
+            path_to_configuration_file,
+            &file_access,
+            &git_details,
+            &gitlab,
+            &print,
+        )
+        .await?),
+    };
+
+    result.map(|_| ExitCode::SUCCESS)
+}
+
+// The CI definition with all variables its jobs get, from the project's settings and secrets as
+// well as the pipeline's variables.
+async fn read_definition_with_variables(
+    path_to_configuration_file: String,
+    path_to_secrets_file: &Path,
+    settings: &Settings,
+    project_files: &ProjectFiles<'_, RealFileSystem>,
+    git_details: &GitDetails,
+    gitlab: &GitLabAccess,
+    variables: &[(String, String)],
+) -> Result<CiDefinition, FakeCiError> {
+    let secrets = load_secrets(path_to_secrets_file, project_files.working_tree())?;
+    let project_variables = project_variables(settings, &secrets, git_details.ref_name())?;
+    let mut definition = read_ci_definition(
+        path_to_configuration_file,
+        project_files,
+        git_details,
+        gitlab,
+    )
+    .await?;
+
+    definition.add_project_variables(&project_variables);
+    definition.add_pipeline_variables(variables);
+    definition.expand_variables()?;
+
+    Ok(definition)
 }
 
 #[derive(Parser)]
@@ -114,6 +257,10 @@ struct Arguments {
     #[clap(short, long)]
     configuration_file: Option<String>,
 
+    /// How to report errors.
+    #[clap(long, value_enum, global = true, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -126,8 +273,18 @@ enum Command {
     Prune(prune::Prune),
     /// Run a job.
     Run(run::Run),
-    /// Print the fully parsed CI definition.
+    /// Open a shell in a job's container, prepared like for running it.
+    Shell(shell::Shell),
+    /// Print the fully parsed CI definition, or a single job of it.
     Print(print::Print),
+    /// Validate the CI definition and report all problems found.
+    Lint(lint::Lint),
+    /// List jobs by stage, or render their `needs` graph.
+    List(list::List),
+    /// List past runs and how their jobs went.
+    History(history::History),
+    /// Show the log of a job from a past run.
+    Logs(logs::Logs),
 }
 
 #[derive(Default)]
@@ -135,4 +292,8 @@ pub struct Context {
     pub current_directory: String,
     pub git_sha: String,
     pub image_tag: String,
+    // Where the record and logs of the current run are kept, if at all.
+    pub run_directory: Option<String>,
+    // What of the project's changes jobs get on top of `git_sha`.
+    pub source_mode: SourceMode,
 }
@@ -0,0 +1,228 @@
+use crate::file::FileAccess;
+use crate::settings::structure::{GitlabSettings, NetrcSettings, TokenSettings};
+use crate::settings::SettingsError;
+use duct::cmd;
+use std::io::Read;
+use url::Url;
+
+pub fn resolve_token(
+    settings: &GitlabSettings,
+    file_access: &impl FileAccess,
+) -> Result<Option<String>, SettingsError> {
+    let Some(token_settings) = &settings.token else {
+        return Ok(None);
+    };
+
+    if let Some(token) = token_from_env(token_settings) {
+        return Ok(Some(token));
+    }
+
+    if let Some(token) = token_from_command(token_settings)? {
+        return Ok(Some(token));
+    }
+
+    token_from_netrc(token_settings, &settings.host, file_access)
+}
+
+fn token_from_env(settings: &TokenSettings) -> Option<String> {
+    settings
+        .env
+        .as_ref()
+        .and_then(|name| std::env::var(name).ok())
+        .filter(|token| !token.is_empty())
+}
+
+fn token_from_command(settings: &TokenSettings) -> Result<Option<String>, SettingsError> {
+    let Some(command) = &settings.command else {
+        return Ok(None);
+    };
+
+    let output = cmd!("sh", "-c", command)
+        .stderr_null()
+        .read()
+        .map_err(|e| SettingsError::token(format!("command '{}' failed: {}", command, e)))?;
+    let token = output.trim().to_string();
+
+    Ok(Some(token).filter(|token| !token.is_empty()))
+}
+
+fn token_from_netrc(
+    settings: &TokenSettings,
+    host: &str,
+    file_access: &impl FileAccess,
+) -> Result<Option<String>, SettingsError> {
+    let path = match &settings.netrc {
+        None | Some(NetrcSettings::Enabled(false)) => return Ok(None),
+        Some(NetrcSettings::Enabled(true)) => default_netrc_path(),
+        Some(NetrcSettings::Path(path)) => Some(path.clone()),
+    };
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let machine = Url::parse(host)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .ok_or_else(|| SettingsError::token(format!("cannot determine host name of {}", host)))?;
+
+    let mut content = String::new();
+    match file_access.read_local_file(&path) {
+        Ok(mut file) => file
+            .read_to_string(&mut content)
+            .map_err(|e| SettingsError::token(format!("cannot read {}: {}", path, e)))?,
+        Err(_) => return Ok(None),
+    };
+
+    Ok(password_for_machine(&content, &machine))
+}
+
+fn default_netrc_path() -> Option<String> {
+    std::env::var("NETRC").ok().or_else(|| {
+        std::env::var("HOME")
+            .ok()
+            .map(|home| format!("{home}/.netrc"))
+    })
+}
+
+// Minimal `.netrc` parser: https://www.gnu.org/software/inetutils/manual/html_node/The-_002enetrc-file.html
+// Only the `password` of the matching `machine` (or the `default` entry) is of interest.
+fn password_for_machine(content: &str, machine: &str) -> Option<String> {
+    let mut tokens = content.split_whitespace();
+    let mut in_matching_entry = false;
+    let mut default_password = None;
+    let mut in_default_entry = false;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                in_matching_entry = tokens.next() == Some(machine);
+                in_default_entry = false;
+            }
+            "default" => {
+                in_matching_entry = false;
+                in_default_entry = true;
+            }
+            "password" => {
+                let password = tokens.next().map(String::from);
+
+                if in_matching_entry {
+                    return password;
+                }
+                if in_default_entry {
+                    default_password = password;
+                }
+            }
+            "login" | "account" => {
+                tokens.next();
+            }
+            "macdef" => {
+                // Macro definitions run until an empty line which `split_whitespace` can't see.
+                // They are rare enough in practice to simply stop looking any further.
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    default_password
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::StubFiles;
+
+    fn settings_with(token: TokenSettings) -> GitlabSettings {
+        GitlabSettings {
+            host: "https://gitlab.example.com".into(),
+            token: Some(token),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn has_no_token_when_nothing_is_configured() {
+        let settings = GitlabSettings::default();
+
+        let token = resolve_token(&settings, &StubFiles::default()).unwrap();
+
+        assert_eq!(token, None);
+    }
+
+    #[test]
+    fn reads_token_from_environment_variable() {
+        std::env::set_var("FAKE_CI_TEST_TOKEN_FROM_ENV", "env-token");
+        let settings = settings_with(TokenSettings {
+            env: Some("FAKE_CI_TEST_TOKEN_FROM_ENV".into()),
+            ..Default::default()
+        });
+
+        let token = resolve_token(&settings, &StubFiles::default()).unwrap();
+
+        assert_eq!(token, Some("env-token".into()));
+    }
+
+    #[test]
+    fn reads_token_from_command_output() {
+        let settings = settings_with(TokenSettings {
+            command: Some("echo command-token".into()),
+            ..Default::default()
+        });
+
+        let token = resolve_token(&settings, &StubFiles::default()).unwrap();
+
+        assert_eq!(token, Some("command-token".into()));
+    }
+
+    #[test]
+    fn fails_when_command_fails() {
+        let settings = settings_with(TokenSettings {
+            command: Some("exit 1".into()),
+            ..Default::default()
+        });
+
+        let result = resolve_token(&settings, &StubFiles::default());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reads_token_from_netrc_file() {
+        let files = StubFiles::with_file(
+            "/home/user/.netrc",
+            "
+              machine other.example.com login someone password other-token
+              machine gitlab.example.com
+                login me
+                password netrc-token
+            ",
+        );
+        let settings = settings_with(TokenSettings {
+            netrc: Some(NetrcSettings::Path("/home/user/.netrc".into())),
+            ..Default::default()
+        });
+
+        let token = resolve_token(&settings, &files).unwrap();
+
+        assert_eq!(token, Some("netrc-token".into()));
+    }
+
+    #[test]
+    fn falls_back_to_default_netrc_entry() {
+        let content = "
+            machine other.example.com login someone password other-token
+            default login me password default-token
+        ";
+
+        assert_eq!(
+            password_for_machine(content, "gitlab.example.com"),
+            Some("default-token".into())
+        );
+    }
+
+    #[test]
+    fn has_no_token_when_netrc_has_no_matching_entry() {
+        let content = "machine other.example.com login someone password other-token";
+
+        assert_eq!(password_for_machine(content, "gitlab.example.com"), None);
+    }
+}
@@ -1,19 +1,39 @@
+use crate::file::FileAccessError;
+use crate::settings::structure::Secrets;
 use crate::{FileAccess, Settings};
 use std::path::Path;
 use thiserror::Error;
 
+pub mod credentials;
 pub mod structure;
+pub mod variables;
 
 #[derive(Error, Debug)]
 pub enum SettingsError {
     #[error("syntax error {0}")]
     Syntax(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("cannot read GitLab token: {0}")]
+    Token(String),
+    #[error("invalid line {line} in {file}: {message}")]
+    EnvFile {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    #[error("variable {0} cannot be masked: {1}")]
+    Mask(String, String),
+    #[error(transparent)]
+    File(#[from] FileAccessError),
 }
 
 impl SettingsError {
     pub fn syntax(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
         SettingsError::Syntax(error.into())
     }
+
+    pub fn token(message: impl Into<String>) -> Self {
+        SettingsError::Token(message.into())
+    }
 }
 
 pub enum LoadedSettings {
@@ -25,10 +45,13 @@ pub async fn load_settings<P: AsRef<Path>>(
     path: P,
     file_access: &impl FileAccess,
 ) -> Result<LoadedSettings, SettingsError> {
-    let settings = match file_access.read_local_file(path) {
+    let settings = match file_access.read_local_file(&path) {
         Ok(file) => {
-            let configuration: Settings =
+            let mut configuration: Settings =
                 serde_yaml::from_reader(file).map_err(SettingsError::syntax)?;
+            if let Some(directory) = path.as_ref().parent() {
+                resolve_clones(&mut configuration, directory);
+            }
 
             LoadedSettings::FromFile(configuration)
         }
@@ -38,6 +61,27 @@ pub async fn load_settings<P: AsRef<Path>>(
     Ok(settings)
 }
 
+// Relative paths of local clones are meant relative to the settings file, not to wherever Fake CI
+// happens to run from.
+fn resolve_clones(settings: &mut Settings, directory: &Path) {
+    for path in settings.gitlab.projects.values_mut() {
+        if Path::new(path.as_str()).is_relative() {
+            *path = directory.join(path.as_str()).display().to_string();
+        }
+    }
+}
+
+// The secrets file is optional, but has to be valid if it exists.
+pub fn load_secrets<P: AsRef<Path>>(
+    path: P,
+    file_access: &impl FileAccess,
+) -> Result<Secrets, SettingsError> {
+    match file_access.read_local_file(path) {
+        Ok(file) => serde_yaml::from_reader(file).map_err(SettingsError::syntax),
+        Err(_) => Ok(Secrets::default()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -59,6 +103,27 @@ mod tests {
         assert!(matches!(settings, LoadedSettings::FromFile(..)));
     }
 
+    #[tokio::test]
+    async fn resolves_local_clones_relative_to_the_settings_file() {
+        let file_access = StubFiles::with_file(
+            "/home/project/.fake-ci.yml",
+            "gitlab:\n  host: gitlab.com\n  projects:\n    group/library: ../library\n    group/other: /clones/other\n",
+        );
+        let LoadedSettings::FromFile(settings) =
+            load_settings("/home/project/.fake-ci.yml", &file_access)
+                .await
+                .unwrap()
+        else {
+            panic!("settings have not been read from the file");
+        };
+
+        assert_eq!(
+            settings.gitlab.projects["group/library"],
+            "/home/project/../library"
+        );
+        assert_eq!(settings.gitlab.projects["group/other"], "/clones/other");
+    }
+
     #[tokio::test]
     async fn returns_error_on_syntax_errors() {
         let file_access = StubFiles::with_file("invalid.file", "invalid-yaml-content");
@@ -66,4 +131,21 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn returns_no_secrets_when_file_does_not_exist() {
+        let file_access = StubFiles::default();
+        let secrets = load_secrets("unknown.file", &file_access).unwrap();
+
+        assert!(secrets.variables.is_empty());
+    }
+
+    #[test]
+    fn returns_secrets_from_file() {
+        let file_access =
+            StubFiles::with_file("secrets.file", "variables:\n  TOKEN: secret-value\n");
+        let secrets = load_secrets("secrets.file", &file_access).unwrap();
+
+        assert_eq!(secrets.variables["TOKEN"].value, "secret-value");
+    }
 }
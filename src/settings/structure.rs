@@ -1,28 +1,252 @@
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::HashMap;
 
-#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Default)]
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug)]
 pub struct Settings {
     #[serde(default)]
     pub gitlab: GitlabSettings,
+    // Emulates the CI/CD variables of a project. They take precedence over the `variables`
+    // of the CI definition.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub variables: IndexMap<String, ProjectVariable>,
+    // Branches protected variables are available on. Supports `*` wildcards.
+    #[serde(default = "default_protected_branches")]
+    pub protected_branches: Vec<String>,
+    // Detected when not set: Docker, then Podman, then nerdctl.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<RuntimeKind>,
 }
 
-#[derive(Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeKind {
+    Docker,
+    Podman,
+    Nerdctl,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            gitlab: GitlabSettings::default(),
+            variables: IndexMap::new(),
+            protected_branches: default_protected_branches(),
+            runtime: None,
+        }
+    }
+}
+
+fn default_protected_branches() -> Vec<String> {
+    vec!["main".into(), "master".into()]
+}
+
+// Content of the git-ignored secrets file, which can hold variables that should not be committed.
+#[derive(Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct Secrets {
+    #[serde(default)]
+    pub variables: IndexMap<String, ProjectVariable>,
+}
+
+// Either just the value, or the value with the attributes GitLab allows for CI/CD variables:
+//
+//     variables:
+//       AWS_ROLE: deployer
+//       KUBECONFIG:
+//         value: ...
+//         file: true
+//       TOKEN:
+//         value: ...
+//         masked: true
+//         protected: true
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Default, Clone)]
+#[serde(try_from = "VariableDefinition")]
+pub struct ProjectVariable {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub protected: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub masked: bool,
+    // Taken literally, other variables referenced in it are not expanded.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub raw: bool,
+    // Written into a file, the variable holds the path to it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub file: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VariableDefinition {
+    Detailed {
+        value: Value,
+        #[serde(default)]
+        protected: bool,
+        #[serde(default)]
+        masked: bool,
+        #[serde(default)]
+        raw: bool,
+        #[serde(default)]
+        file: bool,
+    },
+    Value(Value),
+}
+
+impl TryFrom<VariableDefinition> for ProjectVariable {
+    type Error = String;
+
+    fn try_from(definition: VariableDefinition) -> Result<Self, Self::Error> {
+        let (value, protected, masked, raw, file) = match definition {
+            VariableDefinition::Value(Value::Mapping(_)) => {
+                return Err("variables need to have a `value`".into())
+            }
+            VariableDefinition::Value(value) => (value, false, false, false, false),
+            VariableDefinition::Detailed {
+                value,
+                protected,
+                masked,
+                raw,
+                file,
+            } => (value, protected, masked, raw, file),
+        };
+        let value = match value {
+            Value::String(s) => s,
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::Null => "".into(),
+            _ => return Err("variable values need to be strings".into()),
+        };
+
+        Ok(ProjectVariable {
+            value,
+            protected,
+            masked,
+            raw,
+            file,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
 pub struct GitlabSettings {
     pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<TokenSettings>,
+    // Maps `include:project` paths to local clones of those projects.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub projects: HashMap<String, String>,
 }
 
 impl Default for GitlabSettings {
     fn default() -> Self {
         Self {
             host: "https://gitlab.com".into(),
+            token: None,
+            projects: HashMap::new(),
         }
     }
 }
 
+// Where to look for an access token for the GitLab instance.
+// The sources are checked in order: `env`, `command`, `netrc`.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct TokenSettings {
+    // Name of an environment variable containing the token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    // Shell command printing the token, e.g. a password manager or credential helper.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    // Read the token as the password for the GitLab host from a `.netrc` file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub netrc: Option<NetrcSettings>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+#[serde(untagged)]
+pub enum NetrcSettings {
+    // `netrc: true` uses `$NETRC` or `~/.netrc`.
+    Enabled(bool),
+    Path(String),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod test_variables {
+        use super::*;
+
+        #[test]
+        fn deserialises_variables() {
+            let yaml = "
+                variables:
+                  DEPLOY_ENV: staging
+                  PORT: 8080
+            ";
+            let config = serde_yaml::from_str::<Settings>(yaml).unwrap();
+
+            assert_eq!(config.variables["DEPLOY_ENV"].value, "staging");
+            assert_eq!(config.variables["PORT"].value, "8080");
+            assert!(!config.variables["PORT"].masked);
+        }
+
+        #[test]
+        fn deserialises_attributes() {
+            let yaml = "
+                variables:
+                  TOKEN:
+                    value: secret-token
+                    protected: true
+                    masked: true
+                    raw: true
+                    file: true
+            ";
+            let config = serde_yaml::from_str::<Settings>(yaml).unwrap();
+
+            assert_eq!(
+                config.variables["TOKEN"],
+                ProjectVariable {
+                    value: "secret-token".into(),
+                    protected: true,
+                    masked: true,
+                    raw: true,
+                    file: true,
+                }
+            );
+        }
+
+        #[test]
+        fn rejects_attributes_without_value() {
+            let yaml = "
+                variables:
+                  TOKEN:
+                    masked: true
+            ";
+
+            assert!(serde_yaml::from_str::<Settings>(yaml).is_err());
+        }
+
+        #[test]
+        fn reads_the_container_runtime() {
+            let config = serde_yaml::from_str::<Settings>("runtime: podman").unwrap();
+
+            assert_eq!(config.runtime, Some(RuntimeKind::Podman));
+        }
+
+        #[test]
+        fn protects_main_and_master_by_default() {
+            let config = serde_yaml::from_str::<Settings>("").unwrap();
+
+            assert_eq!(config.protected_branches, vec!["main", "master"]);
+        }
+    }
+
     mod test_gitlab {
         use super::*;
 
@@ -44,5 +268,70 @@ mod tests {
 
             assert_eq!(config.gitlab.host, "https://gitlab.com".to_string());
         }
+
+        #[test]
+        fn deserialises_no_token_when_missing() {
+            let yaml = "
+                gitlab:
+                  host: https://example.com
+            ";
+            let config = serde_yaml::from_str::<Settings>(yaml).unwrap();
+
+            assert!(config.gitlab.token.is_none());
+        }
+
+        #[test]
+        fn deserialises_token_sources() {
+            let yaml = "
+                gitlab:
+                  host: https://example.com
+                  token:
+                    env: GITLAB_TOKEN
+                    command: pass show gitlab
+                    netrc: true
+            ";
+            let config = serde_yaml::from_str::<Settings>(yaml).unwrap();
+
+            assert_eq!(
+                config.gitlab.token,
+                Some(TokenSettings {
+                    env: Some("GITLAB_TOKEN".into()),
+                    command: Some("pass show gitlab".into()),
+                    netrc: Some(NetrcSettings::Enabled(true)),
+                })
+            );
+        }
+
+        #[test]
+        fn deserialises_local_project_directories() {
+            let yaml = "
+                gitlab:
+                  host: https://example.com
+                  projects:
+                    infra/ci-templates: ../ci-templates
+            ";
+            let config = serde_yaml::from_str::<Settings>(yaml).unwrap();
+
+            assert_eq!(
+                config.gitlab.projects,
+                HashMap::from([("infra/ci-templates".into(), "../ci-templates".into())])
+            );
+        }
+
+        #[test]
+        fn deserialises_netrc_path() {
+            let yaml = "
+                gitlab:
+                  host: https://example.com
+                  token:
+                    netrc: /path/to/.netrc
+            ";
+            let config = serde_yaml::from_str::<Settings>(yaml).unwrap();
+
+            assert_eq!(
+                config.gitlab.token.unwrap().netrc,
+                Some(NetrcSettings::Path("/path/to/.netrc".into()))
+            );
+        }
     }
 }
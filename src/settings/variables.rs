@@ -0,0 +1,385 @@
+use crate::file::{FileAccess, FileAccessError};
+use crate::settings::structure::{ProjectVariable, Secrets, Settings};
+use crate::settings::SettingsError;
+use regex::Regex;
+use std::io::Read;
+
+// GitLab refuses to save masked variables that it couldn't reliably find in job logs.
+// https://docs.gitlab.com/ee/ci/variables/#mask-a-cicd-variable
+const MINIMUM_LENGTH_OF_MASKED_VALUES: usize = 8;
+// Besides the Base64 alphabet.
+const MASKABLE_SPECIAL_CHARACTERS: &str = "+/=@:.~-_";
+
+// CI/CD variables of the project, from the settings and the secrets file, which wins.
+// Protected variables are only available on protected branches, just like on GitLab.
+pub fn project_variables(
+    settings: &Settings,
+    secrets: &Secrets,
+    branch_name: &str,
+) -> Result<Vec<(String, ProjectVariable)>, SettingsError> {
+    let mut variables = settings.variables.clone();
+    variables.extend(secrets.variables.clone());
+
+    let is_protected_branch = settings
+        .protected_branches
+        .iter()
+        .any(|pattern| matches_branch(pattern, branch_name));
+
+    for (name, variable) in &variables {
+        if variable.masked {
+            check_maskable(name, &variable.value)?;
+        }
+    }
+
+    Ok(variables
+        .into_iter()
+        .filter(|(_, variable)| !variable.protected || is_protected_branch)
+        .collect())
+}
+
+fn matches_branch(pattern: &str, branch_name: &str) -> bool {
+    let pattern = pattern
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+
+    Regex::new(&format!("^{}$", pattern))
+        .map(|regex| regex.is_match(branch_name))
+        .unwrap_or(false)
+}
+
+fn check_maskable(name: &str, value: &str) -> Result<(), SettingsError> {
+    let problem = if value.chars().count() < MINIMUM_LENGTH_OF_MASKED_VALUES {
+        Some(format!(
+            "value needs to be at least {} characters long",
+            MINIMUM_LENGTH_OF_MASKED_VALUES
+        ))
+    } else if value.contains('\n') {
+        Some("value needs to be a single line".to_string())
+    } else if !value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || MASKABLE_SPECIAL_CHARACTERS.contains(c))
+    {
+        Some(format!(
+            "value can only consist of letters, digits and any of `{}`",
+            MASKABLE_SPECIAL_CHARACTERS
+        ))
+    } else {
+        None
+    };
+
+    match problem {
+        Some(problem) => Err(SettingsError::Mask(name.into(), problem)),
+        None => Ok(()),
+    }
+}
+
+// Variables of a pipeline, with the same precedence GitLab gives to pipeline variables:
+// later ones win, and all of them win over project variables and the CI definition's ones.
+pub fn pipeline_variables(
+    env_files: &[String],
+    command_line_variables: &[(String, String)],
+    file_access: &impl FileAccess,
+) -> Result<Vec<(String, String)>, SettingsError> {
+    let mut variables = vec![];
+
+    for env_file in env_files {
+        let mut content = String::new();
+
+        file_access
+            .read_local_file(env_file)?
+            .read_to_string(&mut content)
+            .map_err(|e| FileAccessError::cannot_read(env_file, e))?;
+
+        variables.extend(parse_env_file(&content).map_err(|(line, message)| {
+            SettingsError::EnvFile {
+                file: env_file.clone(),
+                line,
+                message,
+            }
+        })?);
+    }
+
+    variables.extend(command_line_variables.iter().cloned());
+
+    Ok(variables)
+}
+
+// Reads files in the format used by `docker --env-file` and most dotenv libraries:
+//
+//     # comment
+//     export KEY=value
+//     QUOTED="with \"escapes\"\nand newlines"
+//     LITERAL='taken $as is'
+//     UNQUOTED=value # trailing comment
+//
+// Errors contain the line number and what's wrong with it.
+pub fn parse_env_file(content: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut variables = vec![];
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, value)) = line.split_once('=') else {
+            return Err((index + 1, format!("expected KEY=VALUE, got `{}`", line)));
+        };
+        let key = key.trim();
+
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err((index + 1, format!("invalid variable name `{}`", key)));
+        }
+
+        let value = parse_value(value.trim()).map_err(|message| (index + 1, message))?;
+
+        variables.push((key.to_string(), value));
+    }
+
+    Ok(variables)
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let (literal, _) = rest
+            .split_once('\'')
+            .ok_or_else(|| "missing closing `'`".to_string())?;
+
+        return Ok(literal.to_string());
+    }
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut characters = rest.chars();
+
+        loop {
+            match characters.next() {
+                Some('"') => return Ok(result),
+                Some('\\') => match characters.next() {
+                    Some('n') => result.push('\n'),
+                    Some('t') => result.push('\t'),
+                    Some(c) => result.push(c),
+                    None => break,
+                },
+                Some(c) => result.push(c),
+                None => break,
+            }
+        }
+
+        return Err("missing closing `\"`".into());
+    }
+
+    let value = match value.find(" #") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+
+    Ok(value.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::StubFiles;
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.into(), value.into())
+    }
+
+    mod test_env_files {
+        use super::*;
+
+        #[test]
+        fn parses_unquoted_values() {
+            let variables = parse_env_file("KEY=value\nOTHER = spaced value # comment\n").unwrap();
+
+            assert_eq!(
+                variables,
+                vec![pair("KEY", "value"), pair("OTHER", "spaced value")]
+            );
+        }
+
+        #[test]
+        fn skips_comments_empty_lines_and_export() {
+            let variables = parse_env_file("# comment\n\nexport KEY=value\n").unwrap();
+
+            assert_eq!(variables, vec![pair("KEY", "value")]);
+        }
+
+        #[test]
+        fn parses_quoted_values() {
+            let variables =
+                parse_env_file("DOUBLE=\"a \\\"b\\\"\\nc # d\"\nSINGLE='$literal \\n'\nEMPTY=\n")
+                    .unwrap();
+
+            assert_eq!(
+                variables,
+                vec![
+                    pair("DOUBLE", "a \"b\"\nc # d"),
+                    pair("SINGLE", "$literal \\n"),
+                    pair("EMPTY", "")
+                ]
+            );
+        }
+
+        #[test]
+        fn reports_line_of_invalid_entries() {
+            assert_eq!(
+                parse_env_file("KEY=value\nno assignment\n").unwrap_err().0,
+                2
+            );
+            assert!(parse_env_file("KEY=\"unterminated").is_err());
+            assert!(parse_env_file("IN-VALID=value").is_err());
+        }
+    }
+
+    mod test_pipeline_variables {
+        use super::*;
+
+        #[test]
+        fn command_line_wins_over_env_files() {
+            let file_access = StubFiles::with_file(".env", "KEY=env-file\nFILE=env-file\n");
+            let variables = pipeline_variables(
+                &[".env".into()],
+                &[pair("KEY", "command-line")],
+                &file_access,
+            )
+            .unwrap();
+
+            assert_eq!(
+                variables,
+                vec![
+                    pair("KEY", "env-file"),
+                    pair("FILE", "env-file"),
+                    pair("KEY", "command-line"),
+                ]
+            );
+        }
+
+        #[test]
+        fn fails_for_missing_env_files() {
+            let file_access = StubFiles::default();
+
+            assert!(pipeline_variables(&[".env".into()], &[], &file_access).is_err());
+        }
+    }
+
+    mod test_project_variables {
+        use super::*;
+        use indexmap::IndexMap;
+
+        fn variable(value: &str) -> ProjectVariable {
+            ProjectVariable {
+                value: value.into(),
+                ..Default::default()
+            }
+        }
+
+        fn settings(variables: Vec<(&str, ProjectVariable)>) -> Settings {
+            Settings {
+                variables: variables
+                    .into_iter()
+                    .map(|(name, variable)| (name.to_string(), variable))
+                    .collect(),
+                protected_branches: vec!["main".into(), "release/*".into()],
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn prefers_variables_of_the_secrets_file() {
+            let settings = settings(vec![("TOKEN", variable("from-settings"))]);
+            let secrets = Secrets {
+                variables: IndexMap::from([("TOKEN".into(), variable("from-secrets"))]),
+            };
+
+            let variables = project_variables(&settings, &secrets, "main").unwrap();
+
+            assert_eq!(variables, vec![("TOKEN".into(), variable("from-secrets"))]);
+        }
+
+        #[test]
+        fn provides_protected_variables_only_on_protected_branches() {
+            let protected = ProjectVariable {
+                protected: true,
+                ..variable("value")
+            };
+            let settings = settings(vec![("PROTECTED", protected)]);
+            let secrets = Secrets::default();
+
+            assert_eq!(
+                project_variables(&settings, &secrets, "main")
+                    .unwrap()
+                    .len(),
+                1
+            );
+            assert_eq!(
+                project_variables(&settings, &secrets, "release/1.0")
+                    .unwrap()
+                    .len(),
+                1
+            );
+            assert!(project_variables(&settings, &secrets, "feature")
+                .unwrap()
+                .is_empty());
+        }
+
+        #[test]
+        fn rejects_values_that_cannot_be_masked() {
+            let masked = |value: &str| ProjectVariable {
+                masked: true,
+                ..variable(value)
+            };
+            let secrets = Secrets::default();
+
+            assert!(
+                project_variables(&settings(vec![("A", masked("short"))]), &secrets, "main")
+                    .is_err()
+            );
+            assert!(project_variables(
+                &settings(vec![("A", masked("multiple\nlines"))]),
+                &secrets,
+                "main"
+            )
+            .is_err());
+            assert!(project_variables(
+                &settings(vec![("A", masked("long-enough"))]),
+                &secrets,
+                "main"
+            )
+            .is_ok());
+        }
+
+        #[test]
+        fn rejects_masked_values_with_characters_gitlab_cannot_mask() {
+            let masked = |value: &str| ProjectVariable {
+                masked: true,
+                ..variable(value)
+            };
+            let secrets = Secrets::default();
+
+            assert!(project_variables(
+                &settings(vec![("A", masked("with spaces"))]),
+                &secrets,
+                "main"
+            )
+            .is_err());
+            assert!(project_variables(
+                &settings(vec![("A", masked("pa$$word!"))]),
+                &secrets,
+                "main"
+            )
+            .is_err());
+            assert!(project_variables(
+                &settings(vec![("A", masked("dXNlcjpwYXNz+/=@:.~-_"))]),
+                &secrets,
+                "main"
+            )
+            .is_ok());
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// Fixtures that tests of more than one module need.
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A path in the temporary directory nothing else uses, so that tests running in parallel don't
+// share files, sockets or directories.
+pub fn temporary_path(name: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    std::env::temp_dir().join(format!(
+        "fake-ci-{}-{}-{}",
+        name,
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+// An empty directory at such a path, removed again once dropped.
+pub struct TemporaryDirectory {
+    pub path: PathBuf,
+}
+
+impl TemporaryDirectory {
+    pub fn new(name: &str) -> Self {
+        let path = temporary_path(name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        TemporaryDirectory { path }
+    }
+}
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// An executable script standing in for a program, like the CLI of a container runtime. Removed
+// again once dropped.
+pub struct Script {
+    pub path: PathBuf,
+}
+
+impl Script {
+    pub fn new(name: &str, content: &str) -> Self {
+        let path = temporary_path(name);
+        std::fs::write(&path, content).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        Script { path }
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Runs git quietly with an identity to commit as, panicking if it fails.
+pub fn git(directory: &Path, arguments: &[&str]) {
+    let mut all_arguments = vec!["-c", "user.name=Test", "-c", "user.email=test@example.com"];
+    all_arguments.extend(arguments);
+
+    duct::cmd("git", all_arguments)
+        .dir(directory)
+        .stdout_null()
+        .stderr_null()
+        .run()
+        .unwrap();
+}
+
+pub fn variables(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
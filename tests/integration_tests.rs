@@ -1,4 +1,5 @@
 use assert_cmd::Command;
+use predicates::str::contains;
 use serde_yaml::Value;
 use std::path::PathBuf;
 
@@ -23,3 +24,79 @@ fn binary_correctly_reads_and_prints_out_example_configuration() {
 
     assert!(matches!(output_as_yaml, Value::Mapping { .. }));
 }
+
+#[test]
+fn lint_reports_all_problems_of_invalid_configuration() {
+    let mut binary = Command::cargo_bin("fake-ci").unwrap();
+
+    let mut path_to_configuration = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path_to_configuration.push("tests/configurations/invalid-gitlab-ci.yml");
+    let path_to_configuration = path_to_configuration.to_str().unwrap();
+
+    binary
+        .arg("--configuration-file")
+        .arg(path_to_configuration)
+        .arg("lint")
+        .assert()
+        .failure()
+        .stdout(contains("unknown keyword `scirpt`"))
+        .stdout(contains("missing `script`"))
+        .stdout(contains("stage `test` is not defined"))
+        .stdout(contains("needs unknown job `missing`"));
+}
+
+fn assert_lint_reports_problems_as_nothing_but_json(directory: &str) {
+    let mut binary = Command::cargo_bin("fake-ci").unwrap();
+
+    let mut path_to_configuration = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path_to_configuration.push("tests/configurations/invalid-gitlab-ci.yml");
+    let path_to_configuration = path_to_configuration.to_str().unwrap();
+
+    let output = binary
+        .current_dir(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(directory))
+        .arg("--configuration-file")
+        .arg(path_to_configuration)
+        .arg("--error-format")
+        .arg("json")
+        .arg("lint")
+        .assert()
+        .failure()
+        .stderr("")
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(!output.trim().is_empty());
+    for line in output.lines() {
+        assert!(serde_json::from_str::<serde_json::Value>(line).is_ok());
+    }
+}
+
+#[test]
+fn lint_reports_problems_as_nothing_but_json() {
+    assert_lint_reports_problems_as_nothing_but_json(".");
+}
+
+#[test]
+fn lint_reports_problems_as_nothing_but_json_with_settings_file() {
+    assert_lint_reports_problems_as_nothing_but_json("tests/configurations/with-settings");
+}
+
+#[test]
+fn lint_validates_against_gitlab_schema() {
+    let mut binary = Command::cargo_bin("fake-ci").unwrap();
+
+    let mut path_to_configuration = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path_to_configuration.push("tests/configurations/invalid-gitlab-ci.yml");
+    let path_to_configuration = path_to_configuration.to_str().unwrap();
+
+    binary
+        .arg("--configuration-file")
+        .arg(path_to_configuration)
+        .arg("lint")
+        .arg("--schema")
+        .assert()
+        .failure()
+        .stdout(contains("unknown keyword(s): scirpt"));
+}
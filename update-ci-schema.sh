@@ -0,0 +1,12 @@
+#!/bin/sh
+
+# Replaces the vendored CI schema with the upstream one of the version pinned in
+# src/gitlab/schema/SOURCE, byte for byte.
+
+set -eu
+
+schema_directory="$(dirname "$0")/src/gitlab/schema"
+. "${schema_directory}/SOURCE"
+
+curl --fail --silent --show-error --location "${URL}" --output "${schema_directory}/ci.json"
+echo "Vendored GitLab CI schema ${VERSION}."